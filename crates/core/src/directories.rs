@@ -41,6 +41,17 @@ impl<'a> Directories<'a> {
         }
     }
 
+    /// Where local run state, such as the record of downloaded media, is kept between runs
+    pub fn get_default_state_dir(&self) -> PathBuf {
+        match &self.project_dirs {
+            Some(project_dirs) => project_dirs.data_local_dir().to_path_buf(),
+            None => match &self.base_dirs {
+                Some(base_dirs) => base_dirs.data_local_dir().join(self.app_name),
+                None => panic!("Unable to determine a data directory for this machine."),
+            },
+        }
+    }
+
     pub fn get_default_temp_dir(&self) -> PathBuf {
         let tmp_dir = std::env::var("TEMP_DIR").unwrap_or_default();
         Path::new(&tmp_dir).into()
@@ -0,0 +1,139 @@
+use {
+    crate::rate_limit::TokenBucket,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, sync::Arc, sync::Mutex},
+};
+
+/// Bytes per second allowed for downloads, `None` or 0 is unlimited the same as the API [`crate::http::RateLimit`]
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BandwidthLimits {
+    /// Shared by every download that is running
+    pub max_bytes_per_sec: Option<u64>,
+    /// Applied to each host separately, on top of the global limit
+    pub max_host_bytes_per_sec: Option<u64>,
+}
+
+impl BandwidthLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.global_rate().is_none() && self.host_rate().is_none()
+    }
+
+    fn global_rate(&self) -> Option<u64> {
+        self.max_bytes_per_sec.filter(|rate| *rate > 0)
+    }
+
+    fn host_rate(&self) -> Option<u64> {
+        self.max_host_bytes_per_sec.filter(|rate| *rate > 0)
+    }
+}
+
+/// Caps download traffic, shared between every worker and [`super::FileDownloader`] through an `Arc`
+#[derive(Debug, Default)]
+pub struct BandwidthLimiter {
+    limits: BandwidthLimits,
+    global: Option<TokenBucket>,
+    hosts: Mutex<HashMap<String, Arc<TokenBucket>>>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self {
+            global: limits.global_rate().map(TokenBucket::new),
+            limits,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limits(&self) -> BandwidthLimits {
+        self.limits
+    }
+
+    /// Wait until `bytes` more can be written for a download from `host`
+    pub async fn consume(&self, host: &'_ str, bytes: u64) {
+        if let Some(host_bucket) = self.host_bucket(host) {
+            host_bucket.take(bytes).await;
+        }
+        if let Some(global) = &self.global {
+            global.take(bytes).await;
+        }
+    }
+
+    fn host_bucket(&self, host: &'_ str) -> Option<Arc<TokenBucket>> {
+        let rate = self.limits.host_rate()?;
+        let mut hosts = self.hosts.lock().unwrap();
+        Some(Arc::clone(
+            hosts
+                .entry(host.to_string())
+                .or_insert_with(|| Arc::new(TokenBucket::new(rate))),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BandwidthLimiter, BandwidthLimits},
+        futures::executor::block_on,
+        std::{
+            sync::Arc,
+            time::{Duration, Instant},
+        },
+    };
+
+    #[test]
+    fn hosts_get_a_bucket_each() {
+        let limiter = BandwidthLimiter::new(BandwidthLimits {
+            max_bytes_per_sec: None,
+            max_host_bytes_per_sec: Some(1_000),
+        });
+        let cdn = limiter.host_bucket("cdn.example.test").unwrap();
+        assert!(Arc::ptr_eq(
+            &cdn,
+            &limiter.host_bucket("cdn.example.test").unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &cdn,
+            &limiter.host_bucket("other.example.test").unwrap()
+        ));
+    }
+
+    #[test]
+    fn unlimited_downloads_never_wait() {
+        let limiter = BandwidthLimiter::default();
+        assert!(limiter.limits().is_unlimited());
+        assert!(limiter.host_bucket("cdn.example.test").is_none());
+        let started = Instant::now();
+        block_on(limiter.consume("cdn.example.test", u64::MAX / 2));
+        assert!(started.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn zero_rates_are_unlimited() {
+        let limiter = BandwidthLimiter::new(BandwidthLimits {
+            max_bytes_per_sec: Some(0),
+            max_host_bytes_per_sec: Some(0),
+        });
+        assert!(limiter.limits().is_unlimited());
+        assert!(limiter.global.is_none());
+        assert!(limiter.host_bucket("cdn.example.test").is_none());
+    }
+
+    #[test]
+    fn the_global_limit_is_shared_by_every_host() {
+        let limiter = BandwidthLimiter::new(BandwidthLimits {
+            max_bytes_per_sec: Some(1_000),
+            max_host_bytes_per_sec: Some(1_000),
+        });
+        let started = Instant::now();
+        block_on(limiter.consume("a.example.test", 1_000));
+        // The second host has a full bucket of its own, only the global one is empty
+        block_on(limiter.consume("b.example.test", 200));
+        let waited = started.elapsed();
+        assert!(
+            waited >= Duration::from_millis(150) && waited < Duration::from_millis(500),
+            "{:?}",
+            waited
+        );
+    }
+}
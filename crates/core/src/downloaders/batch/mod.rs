@@ -1,17 +1,97 @@
 mod multi_threaded;
+mod priority_queue;
 mod sequential;
 
 pub use self::{multi_threaded::MultiThreadedDownloader, sequential::SequentialDownloader};
-use {crate::Result, async_trait::async_trait};
+use {
+    super::{
+        BandwidthLimiter, ContentStore, DownloadEvent, FileDownloaderConfig, ProgressEvents,
+        RetryPolicy,
+    },
+    crate::{
+        http::ProxyRoutes,
+        state::{DeadLetters, DownloadState},
+        Result,
+    },
+    async_trait::async_trait,
+    serde::{Deserialize, Serialize},
+    std::{path::Path, sync::Arc},
+};
 
 //
 #[async_trait]
 pub trait BatchDownloader: Send + Sync + std::fmt::Display {
     fn name(&self) -> String;
-    // Add a single item to the downloader queue
-    async fn add_item_to_queue(&self, item: super::Downloadable) -> Result<()>;
-    // Process a single item from the queue
-    async fn process_single_item(&self, worker_num: usize) -> Result<u64>;
-    // Loop through download queue until closed or empty
+    // Loop through the download queue until every sender of its channel is dropped and it is empty.
+    // Items are queued by sending them on the channel the downloader was created with.
     async fn process_all_items(&self) -> Result<super::DownloaderStats>;
 }
+
+/// The options every [`BatchDownloader`] takes, so either strategy can be set up by the same code
+pub trait BatchDownloaderBuilder: BatchDownloader + Sized {
+    /// Skip items already in the download state and record everything saved into it
+    fn with_state(self, state: Arc<DownloadState>) -> Self;
+    fn with_retry_policy(self, retry: RetryPolicy) -> Self;
+    /// Items that are still failing after their last retry are added to the dead letters
+    fn with_dead_letters(self, dead_letters: Arc<DeadLetters>) -> Self;
+    /// Every download shares the same limiter so the limits hold across all of them
+    fn with_bandwidth_limiter(self, limiter: Arc<BandwidthLimiter>) -> Self;
+    /// Keep one copy of each unique file, every saved path becomes a link to it
+    fn with_content_store(self, content_store: Arc<ContentStore>) -> Self;
+    /// Pick the file downloader items are saved with
+    fn with_file_downloader(self, file_downloader: FileDownloaderConfig) -> Self;
+    /// Download each item through the proxy of the gatherer it came from
+    fn with_proxies(self, proxies: ProxyRoutes) -> Self;
+    /// Send a [`DownloadEvent`] for every item processed, subscribe on `events` before processing starts
+    fn with_progress_events(self, events: Arc<ProgressEvents>) -> Self;
+}
+
+/// Which batch downloader works through the queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStrategy {
+    /// One item at a time, see [`SequentialDownloader`]
+    Sequential,
+    /// A pool of workers, see [`MultiThreadedDownloader`]
+    #[default]
+    MultiThreaded,
+}
+
+/// The `[downloader]` section of the config
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DownloaderConfig {
+    pub strategy: BatchStrategy,
+    pub file: FileDownloaderConfig,
+}
+
+/// Send an event when someone is listening, the event is only built in that case
+fn emit(events: &'_ Option<Arc<ProgressEvents>>, event: impl FnOnce() -> DownloadEvent) {
+    if let Some(events) = events {
+        events.emit(event());
+    }
+}
+
+/// Hand a freshly saved file to the content store, returns how many bytes that saved
+async fn dedupe_saved_file(
+    store: &'_ Option<Arc<ContentStore>>,
+    path: &'_ Path,
+    hash: &'_ str,
+    size: u64,
+) -> u64 {
+    let store = match store {
+        Some(store) => store,
+        None => return 0,
+    };
+    match store.store(path, hash, size).await {
+        Ok(bytes_saved) => bytes_saved,
+        Err(store_err) => {
+            log::error!(
+                "Failed to move {:?} into the content store, leaving it in place. {:?}",
+                path,
+                store_err
+            );
+            0
+        }
+    }
+}
@@ -1,6 +1,13 @@
 use {
     crate::{
-        downloaders::{BatchDownloader, Downloadable, DownloaderStats},
+        downloaders::batch::priority_queue::PriorityQueue,
+        downloaders::{
+            BandwidthLimiter, BatchDownloader, ContentStore, DownloadEvent, Downloadable,
+            DownloaderStats, FailureReason, FileDownload, FileDownloaderConfig, ProgressEvents,
+            ProgressReporter, RetryPolicy, SkipReason,
+        },
+        http::ProxyRoutes,
+        state::{DeadLetter, DeadLetters, DownloadState},
         tasks::spawn_on_thread,
     },
     async_channel::Receiver,
@@ -15,6 +22,14 @@ pub struct MultiThreadedDownloader {
     // chunk_size: Option<u32>,
     // min_size_to_chunk: Option<u64>,
     receiver: Receiver<Downloadable>,
+    state: Option<Arc<DownloadState>>,
+    retry: Arc<RetryPolicy>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    limiter: Option<Arc<BandwidthLimiter>>,
+    content_store: Option<Arc<ContentStore>>,
+    events: Option<Arc<ProgressEvents>>,
+    file_downloader: FileDownloaderConfig,
+    proxies: ProxyRoutes,
 }
 
 impl MultiThreadedDownloader {
@@ -25,10 +40,60 @@ impl MultiThreadedDownloader {
             // chunk_size: None,
             // min_size_to_chunk: None,
             receiver: rx,
+            state: None,
+            retry: Arc::new(RetryPolicy::default()),
+            dead_letters: None,
+            limiter: None,
+            content_store: None,
+            events: None,
+            file_downloader: FileDownloaderConfig::default(),
+            proxies: ProxyRoutes::default(),
         }
     }
 }
 
+impl super::BatchDownloaderBuilder for MultiThreadedDownloader {
+    fn with_state(mut self, state: Arc<DownloadState>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Arc::new(retry);
+        self
+    }
+
+    fn with_dead_letters(mut self, dead_letters: Arc<DeadLetters>) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+
+    fn with_bandwidth_limiter(mut self, limiter: Arc<BandwidthLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    fn with_content_store(mut self, content_store: Arc<ContentStore>) -> Self {
+        self.content_store = Some(content_store);
+        self
+    }
+
+    fn with_file_downloader(mut self, file_downloader: FileDownloaderConfig) -> Self {
+        self.file_downloader = file_downloader;
+        self
+    }
+
+    fn with_proxies(mut self, proxies: ProxyRoutes) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    fn with_progress_events(mut self, events: Arc<ProgressEvents>) -> Self {
+        self.events = Some(events);
+        self
+    }
+}
+
 impl Default for MultiThreadedDownloader {
     fn default() -> Self {
         let (_, rx) = async_channel::unbounded();
@@ -48,81 +113,149 @@ impl BatchDownloader for MultiThreadedDownloader {
         "multi-threaded".into()
     }
 
-    async fn add_item_to_queue(&self, _item: Downloadable) -> crate::Result<()> {
-        Ok(())
-    }
-
-    async fn process_single_item(&self, _worker_num: usize) -> crate::Result<u64> {
-        todo!()
-    }
-
     async fn process_all_items(&self) -> crate::Result<DownloaderStats> {
         let stats = Arc::new(Mutex::new(DownloaderStats::default()));
 
+        // Rank everything coming off the channel, workers always take the highest priority item waiting
+        let queue = Arc::new(PriorityQueue::new());
+        let feeder = spawn_on_thread({
+            let receiver = self.receiver.clone();
+            let queue = Arc::clone(&queue);
+            let events = self.events.clone();
+            async move {
+                while let Ok(item) = receiver.recv().await {
+                    super::emit(&events, || DownloadEvent::Queued {
+                        key: item.get_state_key(),
+                        path: item.get_file_path(),
+                        priority: item.priority,
+                    });
+                    queue.push(item);
+                }
+                queue.close();
+            }
+        });
+
         // Generate a collection of tasks that will gather sub data
         let worker_threads: Vec<Task<()>> = (1..self.worker_threads+1)
             .map(|worker_num| {
-                let worker_num = worker_num;
-                let receiver = self.receiver.clone();
+                let queue = Arc::clone(&queue);
                 let stats = Arc::clone(&stats);
+                let state = self.state.clone();
+                let retry = Arc::clone(&self.retry);
+                let dead_letters = self.dead_letters.clone();
+                let limiter = self.limiter.clone();
+                let content_store = self.content_store.clone();
+                let events = self.events.clone();
+                let file_downloader = self.file_downloader.clone();
+                let proxies = self.proxies.clone();
+                let worker = usize::from(worker_num);
 
                 spawn_on_thread(async move {
                     log::debug!("W({:2}): Waiting for items...", worker_num);
-                    loop {
-                        if receiver.is_closed() && receiver.is_empty() {
-                            break;
-                        }
+                    while let Some(item) = queue.pop().await {
+                        let file_path = item.get_file_path();
+                        let file_name = item.file_name.clone();
+                        let state_key = item.get_state_key();
                         log::debug!(
-                            "W({:2}): Senders: {}. Receivers: {}",
+                            "W({:2}): Received new item {:?}, {} still left.",
                             worker_num,
-                            receiver.sender_count(),
-                            receiver.receiver_count()
+                            file_name,
+                            queue.len()
                         );
-
-                        let recv_item = receiver.recv().await;
-                        match recv_item {
-                            Ok(item) => {
-                                let file_path = item.get_file_path();
-                                let file_path = file_path.as_path();
-                                let file_name = item.file_name.clone();
+                        if let Some(state) = &state {
+                            if state.is_saved(&state_key) {
                                 log::debug!(
-                                    "W({:2}): Received new item {:?}, {} still left.",
-                                    worker_num,
-                                    file_name,
-                                    receiver.len()
+                                    "W({:2}): {} is already saved, skipping",
+                                    worker_num, state_key
                                 );
-                                match item.save_item(None).await {
-                                    Ok(bw) => {
-                                        let mut stats = stats.lock().await;
-                                        stats.total += 1;
-                                        if bw.eq(&u64::MIN) {
-                                            stats.previously_saved += 1;
-                                        } else {
-                                            log::info!(
-                                                "W({:2}): Successfully saved item {:?} wrote {} bytes",
-                                                worker_num, file_path, bw
-                                            );
-                                            stats.success += 1;
-                                        }
-                                    }
-                                    Err(save_err) => {
+                                super::emit(&events, || DownloadEvent::Skipped {
+                                    worker,
+                                    key: state_key,
+                                    reason: SkipReason::PreviouslySaved,
+                                });
+                                let mut stats = stats.lock().await;
+                                stats.total += 1;
+                                stats.previously_saved += 1;
+                                continue;
+                            }
+                        }
+                        let mut attempt = 0;
+                        let save = || {
+                            attempt += 1;
+                            super::emit(&events, || DownloadEvent::Started {
+                                worker,
+                                key: state_key.clone(),
+                                path: file_path.clone(),
+                                attempt,
+                            });
+                            let progress = events.as_ref().map(|events| {
+                                ProgressReporter::new(Arc::clone(events), worker, state_key.clone())
+                            });
+                            item.save_item(Some(file_downloader.build(
+                                &limiter,
+                                proxies.for_gatherer(&item.gatherer),
+                                progress,
+                            )))
+                        };
+                        match retry.run(&item, save).await {
+                            Ok(FileDownload::Saved { size, hash }) => {
+                                let bytes_saved =
+                                    super::dedupe_saved_file(&content_store, &file_path, &hash, size)
+                                        .await;
+                                super::emit(&events, || DownloadEvent::Completed {
+                                    worker,
+                                    key: state_key.clone(),
+                                    size,
+                                });
+                                if let Some(state) = &state {
+                                    state.record(state_key, &file_path, size, hash);
+                                }
+                                log::info!(
+                                    "W({:2}): Successfully saved item {:?} wrote {} bytes",
+                                    worker_num, file_path, size
+                                );
+                                let mut stats = stats.lock().await;
+                                stats.total += 1;
+                                stats.success += 1;
+                                stats.add_deduplicated(bytes_saved);
+                            }
+                            Ok(FileDownload::AlreadyExists) => {
+                                super::emit(&events, || DownloadEvent::Skipped {
+                                    worker,
+                                    key: state_key.clone(),
+                                    reason: SkipReason::AlreadyExists,
+                                });
+                                if let Some(state) = &state {
+                                    if let Err(record_err) =
+                                        state.record_file(state_key, &file_path).await
+                                    {
                                         log::error!(
-                                            "W({:2}): Failed to save item {:?}. Save Error: {:?}",
-                                            worker_num, file_path, save_err
+                                            "W({:2}): Failed to record {:?} in the download state. {:?}",
+                                            worker_num, file_path, record_err
                                         );
-                                        let mut stats = stats.lock().await;
-                                        stats.total += 1;
-                                        stats.failed += 1;
                                     }
                                 }
+                                let mut stats = stats.lock().await;
+                                stats.total += 1;
+                                stats.previously_saved += 1;
                             }
-                            Err(recv_err) => {
-                                // let mut stats = stats.lock().await;
-                                // stats.failed += 1;
-                                log::debug!(
-                                    "W({:2}): Failed to receive item from download queue. {:?}",
-                                    worker_num, recv_err
+                            Err(failure) => {
+                                log::error!(
+                                    "W({:2}): Failed to save item {:?} after {} attempts ({:?}). Save Error: {}",
+                                    worker_num, file_path, failure.attempts, failure.class, failure.err
                                 );
+                                super::emit(&events, || DownloadEvent::Failed {
+                                    worker,
+                                    key: state_key,
+                                    reason: FailureReason::from(failure.err.as_ref()),
+                                    attempts: failure.attempts,
+                                });
+                                if let (Some(dead_letters), true) = (&dead_letters, failure.exhausted) {
+                                    dead_letters.push(DeadLetter::new(item, &failure));
+                                }
+                                let mut stats = stats.lock().await;
+                                stats.total += 1;
+                                stats.add_failure(file_path, &failure);
                             }
                         }
                     }
@@ -132,6 +265,19 @@ impl BatchDownloader for MultiThreadedDownloader {
             .collect();
 
         futures::future::join_all(worker_threads).await;
+        feeder.await;
+
+        if let Some(state) = &self.state {
+            if let Err(save_err) = state.save() {
+                log::error!("Failed to save the download state. {:?}", save_err);
+            }
+        }
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters.settle();
+            if let Err(save_err) = dead_letters.save() {
+                log::error!("Failed to save the dead letters. {:?}", save_err);
+            }
+        }
 
         let stats = stats.lock().await;
 
@@ -0,0 +1,89 @@
+use {
+    crate::downloaders::Downloadable,
+    async_channel::{Receiver, Sender},
+    std::{
+        cmp::Ordering,
+        collections::BinaryHeap,
+        sync::{
+            atomic::{AtomicU64, Ordering as AtomicOrdering},
+            Mutex,
+        },
+    },
+};
+
+/// Items waiting to be downloaded, ranked by [`Downloadable::priority`]
+///
+/// Every push sends a ticket on an internal channel and every pop waits for one, so a pop always finds an item
+/// and workers wake up the same way they would on a plain channel. Once [`PriorityQueue::close`] is called
+/// and the tickets run out, pop returns `None`.
+#[derive(Debug)]
+pub(crate) struct PriorityQueue {
+    heap: Mutex<BinaryHeap<Queued>>,
+    next_seq: AtomicU64,
+    tickets_tx: Sender<()>,
+    tickets_rx: Receiver<()>,
+}
+
+impl PriorityQueue {
+    pub fn new() -> Self {
+        let (tickets_tx, tickets_rx) = async_channel::unbounded();
+        Self {
+            heap: Mutex::new(BinaryHeap::new()),
+            next_seq: AtomicU64::new(0),
+            tickets_tx,
+            tickets_rx,
+        }
+    }
+
+    pub fn push(&self, item: Downloadable) {
+        let seq = self.next_seq.fetch_add(1, AtomicOrdering::Relaxed);
+        self.heap.lock().unwrap().push(Queued { item, seq });
+        // The receiver lives as long as the queue, sending can't fail
+        let _ = self.tickets_tx.try_send(());
+    }
+
+    /// Wait for the highest priority item, `None` once the queue is closed and empty
+    pub async fn pop(&self) -> Option<Downloadable> {
+        self.tickets_rx.recv().await.ok()?;
+        self.heap.lock().unwrap().pop().map(|queued| queued.item)
+    }
+
+    /// No more items will be pushed, workers drain what is left and then stop
+    pub fn close(&self) {
+        self.tickets_tx.close();
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.lock().unwrap().len()
+    }
+}
+
+#[derive(Debug)]
+struct Queued {
+    item: Downloadable,
+    seq: u64,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Highest priority first, then whatever was queued first
+        self.item
+            .priority
+            .cmp(&other.item.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
@@ -1,29 +1,222 @@
 use {
     crate::{
-        downloaders::{BatchDownloader, Downloadable, DownloaderStats},
+        downloaders::{
+            BandwidthLimiter, BatchDownloader, ContentStore, DownloadEvent, Downloadable,
+            DownloaderStats, FailureReason, FileDownload, FileDownloaderConfig, ProgressEvents,
+            ProgressReporter, RetryFailure, RetryPolicy, SkipReason,
+        },
+        http::ProxyRoutes,
+        state::{DeadLetter, DeadLetters, DownloadState},
         Result,
     },
-    async_channel::{Receiver, Sender, TrySendError},
+    async_channel::Receiver,
     async_trait::async_trait,
-    std::{fmt::Formatter, time::Duration},
+    std::{fmt::Formatter, sync::Arc},
 };
 
+/// Worker number used in logs and events, there is only ever the one
+const WORKER_NUM: usize = 1;
+
+/// Downloads one item at a time, in the order they arrive on the channel
+///
+/// Processing ends once every sender of the channel has been dropped and the queue is empty.
 #[derive(Debug, Clone)]
 pub struct SequentialDownloader {
-    // The download Queue will send to a worker pool
     receiver: Receiver<Downloadable>,
-    // Download Queue
-    sender: Sender<Downloadable>,
+    state: Option<Arc<DownloadState>>,
+    retry: RetryPolicy,
+    dead_letters: Option<Arc<DeadLetters>>,
+    limiter: Option<Arc<BandwidthLimiter>>,
+    content_store: Option<Arc<ContentStore>>,
+    events: Option<Arc<ProgressEvents>>,
+    file_downloader: FileDownloaderConfig,
+    proxies: ProxyRoutes,
 }
 
 impl SequentialDownloader {
-    pub fn new() -> Self {
-        let (sender, receiver) = async_channel::unbounded();
-        Self { receiver, sender }
+    pub fn new(rx: Receiver<Downloadable>) -> Self {
+        Self {
+            receiver: rx,
+            state: None,
+            retry: RetryPolicy::default(),
+            dead_letters: None,
+            limiter: None,
+            content_store: None,
+            events: None,
+            file_downloader: FileDownloaderConfig::default(),
+            proxies: ProxyRoutes::default(),
+        }
     }
 
-    pub fn sender(&self) -> Sender<Downloadable> {
-        self.sender.clone()
+    /// Save a single item and count the outcome, returns the bytes written
+    async fn save_received_item(
+        &self,
+        item: Downloadable,
+        stats: &'_ mut DownloaderStats,
+    ) -> std::result::Result<u64, RetryFailure> {
+        let file_path = item.get_file_path();
+        let state_key = item.get_state_key();
+        log::debug!(
+            "W({:2}): Received new item {:?}",
+            WORKER_NUM,
+            item.file_name
+        );
+        stats.total += 1;
+        super::emit(&self.events, || DownloadEvent::Queued {
+            key: state_key.clone(),
+            path: file_path.clone(),
+            priority: item.priority,
+        });
+        if let Some(state) = &self.state {
+            if state.is_saved(&state_key) {
+                log::debug!(
+                    "W({:2}): {} is already saved, skipping",
+                    WORKER_NUM,
+                    state_key
+                );
+                super::emit(&self.events, || DownloadEvent::Skipped {
+                    worker: WORKER_NUM,
+                    key: state_key,
+                    reason: SkipReason::PreviouslySaved,
+                });
+                stats.previously_saved += 1;
+                return Ok(0);
+            }
+        }
+        let mut attempt = 0;
+        let save = || {
+            attempt += 1;
+            super::emit(&self.events, || DownloadEvent::Started {
+                worker: WORKER_NUM,
+                key: state_key.clone(),
+                path: file_path.clone(),
+                attempt,
+            });
+            let progress = self.events.as_ref().map(|events| {
+                ProgressReporter::new(Arc::clone(events), WORKER_NUM, state_key.clone())
+            });
+            item.save_item(Some(self.file_downloader.build(
+                &self.limiter,
+                self.proxies.for_gatherer(&item.gatherer),
+                progress,
+            )))
+        };
+        match self.retry.run(&item, save).await {
+            Ok(FileDownload::Saved { size, hash }) => {
+                let bytes_saved =
+                    super::dedupe_saved_file(&self.content_store, &file_path, &hash, size).await;
+                super::emit(&self.events, || DownloadEvent::Completed {
+                    worker: WORKER_NUM,
+                    key: state_key.clone(),
+                    size,
+                });
+                if let Some(state) = &self.state {
+                    state.record(state_key, &file_path, size, hash);
+                }
+                log::info!(
+                    "W({:2}): Successfully saved item {:?} wrote {} bytes",
+                    WORKER_NUM,
+                    file_path,
+                    size
+                );
+                stats.success += 1;
+                stats.add_deduplicated(bytes_saved);
+                Ok(size)
+            }
+            Ok(FileDownload::AlreadyExists) => {
+                super::emit(&self.events, || DownloadEvent::Skipped {
+                    worker: WORKER_NUM,
+                    key: state_key.clone(),
+                    reason: SkipReason::AlreadyExists,
+                });
+                if let Some(state) = &self.state {
+                    if let Err(record_err) = state.record_file(state_key, &file_path).await {
+                        log::error!(
+                            "W({:2}): Failed to record {:?} in the download state. {:?}",
+                            WORKER_NUM,
+                            file_path,
+                            record_err
+                        );
+                    }
+                }
+                stats.previously_saved += 1;
+                Ok(0)
+            }
+            Err(failure) => {
+                log::error!(
+                    "W({:2}): Failed to save item {:?} after {} attempts ({:?}). Save Error: {}",
+                    WORKER_NUM,
+                    file_path,
+                    failure.attempts,
+                    failure.class,
+                    failure.err
+                );
+                super::emit(&self.events, || DownloadEvent::Failed {
+                    worker: WORKER_NUM,
+                    key: state_key,
+                    reason: FailureReason::from(failure.err.as_ref()),
+                    attempts: failure.attempts,
+                });
+                stats.add_failure(file_path, &failure);
+                if let (Some(dead_letters), true) = (&self.dead_letters, failure.exhausted) {
+                    dead_letters.push(DeadLetter::new(item, &failure));
+                }
+                Err(failure)
+            }
+        }
+    }
+
+    fn save_records(&self) -> Result<()> {
+        if let Some(state) = &self.state {
+            state.save()?;
+        }
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters.settle();
+            dead_letters.save()?;
+        }
+        Ok(())
+    }
+}
+
+impl super::BatchDownloaderBuilder for SequentialDownloader {
+    fn with_state(mut self, state: Arc<DownloadState>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn with_dead_letters(mut self, dead_letters: Arc<DeadLetters>) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+
+    fn with_bandwidth_limiter(mut self, limiter: Arc<BandwidthLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    fn with_content_store(mut self, content_store: Arc<ContentStore>) -> Self {
+        self.content_store = Some(content_store);
+        self
+    }
+
+    fn with_file_downloader(mut self, file_downloader: FileDownloaderConfig) -> Self {
+        self.file_downloader = file_downloader;
+        self
+    }
+
+    fn with_proxies(mut self, proxies: ProxyRoutes) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    fn with_progress_events(mut self, events: Arc<ProgressEvents>) -> Self {
+        self.events = Some(events);
+        self
     }
 }
 
@@ -39,58 +232,22 @@ impl BatchDownloader for SequentialDownloader {
         "sequential".into()
     }
 
-    async fn add_item_to_queue(&self, item: Downloadable) -> Result<()> {
-        let mut item = item;
-        loop {
-            match self.sender.try_send(item) {
-                Ok(_) => return Ok(()),
-                Err(try_err) => match try_err {
-                    TrySendError::Full(e) => {
-                        async_io::Timer::after(Duration::from_millis(20));
-                        item = e;
-                    }
-                    TrySendError::Closed(_) => {
-                        return Err("Download queue has been closed already".into())
-                    }
-                },
-            }
-        }
-    }
-
-    async fn process_single_item(&self, worker_num: usize) -> Result<u64> {
-        let item = self.receiver.try_recv()?;
-        let file_name = item.file_name.clone();
-        log::debug!("W({}) received a new item: {:?}", worker_num, file_name);
-        match item.save_item(None).await {
-            Ok(bytes_written) => {
-                log::info!("W({}) Successfully downloaded, {:?}", worker_num, file_name);
-                Ok(bytes_written)
-            }
-            Err(down_err) => Err(format!(
-                "W({}): Failed to download file {:?}. {:?}",
-                worker_num, file_name, down_err
-            )
-            .into()),
-        }
-    }
-
     async fn process_all_items(&self) -> Result<DownloaderStats> {
         let mut stats = DownloaderStats::default();
-        loop {
-            match self.process_single_item(0).await {
-                Ok(_) => {
-                    stats.success += 1;
-                }
-                Err(_) => {
-                    stats.failed += 1;
-                }
-            };
+        // Waits for the next item and only stops once every sender is gone and nothing is left
+        while let Ok(item) = self.receiver.recv().await {
+            // Failures are already counted in the stats
+            let _ = self.save_received_item(item, &mut stats).await;
         }
+        log::debug!("W({:2}): No more items to process", WORKER_NUM);
+        self.save_records()?;
+        Ok(stats)
     }
 }
 
 impl Default for SequentialDownloader {
     fn default() -> Self {
-        Self::new()
+        let (_, rx) = async_channel::unbounded();
+        Self::new(rx)
     }
 }
@@ -0,0 +1,216 @@
+use {
+    crate::Result,
+    futures::lock::Mutex,
+    serde::{Deserialize, Serialize},
+    std::path::{Path, PathBuf},
+};
+
+const DEFAULT_STORE_DIR: &str = ".store";
+
+/// How a logical location points at the single stored copy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMode {
+    /// Falls back to a symlink when the store is on a different filesystem
+    #[default]
+    Hardlink,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContentStoreConfig {
+    pub enabled: bool,
+    /// Where the unique copies are kept, defaults to `.store` in the download directory
+    pub path: Option<PathBuf>,
+    pub link_mode: LinkMode,
+}
+
+impl ContentStoreConfig {
+    /// Build the store if it is enabled, relative to the download directory unless a path was given
+    pub fn build(&self, download_dir: &'_ Path) -> Option<ContentStore> {
+        if !self.enabled {
+            return None;
+        }
+        let root = self
+            .path
+            .clone()
+            .unwrap_or_else(|| download_dir.join(DEFAULT_STORE_DIR));
+        Some(ContentStore::new(root, self.link_mode))
+    }
+}
+
+/// Keeps one copy of every unique file, keyed by its SHA-256, and links each downloaded path to it
+///
+/// The same media shows up from posts, messages and purchases, after a download finishes its file is moved into the store
+/// (or dropped if the store already has that hash) and the original path is replaced with a link to the stored copy.
+#[derive(Debug)]
+pub struct ContentStore {
+    root: PathBuf,
+    link_mode: LinkMode,
+    // Held while a file is moved and linked so two workers with the same content can't race each other
+    lock: Mutex<()>,
+}
+
+impl ContentStore {
+    pub fn new<P: Into<PathBuf>>(root: P, link_mode: LinkMode) -> Self {
+        let root: PathBuf = root.into();
+        // Symlinks need an absolute target to work from any folder
+        let root = std::path::absolute(&root).unwrap_or(root);
+        Self {
+            root,
+            link_mode,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Where the copy for a hash lives, split on the first two characters to keep folders small
+    pub fn path_for(&self, hash: &'_ str) -> PathBuf {
+        let prefix = hash.get(..2).unwrap_or(hash);
+        self.root.join(prefix).join(hash)
+    }
+
+    /// Move a freshly downloaded file into the store and link its path back to the stored copy
+    ///
+    /// Returns the number of bytes saved, the size of the file when the store already had a copy, otherwise 0.
+    pub async fn store(&self, path: &'_ Path, hash: &'_ str, size: u64) -> Result<u64> {
+        let _guard = self.lock.lock().await;
+        let stored_path = self.path_for(hash);
+        let bytes_saved = if stored_path.exists() {
+            log::debug!("{:?} is a duplicate of {:?}", path, stored_path);
+            async_fs::remove_file(path).await?;
+            size
+        } else {
+            if let Some(parent) = stored_path.parent() {
+                async_fs::create_dir_all(parent).await?;
+            }
+            if let Err(rename_err) = async_fs::rename(path, &stored_path).await {
+                // Most likely a different filesystem, copy it over instead
+                log::debug!(
+                    "Failed to move {:?} into the store, copying instead. {:?}",
+                    path,
+                    rename_err
+                );
+                async_fs::copy(path, &stored_path).await?;
+                async_fs::remove_file(path).await?;
+            }
+            0
+        };
+        self.link(&stored_path, path).await?;
+        Ok(bytes_saved)
+    }
+
+    async fn link(&self, stored_path: &'_ Path, path: &'_ Path) -> Result<()> {
+        if self.link_mode == LinkMode::Hardlink {
+            match async_fs::hard_link(stored_path, path).await {
+                Ok(_) => return Ok(()),
+                Err(link_err) => log::debug!(
+                    "Failed to hardlink {:?}, trying a symlink. {:?}",
+                    path,
+                    link_err
+                ),
+            }
+        }
+        if let Err(link_err) = symlink(stored_path, path).await {
+            // Never leave the logical path empty, a plain copy is better than nothing
+            log::warn!(
+                "Failed to link {:?} to the store, keeping a copy. {:?}",
+                path,
+                link_err
+            );
+            async_fs::copy(stored_path, path).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+async fn symlink(original: &'_ Path, link: &'_ Path) -> std::io::Result<()> {
+    async_fs::unix::symlink(original, link).await
+}
+
+#[cfg(windows)]
+async fn symlink(original: &'_ Path, link: &'_ Path) -> std::io::Result<()> {
+    async_fs::windows::symlink_file(original, link).await
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ContentStore, ContentStoreConfig, LinkMode},
+        futures::executor::block_on,
+        std::path::{Path, PathBuf},
+    };
+
+    const HASH: &str = "ab12cd34";
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gatherers-store-{}-{}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn download(dir: &'_ Path, name: &'_ str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, b"same bytes").unwrap();
+        path
+    }
+
+    #[test]
+    fn stored_copies_are_split_on_the_hash_prefix() {
+        let store = ContentStore::new("store", LinkMode::Hardlink);
+        // Relative roots are made absolute
+        assert!(store.path_for(HASH).is_absolute());
+        assert!(store.path_for(HASH).ends_with("store/ab/ab12cd34"));
+        assert!(store.path_for("a").ends_with("store/a/a"));
+    }
+
+    #[test]
+    fn the_store_is_only_built_when_enabled() {
+        let download_dir = Path::new("/downloads");
+        assert!(ContentStoreConfig::default().build(download_dir).is_none());
+        let store = ContentStoreConfig {
+            enabled: true,
+            ..Default::default()
+        }
+        .build(download_dir)
+        .unwrap();
+        assert!(store
+            .path_for(HASH)
+            .ends_with("downloads/.store/ab/ab12cd34"));
+    }
+
+    fn duplicates_share_one_copy(link_mode: LinkMode) {
+        let dir = temp_dir();
+        let store = ContentStore::new(dir.join("store"), link_mode);
+        let first = download(&dir, "post.jpg");
+        let second = download(&dir, "message.jpg");
+
+        assert_eq!(block_on(store.store(&first, HASH, 10)).unwrap(), 0);
+        assert_eq!(block_on(store.store(&second, HASH, 10)).unwrap(), 10);
+
+        assert!(store.path_for(HASH).is_file());
+        for path in [&first, &second] {
+            assert_eq!(std::fs::read(path).unwrap(), b"same bytes");
+        }
+        if link_mode == LinkMode::Symlink {
+            assert_eq!(std::fs::read_link(&second).unwrap(), store.path_for(HASH));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hardlinked_duplicates_share_one_copy() {
+        duplicates_share_one_copy(LinkMode::Hardlink);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_duplicates_share_one_copy() {
+        duplicates_share_one_copy(LinkMode::Symlink);
+    }
+}
@@ -1,17 +1,25 @@
 use {
-    super::InMemoryFileDownloader,
-    crate::{gatherers::Media, Result},
+    super::{FileDownload, InMemoryFileDownloader},
+    crate::{gatherers::Media, state::DownloadKey, Result},
+    serde::{Deserialize, Serialize},
     std::{fmt::Display, path::PathBuf},
 };
 
 // const DEFAULT_BUFFER_SIZE: u32 = 1024; // ~1 mb
 // const DEFAULT_MIN_SIZE_TO_CHUNK: u64 = (100 * 1024) * 1024; // roughly 100 mb
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Downloadable {
     pub public_url: String,
     pub file_name: String,
     pub base_path: PathBuf,
+    // Identifies the item in the download state
+    pub gatherer: String,
+    pub user_name: String,
+    pub media_id: String,
+    /// Higher is downloaded sooner, see [`super::PriorityRules`]
+    #[serde(default)]
+    pub priority: i32,
 }
 
 impl Display for Downloadable {
@@ -23,11 +31,11 @@ impl Display for Downloadable {
 
 impl Downloadable {
     pub async fn save_item(
-        self,
+        &self,
         file_downloader: Option<Box<dyn super::FileDownloader>>,
-    ) -> Result<u64> {
+    ) -> Result<FileDownload> {
         let file_downloader =
-            file_downloader.unwrap_or_else(|| Box::new(InMemoryFileDownloader {}));
+            file_downloader.unwrap_or_else(|| Box::new(InMemoryFileDownloader::default()));
 
         file_downloader
             .download(&self.public_url, self.get_file_path())
@@ -38,7 +46,24 @@ impl Downloadable {
         self.base_path.join(&self.file_name)
     }
 
-    pub fn from_media_with_path(media: &'_ Media, path: PathBuf) -> Self {
+    pub fn get_state_key(&self) -> DownloadKey {
+        DownloadKey::from(self)
+    }
+
+    /// Build from the full output path of the media, usually rendered from a [`crate::gatherers::PathTemplates`]
+    pub fn from_media_at(gatherer: &'_ str, media: &'_ Media, file_path: PathBuf) -> Self {
+        let mut item = Self::from_media_with_path(
+            gatherer,
+            media,
+            file_path.parent().map(PathBuf::from).unwrap_or_default(),
+        );
+        if let Some(file_name) = file_path.file_name() {
+            item.file_name = file_name.to_string_lossy().to_string();
+        }
+        item
+    }
+
+    pub fn from_media_with_path(gatherer: &'_ str, media: &'_ Media, path: PathBuf) -> Self {
         log::debug!(
             "Creating downloadable for {} in {:?}",
             media.file_name,
@@ -48,6 +73,15 @@ impl Downloadable {
             file_name: media.file_name.to_string(),
             base_path: path,
             public_url: media.url.to_string(),
+            gatherer: gatherer.to_string(),
+            user_name: media.user_name.to_string(),
+            priority: 0,
+            // Not every source gives us an id, the file name is the next most stable value
+            media_id: if media.id.is_empty() {
+                media.file_name.to_string()
+            } else {
+                media.id.to_string()
+            },
         }
     }
 }
@@ -0,0 +1,23 @@
+use {std::path::PathBuf, thiserror::Error};
+
+#[derive(Debug, Error)]
+pub enum DownloadErrors {
+    #[error("Expected {expected} bytes for {path:?} but received {received}")]
+    SizeMismatch {
+        path: PathBuf,
+        expected: u64,
+        received: u64,
+    },
+    #[error("Request to {url} returned status {status}")]
+    BadStatus {
+        url: String,
+        status: surf::StatusCode,
+    },
+    #[error("Request to {url} failed. {err}")]
+    RequestFailed { url: String, err: surf::Error },
+    #[error("Failed to write {path:?}. {source}")]
+    WriteFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
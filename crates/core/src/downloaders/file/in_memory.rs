@@ -1,41 +1,177 @@
-use async_trait::async_trait;
+use {
+    super::{
+        copy_and_hash, finish_part_file, part_file_path, url_host, FileDownload,
+        IN_MEMORY_PART_SUFFIX,
+    },
+    crate::{
+        downloaders::{BandwidthLimiter, DownloadErrors, ProgressReporter},
+        http::{proxy, ProxyConfig},
+    },
+    async_trait::async_trait,
+    sha2::{Digest, Sha256},
+    std::sync::Arc,
+};
 
-pub struct InMemoryFileDownloader;
+#[derive(Default)]
+pub struct InMemoryFileDownloader {
+    limiter: Option<Arc<BandwidthLimiter>>,
+    progress: Option<Arc<ProgressReporter>>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl super::FileDownloaderBuilder for InMemoryFileDownloader {
+    fn with_bandwidth_limiter(mut self, limiter: Arc<BandwidthLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    fn with_progress(mut self, progress: Arc<ProgressReporter>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
 
 #[async_trait]
 impl super::FileDownloader for InMemoryFileDownloader {
-    async fn download(&self, url: &'_ str, output_path: std::path::PathBuf) -> crate::Result<u64> {
-        let mut output_file = if output_path.exists() {
-            log::debug!("opening file: {:?}", output_path);
-            async_fs::File::open(output_path).await
-        } else {
-            log::debug!("creating file: {:?}", output_path);
-            async_fs::create_dir_all(output_path.parent().unwrap()).await?;
-            async_fs::File::create(output_path).await
-        }?;
-        let client = surf::Client::default();
-        let req = client.get(&url).build();
-        match client.send(req).await {
-            Ok(mut resp) => {
-                log::debug!("Download response for {} {:?}", url, resp);
-                match futures::io::copy(&mut resp, &mut output_file).await {
-                    Ok(bytes_written) => Ok(bytes_written),
-                    Err(copy_err) => {
-                        let copy_err: std::io::ErrorKind = copy_err.kind();
-                        match copy_err {
-                            std::io::ErrorKind::WriteZero | std::io::ErrorKind::AlreadyExists => {
-                                Ok(0)
-                            }
-                            _ => Err(format!(
-                                "Failed to copy bytes to file {:?}. {:?}",
-                                output_file, copy_err
-                            )
-                            .into()),
-                        }
-                    }
+    async fn download(
+        &self,
+        url: &'_ str,
+        output_path: std::path::PathBuf,
+    ) -> crate::Result<FileDownload> {
+        if output_path.exists() {
+            log::debug!("in-mem: {:?} already exists, skipping", output_path);
+            return Ok(FileDownload::AlreadyExists);
+        }
+        let part_path = part_file_path(&output_path, IN_MEMORY_PART_SUFFIX);
+        let write_err = |source| DownloadErrors::WriteFailed {
+            path: part_path.clone(),
+            source,
+        };
+
+        let client = proxy::surf_client(self.proxy.as_ref());
+        let req = client.get(url).build();
+        let resp = client
+            .send(req)
+            .await
+            .map_err(|req_err| DownloadErrors::RequestFailed {
+                url: url.to_string(),
+                err: req_err,
+            })?;
+        log::debug!("Download response for {} {:?}", url, resp);
+        if !resp.status().is_success() {
+            return Err(Box::new(DownloadErrors::BadStatus {
+                url: url.to_string(),
+                status: resp.status(),
+            }));
+        }
+        // Nothing is created until the status is known to be good, a bad one leaves no file behind
+        log::debug!("creating file: {:?}", part_path);
+        if let Some(parent) = output_path.parent() {
+            async_fs::create_dir_all(parent).await.map_err(write_err)?;
+        }
+        let mut part_file = async_fs::File::create(&part_path)
+            .await
+            .map_err(write_err)?;
+        let expected_size = super::content_length(&resp);
+        if let Some(progress) = &self.progress {
+            progress.set_total(expected_size);
+        }
+        let mut hasher = Sha256::new();
+        let host = url_host(url);
+        let throttle = self.limiter.as_deref().map(|limiter| (limiter, &host[..]));
+        if let Err(copy_err) = copy_and_hash(
+            resp,
+            &mut part_file,
+            &mut hasher,
+            throttle,
+            self.progress.as_deref(),
+        )
+        .await
+        {
+            // Can't be resumed, the next attempt starts over anyway
+            drop(part_file);
+            let _ = async_fs::remove_file(&part_path).await;
+            return Err(Box::new(write_err(copy_err)));
+        }
+        finish_part_file(part_file, &part_path, &output_path, expected_size, hasher).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::InMemoryFileDownloader,
+        crate::downloaders::{FileDownload, FileDownloader},
+        futures::executor::block_on,
+        std::{
+            io::{Read, Write},
+            net::TcpListener,
+            path::PathBuf,
+            thread,
+        },
+    };
+
+    /// Answers a single request with `status` and `body`
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
                 }
+                request.extend_from_slice(&buf[..read]);
             }
-            Err(req_err) => Err(format!("in-mem: Request to {} failed. {}", &url, req_err).into()),
-        }
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}/file.bin", addr)
+    }
+
+    fn output_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gatherers-in-memory-{}-{}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_streamed_part_file_is_left_alone() {
+        let dir = output_dir();
+        let output_path = dir.join("video.mp4");
+        let streamed_part = dir.join("video.mp4.part");
+        std::fs::write(&streamed_part, b"resumable").unwrap();
+        let downloader = InMemoryFileDownloader::default();
+
+        let url = serve_once("404 Not Found", "");
+        assert!(block_on(downloader.download(&url, output_path.clone())).is_err());
+        // A bad status creates nothing
+        assert!(!dir.join("video.mp4.tmp").exists());
+
+        let url = serve_once("200 OK", "whole file");
+        assert!(matches!(
+            block_on(downloader.download(&url, output_path.clone())).unwrap(),
+            FileDownload::Saved { size: 10, .. }
+        ));
+        assert_eq!(std::fs::read(&output_path).unwrap(), b"whole file");
+        assert_eq!(std::fs::read(&streamed_part).unwrap(), b"resumable");
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,10 +1,228 @@
 mod in_memory;
 mod streaming;
+mod threshold;
 
-pub use self::{in_memory::InMemoryFileDownloader, streaming::StreamingFileDownloader};
-use {async_trait::async_trait, std::path::PathBuf};
+pub use self::{
+    in_memory::InMemoryFileDownloader, streaming::StreamingFileDownloader,
+    threshold::ThresholdFileDownloader,
+};
+use {
+    super::{BandwidthLimiter, DownloadErrors, ProgressReporter},
+    crate::http::ProxyConfig,
+    async_trait::async_trait,
+    futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
 
 #[async_trait]
 pub trait FileDownloader: Send + Sync {
-    async fn download(&self, url: &'_ str, output_path: PathBuf) -> crate::Result<u64>;
+    async fn download(&self, url: &'_ str, output_path: PathBuf) -> crate::Result<FileDownload>;
+}
+
+/// The options every single-request [`FileDownloader`] takes, [`FileDownloaderConfig::build`] sets them the same way
+/// whichever kind it builds
+pub trait FileDownloaderBuilder: FileDownloader + Sized {
+    /// Wait on the limiter before writing each chunk, share it to hold the limits across downloads
+    fn with_bandwidth_limiter(self, limiter: Arc<BandwidthLimiter>) -> Self;
+    /// Report the size and every chunk written
+    fn with_progress(self, progress: Arc<ProgressReporter>) -> Self;
+    /// Send the requests through this proxy instead of connecting directly
+    fn with_proxy(self, proxy: ProxyConfig) -> Self;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileDownloaderKind {
+    /// One request per file, see [`InMemoryFileDownloader`]
+    #[default]
+    InMemory,
+    /// `Range` requests that resume from a `.part` file, see [`StreamingFileDownloader`]
+    Streaming,
+}
+
+/// Which [`FileDownloader`] batch downloaders use for each item
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileDownloaderConfig {
+    pub kind: FileDownloaderKind,
+    /// With `in_memory`, files at least this many bytes are streamed instead. The size is checked with a `HEAD` request.
+    pub streaming_threshold: Option<u64>,
+    /// Bytes asked for in each `Range` request when streaming
+    pub chunk_size: u32,
+}
+
+impl Default for FileDownloaderConfig {
+    fn default() -> Self {
+        Self {
+            kind: FileDownloaderKind::default(),
+            streaming_threshold: None,
+            chunk_size: streaming::DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+impl FileDownloaderConfig {
+    /// Build the downloader for a single item, sharing the limiter, going through `proxy` and reporting to `progress`
+    pub fn build(
+        &self,
+        limiter: &'_ Option<Arc<BandwidthLimiter>>,
+        proxy: Option<&'_ ProxyConfig>,
+        progress: Option<ProgressReporter>,
+    ) -> Box<dyn FileDownloader> {
+        let progress = progress.map(Arc::new);
+        let in_memory =
+            || Self::configure(InMemoryFileDownloader::default(), limiter, &progress, proxy);
+        let streaming = || {
+            Self::configure(
+                StreamingFileDownloader::new(self.chunk_size),
+                limiter,
+                &progress,
+                proxy,
+            )
+        };
+        match (self.kind, self.streaming_threshold) {
+            (FileDownloaderKind::InMemory, Some(threshold)) => {
+                let mut file_downloader = ThresholdFileDownloader::new(
+                    threshold,
+                    Box::new(in_memory()),
+                    Box::new(streaming()),
+                );
+                if let Some(proxy) = proxy {
+                    file_downloader = file_downloader.with_proxy(proxy.clone());
+                }
+                Box::new(file_downloader)
+            }
+            (FileDownloaderKind::InMemory, None) => Box::new(in_memory()),
+            (FileDownloaderKind::Streaming, _) => Box::new(streaming()),
+        }
+    }
+
+    fn configure<D: FileDownloaderBuilder>(
+        file_downloader: D,
+        limiter: &'_ Option<Arc<BandwidthLimiter>>,
+        progress: &'_ Option<Arc<ProgressReporter>>,
+        proxy: Option<&'_ ProxyConfig>,
+    ) -> D {
+        let mut file_downloader = file_downloader;
+        if let Some(limiter) = limiter {
+            file_downloader = file_downloader.with_bandwidth_limiter(Arc::clone(limiter));
+        }
+        if let Some(progress) = progress {
+            file_downloader = file_downloader.with_progress(Arc::clone(progress));
+        }
+        if let Some(proxy) = proxy {
+            file_downloader = file_downloader.with_proxy(proxy.clone());
+        }
+        file_downloader
+    }
+}
+
+/// Outcome of a successful [`FileDownloader::download`]
+#[derive(Debug, Clone)]
+pub enum FileDownload {
+    /// The file was written, matched the expected size and was moved into place
+    Saved { size: u64, hash: String },
+    /// A file is already at the output path so nothing was requested
+    AlreadyExists,
+}
+
+/// Streamed downloads resume from what is already in this file
+pub(crate) const STREAMING_PART_SUFFIX: &str = ".part";
+/// In-memory downloads start over each time, their own suffix keeps them from emptying a streamed `.part` file
+pub(crate) const IN_MEMORY_PART_SUFFIX: &str = ".tmp";
+
+/// The sibling path a download is written to until it is complete, `video.mp4` -> `video.mp4.part`
+pub(crate) fn part_file_path(output_path: &'_ Path, suffix: &'_ str) -> PathBuf {
+    let mut part_path = output_path.as_os_str().to_owned();
+    part_path.push(suffix);
+    PathBuf::from(part_path)
+}
+
+/// Copy everything from the reader into the writer, feeding the bytes to the hasher on the way
+///
+/// With a limiter each chunk waits for the bandwidth of the host it came from before it is written.
+/// Every chunk written is added to the progress reporter.
+pub(crate) async fn copy_and_hash<R, W>(
+    reader: R,
+    writer: &'_ mut W,
+    hasher: &'_ mut Sha256,
+    throttle: Option<(&'_ BandwidthLimiter, &'_ str)>,
+    progress: Option<&'_ ProgressReporter>,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = reader;
+    let mut buf = vec![0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        if let Some((limiter, host)) = throttle {
+            limiter.consume(host, read as u64).await;
+        }
+        hasher.update(&buf[..read]);
+        writer.write_all(&buf[..read]).await?;
+        copied += read as u64;
+        if let Some(progress) = progress {
+            progress.advance(read as u64);
+        }
+    }
+    Ok(copied)
+}
+
+/// Check the part file against the expected size then atomically rename it to the output path
+///
+/// The part file is left where it is on a mismatch so the caller can decide whether to resume or restart.
+pub(crate) async fn finish_part_file(
+    part_file: async_fs::File,
+    part_path: &'_ Path,
+    output_path: &'_ Path,
+    expected_size: Option<u64>,
+    hasher: Sha256,
+) -> crate::Result<FileDownload> {
+    let write_err = |source| DownloadErrors::WriteFailed {
+        path: part_path.to_path_buf(),
+        source,
+    };
+    part_file.sync_all().await.map_err(write_err)?;
+    let size = part_file.metadata().await.map_err(write_err)?.len();
+    drop(part_file);
+    if let Some(expected) = expected_size {
+        if size != expected {
+            return Err(Box::new(DownloadErrors::SizeMismatch {
+                path: output_path.to_path_buf(),
+                expected,
+                received: size,
+            }));
+        }
+    }
+    async_fs::rename(part_path, output_path)
+        .await
+        .map_err(write_err)?;
+    let hash = format!("{:x}", hasher.finalize());
+    log::debug!("Saved {} bytes to {:?} [{}]", size, output_path, hash);
+    Ok(FileDownload::Saved { size, hash })
+}
+
+/// Host the url points to, bandwidth limits per host are keyed on this
+pub(crate) fn url_host(url: &'_ str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_default()
+}
+
+/// Read the `Content-Length` of a response, if the server sent one
+pub(crate) fn content_length(resp: &'_ surf::Response) -> Option<u64> {
+    resp.header(surf::http::headers::CONTENT_LENGTH)
+        .and_then(|length| length.as_str().parse().ok())
 }
@@ -1,12 +1,300 @@
-use {crate::Result, async_trait::async_trait, surf::http::headers::HeaderValue};
+use {
+    super::{
+        copy_and_hash, finish_part_file, part_file_path, url_host, FileDownload,
+        STREAMING_PART_SUFFIX,
+    },
+    crate::{
+        downloaders::{BandwidthLimiter, DownloadErrors, ProgressReporter},
+        http::{proxy, ProxyConfig},
+        Result,
+    },
+    async_trait::async_trait,
+    futures::AsyncWriteExt,
+    sha2::{Digest, Sha256},
+    std::{path::PathBuf, sync::Arc},
+    surf::{
+        http::headers::{HeaderValue, ACCEPT_RANGES, ETAG, IF_RANGE, LAST_MODIFIED},
+        StatusCode,
+    },
+};
 
-pub struct StreamingFileDownloader;
+/// Next to the `.part` file, holds the `ETag` or `Last-Modified` of the remote file the saved bytes came from
+const VALIDATOR_SUFFIX: &str = ".validator";
+
+pub(super) const DEFAULT_CHUNK_SIZE: u32 = 8 * 1024 * 1024; // 8 MiB per range request
+
+/// Downloads a file in `Range` sized chunks, appending each one to a `.part` file as it arrives.
+///
+/// If a `.part` file already exists for the output path the download resumes from its current size,
+/// so an interrupted run only needs to fetch the remaining bytes. Every range is sent with `If-Range`, a remote file
+/// that changed since the `.part` file was started is downloaded again from the start rather than joined onto it.
+/// Servers that don't advertise `Accept-Ranges: bytes` are downloaded with a single GET instead.
+pub struct StreamingFileDownloader {
+    chunk_size: u32,
+    limiter: Option<Arc<BandwidthLimiter>>,
+    progress: Option<Arc<ProgressReporter>>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl StreamingFileDownloader {
+    pub fn new(chunk_size: u32) -> Self {
+        Self {
+            chunk_size,
+            limiter: None,
+            progress: None,
+            proxy: None,
+        }
+    }
+}
+
+impl super::FileDownloaderBuilder for StreamingFileDownloader {
+    fn with_bandwidth_limiter(mut self, limiter: Arc<BandwidthLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    fn with_progress(mut self, progress: Arc<ProgressReporter>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
+
+impl Default for StreamingFileDownloader {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+}
 
 #[async_trait]
 impl super::FileDownloader for StreamingFileDownloader {
-    async fn download(&self, _url: &'_ str, _output_path: std::path::PathBuf) -> Result<u64> {
-        todo!()
+    async fn download(&self, url: &'_ str, output_path: PathBuf) -> Result<FileDownload> {
+        if output_path.exists() {
+            log::debug!("streaming: {:?} already exists, skipping", output_path);
+            return Ok(FileDownload::AlreadyExists);
+        }
+        let part_path = part_file_path(&output_path, STREAMING_PART_SUFFIX);
+        let write_err = |source| DownloadErrors::WriteFailed {
+            path: part_path.clone(),
+            source,
+        };
+        if let Some(parent) = output_path.parent() {
+            async_fs::create_dir_all(parent).await.map_err(write_err)?;
+        }
+        let mut part_file = async_fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&part_path)
+            .await
+            .map_err(write_err)?;
+        let mut hasher = Sha256::new();
+        let host = url_host(url);
+        let throttle = self.limiter.as_deref().map(|limiter| (limiter, &host[..]));
+        let progress = self.progress.as_deref();
+
+        let client = proxy::surf_client(self.proxy.as_ref());
+        let RangedFile {
+            size: total_size,
+            validator,
+        } = match get_ranged_file(&client, url).await {
+            Some(remote) => remote,
+            None => {
+                log::debug!(
+                    "streaming: {} does not support ranges, falling back to a single request",
+                    url
+                );
+                part_file.set_len(0).await.map_err(write_err)?;
+                let resp =
+                    send_checked(&client, client.get(url).build(), &[StatusCode::Ok]).await?;
+                let expected_size = super::content_length(&resp);
+                if let Some(progress) = progress {
+                    progress.set_total(expected_size);
+                }
+                copy_and_hash(resp, &mut part_file, &mut hasher, throttle, progress)
+                    .await
+                    .map_err(write_err)?;
+                return finish_part_file(
+                    part_file,
+                    &part_path,
+                    &output_path,
+                    expected_size,
+                    hasher,
+                )
+                .await;
+            }
+        };
+
+        let validator_path = part_file_path(&part_path, VALIDATOR_SUFFIX);
+        let mut saved_size = part_file.metadata().await.map_err(write_err)?.len();
+        // Without the validator the saved bytes came from, there is no telling whether they still match
+        let saved_validator = async_fs::read_to_string(&validator_path).await.ok();
+        if saved_size > 0 && saved_validator.as_deref() != Some(validator.as_str()) {
+            log::debug!(
+                "streaming: {} changed since {:?} was started, starting over",
+                url,
+                part_path
+            );
+            part_file.set_len(0).await.map_err(write_err)?;
+            saved_size = 0;
+        }
+        if saved_size > total_size {
+            log::debug!(
+                "streaming: {:?} is larger than the remote file, starting over",
+                part_path
+            );
+            part_file.set_len(0).await.map_err(write_err)?;
+            saved_size = 0;
+        } else if saved_size > 0 {
+            log::info!(
+                "streaming: resuming {:?} from {} of {} bytes",
+                output_path,
+                saved_size,
+                total_size
+            );
+            // The checksum covers the whole file, catch the hasher up with what is already on disk
+            let existing = async_fs::File::open(&part_path).await.map_err(write_err)?;
+            copy_and_hash(existing, &mut futures::io::sink(), &mut hasher, None, None)
+                .await
+                .map_err(write_err)?;
+        }
+
+        if saved_size == 0 {
+            async_fs::write(&validator_path, &validator)
+                .await
+                .map_err(write_err)?;
+        }
+
+        if let Some(progress) = progress {
+            progress.set_total(Some(total_size));
+            progress.advance(saved_size);
+        }
+        let mut expected_size = Some(total_size);
+        if saved_size < total_size {
+            for range in PartialRangeIter::new(saved_size, total_size - 1, self.chunk_size)? {
+                let req = client
+                    .get(url)
+                    .header("Range", range)
+                    .header(IF_RANGE, validator.as_str())
+                    .build();
+                let resp =
+                    send_checked(&client, req, &[StatusCode::PartialContent, StatusCode::Ok])
+                        .await?;
+                if resp.status() == StatusCode::Ok {
+                    // The file changed since the download started, the server sent all of the new one instead
+                    log::debug!(
+                        "streaming: {} changed while downloading, starting over",
+                        url
+                    );
+                    part_file.set_len(0).await.map_err(write_err)?;
+                    hasher = Sha256::new();
+                    expected_size = super::content_length(&resp);
+                    if let Some(progress) = progress {
+                        progress.set_total(expected_size);
+                    }
+                    copy_and_hash(resp, &mut part_file, &mut hasher, throttle, progress)
+                        .await
+                        .map_err(write_err)?;
+                    break;
+                }
+                copy_and_hash(resp, &mut part_file, &mut hasher, throttle, progress)
+                    .await
+                    .map_err(write_err)?;
+                // Make sure the chunk is on disk before asking for the next one so a crash
+                // never leaves the part file claiming bytes it doesn't have
+                part_file.flush().await.map_err(write_err)?;
+            }
+        }
+
+        let download =
+            finish_part_file(part_file, &part_path, &output_path, expected_size, hasher).await?;
+        if let Err(remove_err) = async_fs::remove_file(&validator_path).await {
+            log::debug!(
+                "streaming: Failed to remove {:?}. {:?}",
+                validator_path,
+                remove_err
+            );
+        }
+        Ok(download)
+    }
+}
+
+/// What a `HEAD` request says about a file that can be downloaded in ranges
+struct RangedFile {
+    size: u64,
+    /// A strong `ETag` or the `Last-Modified` date, sent as `If-Range` so a changed file is never joined onto old bytes
+    validator: String,
+}
+
+/// Ask the server for the size of the file, only returns a value if it accepts byte ranges and names the version of
+/// the file it is serving
+async fn get_ranged_file(client: &'_ surf::Client, url: &'_ str) -> Option<RangedFile> {
+    let resp = match client.send(client.head(url).build()).await {
+        Ok(resp) if resp.status().is_success() => resp,
+        Ok(resp) => {
+            log::debug!("streaming: HEAD {} returned {}", url, resp.status());
+            return None;
+        }
+        Err(head_err) => {
+            log::debug!("streaming: HEAD {} failed. {:?}", url, head_err);
+            return None;
+        }
+    };
+    let accepts_bytes = resp
+        .header(ACCEPT_RANGES)
+        .map(|ranges| ranges.as_str().contains("bytes"))
+        .unwrap_or(false);
+    if !accepts_bytes {
+        return None;
+    }
+    // Weak tags can't be used with `If-Range`
+    let validator = resp
+        .header(ETAG)
+        .map(|etag| etag.as_str())
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| resp.header(LAST_MODIFIED).map(|modified| modified.as_str()));
+    let validator = match validator {
+        Some(validator) => validator.to_string(),
+        None => {
+            log::debug!("streaming: {} has no ETag or Last-Modified", url);
+            return None;
+        }
+    };
+    Some(RangedFile {
+        size: super::content_length(&resp)?,
+        validator,
+    })
+}
+
+async fn send_checked(
+    client: &'_ surf::Client,
+    req: surf::Request,
+    expected: &'_ [StatusCode],
+) -> Result<surf::Response> {
+    let url = req.url().to_string();
+    let resp = client
+        .send(req)
+        .await
+        .map_err(|req_err| DownloadErrors::RequestFailed {
+            url: url.clone(),
+            err: req_err,
+        })?;
+    if !expected.contains(&resp.status()) {
+        log::debug!(
+            "streaming: Request to {} returned {}, expected {:?}",
+            url,
+            resp.status(),
+            expected
+        );
+        return Err(Box::new(DownloadErrors::BadStatus {
+            url,
+            status: resp.status(),
+        }));
     }
+    Ok(resp)
 }
 
 // Initial version from Rust Cookbook
@@ -19,7 +307,6 @@ struct PartialRangeIter {
 }
 
 impl PartialRangeIter {
-    #[allow(unused)]
     pub fn new(start: u64, end: u64, buffer_size: u32) -> Result<Self> {
         if buffer_size == 0 {
             return Err("invalid buffer_size, give a value greater than zero.".into());
@@ -60,3 +347,209 @@ impl Iterator for PartialRangeIter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{PartialRangeIter, StreamingFileDownloader},
+        crate::downloaders::{FileDownload, FileDownloader},
+        futures::executor::block_on,
+        std::{
+            io::{Read, Write},
+            net::{TcpListener, TcpStream},
+            path::PathBuf,
+            sync::mpsc,
+            thread,
+        },
+    };
+
+    /// A file server whose `HEAD` and `GET` can disagree, as when the file changes between the two
+    struct FakeServer {
+        head_etag: &'static str,
+        head_body: &'static str,
+        get_etag: &'static str,
+        get_body: &'static str,
+    }
+
+    fn read_head(stream: &'_ mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte).unwrap() == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    fn header<'a>(head: &'a str, name: &'_ str) -> Option<&'a str> {
+        head.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    /// Serves requests until the test ends, the `If-Range` sent with each comes back on the channel
+    fn serve(server: FakeServer) -> (String, mpsc::Receiver<Option<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let head = read_head(&mut stream);
+                let request_line = head.lines().next().unwrap_or_default();
+                let if_range = header(&head, "if-range");
+                tx.send(if_range.map(String::from)).unwrap();
+                let response = if request_line.starts_with("HEAD") {
+                    format!(
+                        "HTTP/1.1 200 OK\r\naccept-ranges: bytes\r\netag: {}\r\ncontent-length: {}\r\n\
+                         connection: close\r\n\r\n",
+                        server.head_etag,
+                        server.head_body.len()
+                    )
+                } else {
+                    let body = server.get_body;
+                    let range = header(&head, "range")
+                        .and_then(|range| range.strip_prefix("bytes="))
+                        .and_then(|range| range.split_once('-'))
+                        .map(|(start, end)| {
+                            (start.parse().unwrap(), end.parse::<usize>().unwrap())
+                        });
+                    match range {
+                        Some((start, end)) if if_range == Some(server.get_etag) => format!(
+                            "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            end + 1 - start,
+                            &body[start..=end]
+                        ),
+                        _ => format!(
+                            "HTTP/1.1 200 OK\r\netag: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            server.get_etag,
+                            body.len(),
+                            body
+                        ),
+                    }
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (format!("http://{}/video.mp4", addr), rx)
+    }
+
+    fn output_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gatherers-streaming-{}-{}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Download into a folder that already has `part` saved from a file tagged `saved_etag`
+    fn resume(
+        server: FakeServer,
+        part: &'_ str,
+        saved_etag: &'_ str,
+    ) -> (String, Vec<Option<String>>) {
+        let dir = output_dir();
+        let output_path = dir.join("video.mp4");
+        std::fs::write(dir.join("video.mp4.part"), part).unwrap();
+        std::fs::write(dir.join("video.mp4.part.validator"), saved_etag).unwrap();
+        let (url, if_ranges) = serve(server);
+        let download =
+            block_on(StreamingFileDownloader::new(4).download(&url, output_path.clone())).unwrap();
+        let contents = std::fs::read_to_string(&output_path).unwrap();
+        assert!(
+            matches!(download, FileDownload::Saved { size, .. } if size as usize == contents.len())
+        );
+        assert!(!dir.join("video.mp4.part.validator").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+        (contents, if_ranges.try_iter().collect())
+    }
+
+    #[test]
+    fn resumes_an_unchanged_file_with_if_range() {
+        let (contents, if_ranges) = resume(
+            FakeServer {
+                head_etag: "\"v1\"",
+                head_body: "hello world",
+                get_etag: "\"v1\"",
+                get_body: "hello world",
+            },
+            "hello ",
+            "\"v1\"",
+        );
+        assert_eq!(contents, "hello world");
+        // Nothing for the HEAD, then the tag of the saved bytes with both ranges that were left
+        let v1 = Some("\"v1\"".to_string());
+        assert_eq!(if_ranges, [None, v1.clone(), v1]);
+    }
+
+    #[test]
+    fn a_file_changed_before_the_run_starts_over() {
+        let (contents, if_ranges) = resume(
+            FakeServer {
+                head_etag: "\"v2\"",
+                head_body: "HELLO WORLD",
+                get_etag: "\"v2\"",
+                get_body: "HELLO WORLD",
+            },
+            "hello ",
+            "\"v1\"",
+        );
+        assert_eq!(contents, "HELLO WORLD");
+        // 11 bytes in chunks of 4, from the start
+        assert_eq!(if_ranges.len(), 4);
+    }
+
+    #[test]
+    fn a_file_changed_during_the_download_starts_over() {
+        let (contents, _) = resume(
+            FakeServer {
+                head_etag: "\"v1\"",
+                head_body: "hello world",
+                get_etag: "\"v2\"",
+                get_body: "a newer and longer file",
+            },
+            "hello ",
+            "\"v1\"",
+        );
+        assert_eq!(contents, "a newer and longer file");
+    }
+
+    fn ranges(start: u64, end: u64, chunk_size: u32) -> Vec<String> {
+        PartialRangeIter::new(start, end, chunk_size)
+            .unwrap()
+            .map(|range| range.as_str().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn first_and_last_chunks_cover_the_whole_file() {
+        // 10 bytes in chunks of 4, the last chunk only has what is left
+        assert_eq!(ranges(0, 9, 4), ["bytes=0-3", "bytes=4-7", "bytes=8-9"]);
+    }
+
+    #[test]
+    fn chunk_the_same_size_as_the_remaining_bytes() {
+        assert_eq!(ranges(0, 7, 4), ["bytes=0-3", "bytes=4-7"]);
+        assert_eq!(ranges(0, 3, 4), ["bytes=0-3"]);
+        // A single byte file
+        assert_eq!(ranges(0, 0, 4), ["bytes=0-0"]);
+    }
+
+    #[test]
+    fn resumes_from_the_saved_size() {
+        assert_eq!(ranges(6, 9, 4), ["bytes=6-9"]);
+        assert_eq!(ranges(5, 13, 4), ["bytes=5-8", "bytes=9-12", "bytes=13-13"]);
+        // Nothing left once the start is past the end
+        assert!(ranges(10, 9, 4).is_empty());
+    }
+
+    #[test]
+    fn zero_chunk_size_is_rejected() {
+        assert!(PartialRangeIter::new(0, 9, 0).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use {
+    super::{content_length, FileDownload, FileDownloader},
+    crate::{
+        http::{proxy, ProxyConfig},
+        Result,
+    },
+    async_trait::async_trait,
+    std::path::PathBuf,
+};
+
+/// Hands each file to one of two downloaders depending on its size
+///
+/// The size comes from a `HEAD` request, files at or over the threshold go to `large` and everything else,
+/// including files the server won't give a size for, goes to `small`.
+pub struct ThresholdFileDownloader {
+    threshold: u64,
+    small: Box<dyn FileDownloader>,
+    large: Box<dyn FileDownloader>,
+    /// Only for the `HEAD` request, the two downloaders have their own
+    proxy: Option<ProxyConfig>,
+}
+
+impl ThresholdFileDownloader {
+    pub fn new(
+        threshold: u64,
+        small: Box<dyn FileDownloader>,
+        large: Box<dyn FileDownloader>,
+    ) -> Self {
+        Self {
+            threshold,
+            small,
+            large,
+            proxy: None,
+        }
+    }
+
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
+
+#[async_trait]
+impl FileDownloader for ThresholdFileDownloader {
+    async fn download(&self, url: &'_ str, output_path: PathBuf) -> Result<FileDownload> {
+        if output_path.exists() {
+            log::debug!("threshold: {:?} already exists, skipping", output_path);
+            return Ok(FileDownload::AlreadyExists);
+        }
+        let client = proxy::surf_client(self.proxy.as_ref());
+        let size = match client.send(client.head(url).build()).await {
+            Ok(resp) if resp.status().is_success() => content_length(&resp),
+            Ok(resp) => {
+                log::debug!("threshold: HEAD {} returned {}", url, resp.status());
+                None
+            }
+            Err(head_err) => {
+                log::debug!("threshold: HEAD {} failed. {:?}", url, head_err);
+                None
+            }
+        };
+        match size {
+            Some(size) if size >= self.threshold => {
+                log::debug!(
+                    "threshold: {:?} is {} bytes, using the large file downloader",
+                    output_path,
+                    size
+                );
+                self.large.download(url, output_path).await
+            }
+            _ => self.small.download(url, output_path).await,
+        }
+    }
+}
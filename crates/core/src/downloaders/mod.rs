@@ -1,18 +1,109 @@
+mod bandwidth;
 mod batch;
+mod dedupe;
 mod downloadable;
+mod errors;
 mod file;
+mod priority;
+mod progress;
+mod retry;
 
 pub use self::{
-    batch::{BatchDownloader, MultiThreadedDownloader, SequentialDownloader},
+    bandwidth::{BandwidthLimiter, BandwidthLimits},
+    batch::{
+        BatchDownloader, BatchDownloaderBuilder, BatchStrategy, DownloaderConfig,
+        MultiThreadedDownloader, SequentialDownloader,
+    },
+    dedupe::{ContentStore, ContentStoreConfig, LinkMode},
     downloadable::Downloadable,
-    file::{FileDownloader, InMemoryFileDownloader, StreamingFileDownloader},
+    errors::DownloadErrors,
+    file::{
+        FileDownload, FileDownloader, FileDownloaderBuilder, FileDownloaderConfig,
+        FileDownloaderKind, InMemoryFileDownloader, StreamingFileDownloader,
+        ThresholdFileDownloader,
+    },
+    priority::PriorityRules,
+    progress::{DownloadEvent, ProgressEvents, ProgressReporter, SkipReason},
+    retry::{ErrorClass, RetryFailure, RetryPolicy},
+};
+pub(crate) use self::{file::copy_and_hash, retry::backoff};
+use {
+    serde::{Deserialize, Serialize},
+    std::{error::Error, path::PathBuf},
 };
-use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct DownloaderStats {
     pub total: usize,
     pub failed: usize,
     pub success: usize,
     pub previously_saved: usize,
+    /// Downloads that turned out to be a copy of something already in the [`ContentStore`]
+    pub deduplicated: usize,
+    pub bytes_saved: u64,
+    pub failures: Vec<DownloadFailure>,
+}
+
+impl DownloaderStats {
+    pub fn add_deduplicated(&mut self, bytes_saved: u64) {
+        if bytes_saved > 0 {
+            self.deduplicated += 1;
+            self.bytes_saved += bytes_saved;
+        }
+    }
+
+    /// Count a failed item and keep why it failed
+    pub fn add_failure(&mut self, path: PathBuf, failure: &'_ RetryFailure) {
+        self.failed += 1;
+        self.failures.push(DownloadFailure {
+            path,
+            reason: FailureReason::from(failure.err.as_ref()),
+            class: failure.class,
+            attempts: failure.attempts,
+        });
+    }
+}
+
+/// An item that could not be saved
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DownloadFailure {
+    pub path: PathBuf,
+    pub reason: FailureReason,
+    /// [`ErrorClass::Permanent`] failures were not retried and won't be re-attempted by a later run
+    pub class: ErrorClass,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum FailureReason {
+    /// The body didn't match the `Content-Length` the server sent, nothing was moved into place
+    SizeMismatch {
+        expected: u64,
+        received: u64,
+    },
+    BadStatus {
+        status: u16,
+    },
+    Request(String),
+    Write(String),
+    Other(String),
+}
+
+impl From<&'_ (dyn Error + Send + Sync + 'static)> for FailureReason {
+    fn from(err: &'_ (dyn Error + Send + Sync + 'static)) -> Self {
+        match err.downcast_ref::<DownloadErrors>() {
+            Some(DownloadErrors::SizeMismatch {
+                expected, received, ..
+            }) => Self::SizeMismatch {
+                expected: *expected,
+                received: *received,
+            },
+            Some(DownloadErrors::BadStatus { status, .. }) => Self::BadStatus {
+                status: *status as u16,
+            },
+            Some(DownloadErrors::RequestFailed { err, .. }) => Self::Request(err.to_string()),
+            Some(DownloadErrors::WriteFailed { source, .. }) => Self::Write(source.to_string()),
+            None => Self::Other(err.to_string()),
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use {
+    crate::gatherers::{GatherType, Media, MediaKind},
+    serde::{Deserialize, Serialize},
+};
+
+/// How queued items are ranked, the highest score is downloaded first and equal scores keep their queue order
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PriorityRules {
+    /// Added for content that was paid for
+    pub paid: i32,
+    /// Added for content that will disappear, stories and messages with an expiry
+    pub expiring: i32,
+    /// Push every video behind the images so those finish quickly
+    pub videos_last: bool,
+}
+
+impl Default for PriorityRules {
+    fn default() -> Self {
+        Self {
+            paid: 10,
+            expiring: 20,
+            videos_last: false,
+        }
+    }
+}
+
+impl PriorityRules {
+    pub fn score(&self, media: &'_ Media, gather_type: GatherType) -> i32 {
+        let mut score: i32 = 0;
+        if media.paid || gather_type == GatherType::Purchased {
+            score = score.saturating_add(self.paid);
+        }
+        if media.expires_at.is_some() || gather_type == GatherType::Stories {
+            score = score.saturating_add(self.expiring);
+        }
+        if self.videos_last && media.kind == MediaKind::Video {
+            // Far enough below anything else the rules can add, weights near the limits clamp instead of overflowing
+            let below = self
+                .paid
+                .saturating_abs()
+                .saturating_add(self.expiring.saturating_abs())
+                .saturating_add(1);
+            score = score.saturating_sub(below);
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::PriorityRules,
+        crate::gatherers::{GatherType, Media, MediaKind},
+        chrono::Utc,
+    };
+
+    fn media(paid: bool, expiring: bool, mime_type: &'_ str) -> Media {
+        Media {
+            paid,
+            expires_at: expiring.then(Utc::now),
+            mime_type: mime_type.to_string(),
+            kind: MediaKind::from_mime_type(mime_type),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn paid_and_expiring_content_scores_higher() {
+        let rules = PriorityRules::default();
+        assert_eq!(
+            rules.score(&media(false, false, "image/jpeg"), GatherType::Posts),
+            0
+        );
+        assert_eq!(
+            rules.score(&media(true, false, "image/jpeg"), GatherType::Posts),
+            10
+        );
+        assert_eq!(
+            rules.score(&media(false, true, "image/jpeg"), GatherType::Messages),
+            20
+        );
+        assert_eq!(
+            rules.score(&media(true, true, "image/jpeg"), GatherType::Messages),
+            30
+        );
+        // The gather type alone is enough
+        assert_eq!(
+            rules.score(&media(false, false, "image/jpeg"), GatherType::Purchased),
+            10
+        );
+        assert_eq!(
+            rules.score(&media(false, false, "image/jpeg"), GatherType::Stories),
+            20
+        );
+    }
+
+    #[test]
+    fn videos_last_puts_every_video_behind_every_image() {
+        let rules = PriorityRules {
+            videos_last: true,
+            ..Default::default()
+        };
+        let best_video = rules.score(&media(true, true, "video/mp4"), GatherType::Stories);
+        let worst_image = rules.score(&media(false, false, "image/jpeg"), GatherType::Posts);
+        assert!(
+            best_video < worst_image,
+            "{} >= {}",
+            best_video,
+            worst_image
+        );
+        // Negative weights can't lift a video back above an image
+        let rules = PriorityRules {
+            paid: -50,
+            expiring: 5,
+            videos_last: true,
+        };
+        assert!(
+            rules.score(&media(false, true, "video/mp4"), GatherType::Posts)
+                < rules.score(&media(true, false, "image/jpeg"), GatherType::Posts)
+        );
+    }
+
+    #[test]
+    fn extreme_weights_clamp_instead_of_overflowing() {
+        let rules = PriorityRules {
+            paid: i32::MAX,
+            expiring: i32::MIN,
+            videos_last: true,
+        };
+        let video = rules.score(&media(true, true, "video/mp4"), GatherType::Stories);
+        assert_eq!(video, i32::MIN);
+        assert_eq!(
+            rules.score(&media(true, false, "image/jpeg"), GatherType::Posts),
+            i32::MAX
+        );
+    }
+
+    #[test]
+    fn videos_are_picked_by_kind_not_mime_type() {
+        let rules = PriorityRules {
+            videos_last: true,
+            ..Default::default()
+        };
+        // Some sources only give a generic mime type, the kind still says it is a video
+        let video = Media {
+            mime_type: "application/octet-stream".to_string(),
+            kind: MediaKind::Video,
+            ..Default::default()
+        };
+        assert!(rules.score(&video, GatherType::Posts) < 0);
+    }
+}
@@ -0,0 +1,144 @@
+use {
+    super::FailureReason,
+    crate::state::DownloadKey,
+    async_channel::{Receiver, Sender},
+    std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Bytes progressed are reported at most this often for each item, the last chunk is always reported
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a [`super::BatchDownloader`] is doing, subscribe with [`ProgressEvents::subscribe`]
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    /// Picked up by the downloader and waiting for a worker
+    Queued {
+        key: DownloadKey,
+        path: PathBuf,
+        priority: i32,
+    },
+    /// A worker started on the item, `attempt` starts at 1 and goes up with every retry
+    Started {
+        worker: usize,
+        key: DownloadKey,
+        path: PathBuf,
+        attempt: u32,
+    },
+    /// Bytes written for the item so far, `total` is known once the server sends a size
+    Progress {
+        worker: usize,
+        key: DownloadKey,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    Completed {
+        worker: usize,
+        key: DownloadKey,
+        size: u64,
+    },
+    /// Still failing after the last retry
+    Failed {
+        worker: usize,
+        key: DownloadKey,
+        reason: FailureReason,
+        attempts: u32,
+    },
+    /// Nothing was requested for the item
+    Skipped {
+        worker: usize,
+        key: DownloadKey,
+        reason: SkipReason,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Found in the download state
+    PreviouslySaved,
+    /// A file is already at the output path
+    AlreadyExists,
+}
+
+/// Fans [`DownloadEvent`]s out to every subscriber
+///
+/// Sending never waits, subscribers that have been dropped are forgotten on the next event.
+#[derive(Debug, Default)]
+pub struct ProgressEvents {
+    subscribers: Mutex<Vec<Sender<DownloadEvent>>>,
+}
+
+impl ProgressEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive every event sent from now on, the stream ends once the downloader is dropped
+    pub fn subscribe(&self) -> Receiver<DownloadEvent> {
+        let (tx, rx) = async_channel::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn emit(&self, event: DownloadEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+    }
+}
+
+/// Reports the bytes written for a single item, handed to the [`super::FileDownloader`] doing the work
+#[derive(Debug)]
+pub struct ProgressReporter {
+    events: Arc<ProgressEvents>,
+    worker: usize,
+    key: DownloadKey,
+    downloaded: AtomicU64,
+    total: Mutex<Option<u64>>,
+    last_sent: Mutex<Option<Instant>>,
+}
+
+impl ProgressReporter {
+    pub fn new(events: Arc<ProgressEvents>, worker: usize, key: DownloadKey) -> Self {
+        Self {
+            events,
+            worker,
+            key,
+            downloaded: AtomicU64::new(0),
+            total: Mutex::new(None),
+            last_sent: Mutex::new(None),
+        }
+    }
+
+    /// The full size of the file once the server has told us
+    pub fn set_total(&self, total: Option<u64>) {
+        *self.total.lock().unwrap() = total;
+    }
+
+    /// Count `bytes` more as written, resumed downloads start by adding what is already on disk
+    pub fn advance(&self, bytes: u64) {
+        let downloaded = self.downloaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let total = *self.total.lock().unwrap();
+        {
+            let mut last_sent = self.last_sent.lock().unwrap();
+            let finished = total == Some(downloaded);
+            if !finished && matches!(*last_sent, Some(sent) if sent.elapsed() < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last_sent = Some(Instant::now());
+        }
+        self.events.emit(DownloadEvent::Progress {
+            worker: self.worker,
+            key: self.key.clone(),
+            downloaded,
+            total,
+        });
+    }
+}
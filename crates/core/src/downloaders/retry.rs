@@ -0,0 +1,339 @@
+use {
+    super::DownloadErrors,
+    serde::{Deserialize, Serialize},
+    std::{error::Error, fmt::Display, future::Future, io::ErrorKind, time::Duration},
+};
+
+/// Broad groups of download errors, a [`RetryPolicy`] decides which of them are worth another attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// 5xx responses
+    ServerError,
+    /// 429 responses, the server wants us to slow down
+    Throttled,
+    /// The request or the body took too long
+    Timeout,
+    /// The connection was reset, refused or closed before the body finished
+    Connection,
+    /// The body didn't match the `Content-Length` the server sent
+    Incomplete,
+    /// 403, 404 and any other 4xx, asking again will get the same answer
+    Permanent,
+    /// Anything else, mostly local problems like a full disk
+    Other,
+}
+
+impl ErrorClass {
+    pub fn classify(err: &'_ (dyn Error + Send + Sync + 'static)) -> Self {
+        match err.downcast_ref::<DownloadErrors>() {
+            Some(DownloadErrors::BadStatus { status, .. }) => Self::from_status(*status as u16),
+            Some(DownloadErrors::SizeMismatch { .. }) => Self::Incomplete,
+            Some(DownloadErrors::WriteFailed { source, .. }) => {
+                Self::from_io_kind(source.kind()).unwrap_or(Self::Other)
+            }
+            Some(DownloadErrors::RequestFailed { err, .. }) => {
+                match err.downcast_ref::<std::io::Error>() {
+                    Some(io_err) => Self::from_io_kind(io_err.kind()).unwrap_or(Self::Connection),
+                    None if err.to_string().contains("timed out") => Self::Timeout,
+                    // No response at all, treat it the same as a dropped connection
+                    None => Self::Connection,
+                }
+            }
+            None => match err.downcast_ref::<std::io::Error>() {
+                Some(io_err) => Self::from_io_kind(io_err.kind()).unwrap_or(Self::Other),
+                None => Self::Other,
+            },
+        }
+    }
+
+    fn from_status(status: u16) -> Self {
+        match status {
+            408 => Self::Timeout,
+            429 => Self::Throttled,
+            500..=599 => Self::ServerError,
+            _ => Self::Permanent,
+        }
+    }
+
+    fn from_io_kind(kind: ErrorKind) -> Option<Self> {
+        match kind {
+            ErrorKind::TimedOut => Some(Self::Timeout),
+            ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof => Some(Self::Connection),
+            _ => None,
+        }
+    }
+}
+
+/// Exponential backoff with jitter, the delay is somewhere between half and all of the doubled delay
+/// so workers that failed together don't all come back at the same moment
+pub(crate) fn backoff(initial_delay_ms: u64, max_delay_ms: u64, attempt: u32) -> Duration {
+    let exp = attempt.saturating_sub(1).min(16);
+    let delay = initial_delay_ms.saturating_mul(1 << exp).min(max_delay_ms);
+    Duration::from_millis(fastrand::u64(delay / 2..=delay))
+}
+
+/// How many times, how often and for which errors a failed download is attempted again
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one, 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each retry after that
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Error classes that get another attempt, everything else fails right away
+    pub retry_on: Vec<ErrorClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_delay_ms: 1_000,
+            max_delay_ms: 30_000,
+            retry_on: vec![
+                ErrorClass::ServerError,
+                ErrorClass::Throttled,
+                ErrorClass::Timeout,
+                ErrorClass::Connection,
+                ErrorClass::Incomplete,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that gives every item exactly one attempt
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn should_retry(&self, class: ErrorClass) -> bool {
+        self.retry_on.contains(&class)
+    }
+
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        backoff(self.initial_delay_ms, self.max_delay_ms, attempt)
+    }
+
+    /// Run `op` until it succeeds, fails with an error this policy doesn't retry or runs out of attempts
+    pub async fn run<T, F, Fut>(
+        &self,
+        label: &'_ (dyn Display + Sync),
+        mut op: F,
+    ) -> Result<T, RetryFailure>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = crate::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let class = ErrorClass::classify(err.as_ref());
+                    let retryable = self.should_retry(class);
+                    if !retryable || attempt >= self.max_attempts.max(1) {
+                        return Err(RetryFailure {
+                            err,
+                            class,
+                            attempts: attempt,
+                            exhausted: retryable,
+                        });
+                    }
+                    let delay = self.delay_for(attempt);
+                    log::warn!(
+                        "Attempt {} of {} for {} failed ({:?}), retrying in {:?}. {}",
+                        attempt,
+                        self.max_attempts,
+                        label,
+                        class,
+                        delay,
+                        err
+                    );
+                    async_io::Timer::after(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The last error from [`RetryPolicy::run`] along with how it was classified
+#[derive(Debug)]
+pub struct RetryFailure {
+    pub err: Box<dyn Error + Send + Sync>,
+    pub class: ErrorClass,
+    pub attempts: u32,
+    /// Every allowed attempt was used on an error that could have been retried,
+    /// the item is worth trying again in a later run
+    pub exhausted: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ErrorClass, RetryPolicy},
+        crate::downloaders::DownloadErrors,
+        futures::executor::block_on,
+        std::{cell::Cell, io::ErrorKind, path::PathBuf, time::Duration},
+        surf::StatusCode,
+    };
+
+    fn classify(err: DownloadErrors) -> ErrorClass {
+        ErrorClass::classify(&err)
+    }
+
+    fn bad_status(status: StatusCode) -> ErrorClass {
+        classify(DownloadErrors::BadStatus {
+            url: String::new(),
+            status,
+        })
+    }
+
+    fn request_failed(err: surf::Error) -> ErrorClass {
+        classify(DownloadErrors::RequestFailed {
+            url: String::new(),
+            err,
+        })
+    }
+
+    #[test]
+    fn statuses_are_classified() {
+        assert_eq!(bad_status(StatusCode::RequestTimeout), ErrorClass::Timeout);
+        assert_eq!(
+            bad_status(StatusCode::TooManyRequests),
+            ErrorClass::Throttled
+        );
+        assert_eq!(bad_status(StatusCode::BadGateway), ErrorClass::ServerError);
+        assert_eq!(bad_status(StatusCode::Forbidden), ErrorClass::Permanent);
+        assert_eq!(bad_status(StatusCode::NotFound), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn request_and_io_errors_are_classified() {
+        let io_err = |kind: ErrorKind| surf::Error::from(std::io::Error::from(kind));
+        assert_eq!(
+            request_failed(io_err(ErrorKind::TimedOut)),
+            ErrorClass::Timeout
+        );
+        assert_eq!(
+            request_failed(io_err(ErrorKind::ConnectionReset)),
+            ErrorClass::Connection
+        );
+        // Unknown io errors while requesting still count as a lost connection
+        assert_eq!(
+            request_failed(io_err(ErrorKind::PermissionDenied)),
+            ErrorClass::Connection
+        );
+        assert_eq!(
+            request_failed(surf::Error::from_str(
+                StatusCode::InternalServerError,
+                "request timed out"
+            )),
+            ErrorClass::Timeout
+        );
+        assert_eq!(
+            classify(DownloadErrors::SizeMismatch {
+                path: PathBuf::new(),
+                expected: 10,
+                received: 5,
+            }),
+            ErrorClass::Incomplete
+        );
+        assert_eq!(
+            classify(DownloadErrors::WriteFailed {
+                path: PathBuf::new(),
+                source: std::io::Error::from(ErrorKind::PermissionDenied),
+            }),
+            ErrorClass::Other
+        );
+        assert_eq!(
+            ErrorClass::classify(&std::io::Error::from(ErrorKind::BrokenPipe)),
+            ErrorClass::Connection
+        );
+    }
+
+    #[test]
+    fn delays_double_up_to_the_max_with_jitter() {
+        let policy = RetryPolicy {
+            initial_delay_ms: 100,
+            max_delay_ms: 1_000,
+            ..Default::default()
+        };
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400), (5, 1_000), (40, 1_000)] {
+            let delay = policy.delay_for(attempt);
+            assert!(
+                delay >= Duration::from_millis(full / 2) && delay <= Duration::from_millis(full),
+                "attempt {} waited {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    fn attempts_until_failure(policy: &'_ RetryPolicy, err: fn() -> DownloadErrors) -> (u32, bool) {
+        let calls = Cell::new(0);
+        let failure = block_on(policy.run(&"item", || {
+            calls.set(calls.get() + 1);
+            async move { Err::<(), _>(err().into()) }
+        }))
+        .unwrap_err();
+        assert_eq!(failure.attempts, calls.get());
+        (failure.attempts, failure.exhausted)
+    }
+
+    #[test]
+    fn only_retryable_errors_are_attempted_again() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_delay_ms: 0,
+            ..Default::default()
+        };
+        let server_error = || DownloadErrors::BadStatus {
+            url: String::new(),
+            status: StatusCode::ServiceUnavailable,
+        };
+        let not_found = || DownloadErrors::BadStatus {
+            url: String::new(),
+            status: StatusCode::NotFound,
+        };
+        assert_eq!(attempts_until_failure(&policy, server_error), (3, true));
+        assert_eq!(attempts_until_failure(&policy, not_found), (1, false));
+        assert_eq!(
+            attempts_until_failure(&RetryPolicy::none(), server_error),
+            (1, true)
+        );
+    }
+
+    #[test]
+    fn a_success_after_a_retry_is_returned() {
+        let policy = RetryPolicy {
+            initial_delay_ms: 0,
+            ..Default::default()
+        };
+        let calls = Cell::new(0);
+        let value = block_on(policy.run(&"item", || {
+            calls.set(calls.get() + 1);
+            let attempt = calls.get();
+            async move {
+                if attempt < 2 {
+                    Err(std::io::Error::from(ErrorKind::ConnectionReset).into())
+                } else {
+                    Ok(attempt)
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(value, 2);
+    }
+}
@@ -0,0 +1,259 @@
+use {
+    super::{GatherType, Media},
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::str::FromStr,
+};
+
+/// What sort of file a [`Media`] is
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize, strum::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    /// Animated images, some sources serve these as short videos
+    Gif,
+    #[default]
+    Unknown,
+}
+
+impl MediaKind {
+    pub fn from_mime_type(mime_type: &'_ str) -> Self {
+        match mime_type.split_once('/') {
+            Some((_, "gif")) => Self::Gif,
+            Some(("image", _)) => Self::Image,
+            Some(("video", _)) => Self::Video,
+            Some(("audio", _)) => Self::Audio,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Whether paid or free content is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaidFilter {
+    #[default]
+    Any,
+    Paid,
+    Free,
+}
+
+/// Picks the media that is queued for download, an empty list allows everything of that sort
+///
+/// Parsed from a comma separated list such as `videos,messages,paid`, each value is a media kind, a source or
+/// `paid`/`free`. Values of the same sort are combined with or, the different sorts with and.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContentFilter {
+    pub kinds: Vec<MediaKind>,
+    pub sources: Vec<GatherType>,
+    pub paid: PaidFilter,
+}
+
+impl ContentFilter {
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty() && self.sources.is_empty() && self.paid == PaidFilter::Any
+    }
+
+    /// Whether anything gathered for the type can pass, gathers that can't are never started
+    pub fn allows_source(&self, gather_type: GatherType) -> bool {
+        self.sources.is_empty() || self.sources.contains(&gather_type)
+    }
+
+    /// Whether every item gathered for the type gets through, only then is the gather complete enough to move
+    /// its watermark forward
+    pub fn keeps_all_of(&self, gather_type: GatherType) -> bool {
+        self.allows_source(gather_type) && self.kinds.is_empty() && self.paid == PaidFilter::Any
+    }
+
+    pub fn allows(&self, media: &'_ Media, gather_type: GatherType) -> bool {
+        let paid = media.paid || gather_type == GatherType::Purchased;
+        self.allows_source(gather_type)
+            && (self.kinds.is_empty() || self.kinds.contains(&media.kind))
+            && match self.paid {
+                PaidFilter::Any => true,
+                PaidFilter::Paid => paid,
+                PaidFilter::Free => !paid,
+            }
+    }
+}
+
+impl FromStr for ContentFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+        for value in s.split(',').map(|value| value.trim().to_lowercase()) {
+            match value.as_str() {
+                "all" | "" => {}
+                "image" | "images" => filter.kinds.push(MediaKind::Image),
+                "video" | "videos" => filter.kinds.push(MediaKind::Video),
+                "audio" => filter.kinds.push(MediaKind::Audio),
+                "gif" | "gifs" => filter.kinds.push(MediaKind::Gif),
+                "post" | "posts" => filter.sources.push(GatherType::Posts),
+                "message" | "messages" => filter.sources.push(GatherType::Messages),
+                "story" | "stories" => filter.sources.push(GatherType::Stories),
+                "bundle" | "bundles" => filter.sources.push(GatherType::Bundles),
+                "purchased" => filter.sources.push(GatherType::Purchased),
+                "paid" if filter.paid != PaidFilter::Free => filter.paid = PaidFilter::Paid,
+                "free" if filter.paid != PaidFilter::Paid => filter.paid = PaidFilter::Free,
+                // Asking for both is the same as not asking
+                "paid" | "free" => filter.paid = PaidFilter::Any,
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a valid content type. Valid: image, video, audio, gif, posts, messages, \
+                         stories, bundles, purchased, paid, free",
+                        value
+                    ))
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// Only content dated inside the range is gathered, either end can be left open
+///
+/// Posts are dated by when they went up, messages by when they were sent and purchases by when they were bought,
+/// see [`Media::dated_at`]. Undated content is always kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DateRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    pub fn new(since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> Self {
+        Self { since, until }
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    pub fn contains(&self, time: Option<DateTime<Utc>>) -> bool {
+        let time = match time {
+            Some(time) => time,
+            None => return true,
+        };
+        !matches!(self.since, Some(since) if time < since)
+            && !matches!(self.until, Some(until) if time > until)
+    }
+
+    /// Whether the item is older than `since`, in a newest first listing so is everything after it
+    pub fn is_past(&self, time: Option<DateTime<Utc>>) -> bool {
+        matches!((self.since, time), (Some(since), Some(time)) if time < since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ContentFilter, DateRange, MediaKind, PaidFilter},
+        crate::gatherers::{GatherType, Media},
+        chrono::{TimeZone, Utc},
+    };
+
+    fn media(kind: MediaKind, paid: bool) -> Media {
+        Media {
+            kind,
+            paid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn content_filters_are_parsed_from_a_list() {
+        let filter: ContentFilter = " Videos, gif,messages ,paid".parse().unwrap();
+        assert_eq!(
+            filter,
+            ContentFilter {
+                kinds: vec![MediaKind::Video, MediaKind::Gif],
+                sources: vec![GatherType::Messages],
+                paid: PaidFilter::Paid,
+            }
+        );
+        assert!("all".parse::<ContentFilter>().unwrap().is_empty());
+        assert!("".parse::<ContentFilter>().unwrap().is_empty());
+        // Both paid and free is the same as neither
+        assert_eq!(
+            "paid,free".parse::<ContentFilter>().unwrap().paid,
+            PaidFilter::Any
+        );
+        let err = "videos,pictures".parse::<ContentFilter>().unwrap_err();
+        assert!(err.contains("'pictures'"), "{}", err);
+    }
+
+    #[test]
+    fn sorts_are_combined_with_and_values_with_or() {
+        let filter: ContentFilter = "images,videos,free,posts".parse().unwrap();
+        assert!(filter.allows(&media(MediaKind::Image, false), GatherType::Posts));
+        assert!(filter.allows(&media(MediaKind::Video, false), GatherType::Posts));
+        assert!(!filter.allows(&media(MediaKind::Audio, false), GatherType::Posts));
+        assert!(!filter.allows(&media(MediaKind::Image, true), GatherType::Posts));
+        assert!(!filter.allows(&media(MediaKind::Image, false), GatherType::Messages));
+        // Purchased content is always paid
+        let paid: ContentFilter = "paid".parse().unwrap();
+        assert!(paid.allows(&media(MediaKind::Image, false), GatherType::Purchased));
+        assert!(!paid.allows(&media(MediaKind::Image, false), GatherType::Posts));
+    }
+
+    #[test]
+    fn only_a_filter_that_keeps_everything_of_a_source_completes_it() {
+        let filter: ContentFilter = "posts".parse().unwrap();
+        assert!(filter.keeps_all_of(GatherType::Posts));
+        assert!(!filter.keeps_all_of(GatherType::Messages));
+        assert!(!filter.allows_source(GatherType::Messages));
+        assert!(!"posts,videos"
+            .parse::<ContentFilter>()
+            .unwrap()
+            .keeps_all_of(GatherType::Posts));
+        assert!(!"free"
+            .parse::<ContentFilter>()
+            .unwrap()
+            .keeps_all_of(GatherType::Posts));
+    }
+
+    #[test]
+    fn media_kinds_come_from_the_mime_type() {
+        assert_eq!(MediaKind::from_mime_type("image/gif"), MediaKind::Gif);
+        assert_eq!(MediaKind::from_mime_type("image/jpeg"), MediaKind::Image);
+        assert_eq!(MediaKind::from_mime_type("video/mp4"), MediaKind::Video);
+        assert_eq!(MediaKind::from_mime_type("audio/mpeg"), MediaKind::Audio);
+        assert_eq!(
+            MediaKind::from_mime_type("application/pdf"),
+            MediaKind::Unknown
+        );
+        assert_eq!(MediaKind::from_mime_type(""), MediaKind::Unknown);
+    }
+
+    #[test]
+    fn date_range_bounds_are_inclusive() {
+        let since = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let until = Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap();
+        let range = DateRange::new(Some(since), Some(until));
+        assert!(range.contains(Some(since)));
+        assert!(range.contains(Some(until)));
+        assert!(!range.contains(Some(since - chrono::Duration::seconds(1))));
+        assert!(!range.contains(Some(until + chrono::Duration::seconds(1))));
+        // Undated items are kept
+        assert!(range.contains(None));
+        assert!(DateRange::default().is_unbounded());
+        assert!(DateRange::default().contains(Some(since)));
+    }
+
+    #[test]
+    fn only_items_older_than_since_are_past() {
+        let since = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let range = DateRange::new(Some(since), None);
+        assert!(range.is_past(Some(since - chrono::Duration::seconds(1))));
+        assert!(!range.is_past(Some(since)));
+        assert!(!range.is_past(None));
+        assert!(!DateRange::new(None, Some(since)).is_past(Some(since - chrono::Duration::days(1))));
+    }
+}
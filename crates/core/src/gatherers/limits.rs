@@ -0,0 +1,251 @@
+use {
+    super::GatherType,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, sync::Mutex},
+};
+
+/// How many media items a run queues for download, `None` is unlimited
+///
+/// Only items sent to the downloader count, anything skipped as previously saved doesn't use up a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MediaLimits {
+    /// Across every gather type of a single subscription
+    pub per_subscription: Option<usize>,
+    /// Across every gatherer and subscription of the run
+    pub per_run: Option<usize>,
+    /// For a single gather type of a single subscription
+    pub per_type: GatherTypeLimits,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GatherTypeLimits {
+    pub posts: Option<usize>,
+    pub messages: Option<usize>,
+    pub bundles: Option<usize>,
+    pub stories: Option<usize>,
+    pub purchased: Option<usize>,
+}
+
+impl GatherTypeLimits {
+    pub fn get(&self, gather_type: GatherType) -> Option<usize> {
+        match gather_type {
+            GatherType::Posts => self.posts,
+            GatherType::Messages => self.messages,
+            GatherType::Bundles => self.bundles,
+            GatherType::Stories => self.stories,
+            GatherType::Purchased => self.purchased,
+        }
+    }
+}
+
+/// Which of the [`MediaLimits`] stopped a gather
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, strum::Display)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitScope {
+    #[strum(serialize = "subscription")]
+    Subscription,
+    #[strum(serialize = "gather type")]
+    GatherType,
+    #[strum(serialize = "run")]
+    Run,
+}
+
+#[derive(Debug, Default)]
+struct BudgetCounts {
+    run: usize,
+    /// Keyed on gatherer and user name
+    subscriptions: HashMap<(String, String), usize>,
+}
+
+/// Counts queued media against the [`MediaLimits`], one budget is shared by every gather of a run
+#[derive(Debug, Default)]
+pub struct MediaBudget {
+    limits: MediaLimits,
+    counts: Mutex<BudgetCounts>,
+}
+
+impl MediaBudget {
+    pub fn new(limits: MediaLimits) -> Self {
+        Self {
+            limits,
+            counts: Mutex::new(BudgetCounts::default()),
+        }
+    }
+
+    pub fn limits(&self) -> MediaLimits {
+        self.limits
+    }
+
+    /// Which limit is already used up, checked before a gather starts so nothing gets requested
+    ///
+    /// `queued` is how many items the gather itself has queued so far, purchased content has no `user_name` and
+    /// only the per type and per run limits apply to it.
+    pub fn exhausted(
+        &self,
+        gatherer: &'_ str,
+        user_name: Option<&'_ str>,
+        gather_type: GatherType,
+        queued: usize,
+    ) -> Option<LimitScope> {
+        let counts = self.counts.lock().unwrap();
+        self.exhausted_with(&counts, gatherer, user_name, gather_type, queued)
+    }
+
+    /// Count one more queued item, nothing is counted and the used up limit is returned when there is no room left
+    pub fn take(
+        &self,
+        gatherer: &'_ str,
+        user_name: Option<&'_ str>,
+        gather_type: GatherType,
+        queued: usize,
+    ) -> Result<(), LimitScope> {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(scope) = self.exhausted_with(&counts, gatherer, user_name, gather_type, queued)
+        {
+            return Err(scope);
+        }
+        counts.run += 1;
+        if let Some(user_name) = user_name {
+            *counts
+                .subscriptions
+                .entry((gatherer.to_string(), user_name.to_string()))
+                .or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn exhausted_with(
+        &self,
+        counts: &'_ BudgetCounts,
+        gatherer: &'_ str,
+        user_name: Option<&'_ str>,
+        gather_type: GatherType,
+        queued: usize,
+    ) -> Option<LimitScope> {
+        if matches!(self.limits.per_run, Some(limit) if counts.run >= limit) {
+            return Some(LimitScope::Run);
+        }
+        if let (Some(limit), Some(user_name)) = (self.limits.per_subscription, user_name) {
+            let sub_count = counts
+                .subscriptions
+                .get(&(gatherer.to_string(), user_name.to_string()))
+                .copied()
+                .unwrap_or_default();
+            if sub_count >= limit {
+                return Some(LimitScope::Subscription);
+            }
+        }
+        match self.limits.per_type.get(gather_type) {
+            Some(limit) if queued >= limit => Some(LimitScope::GatherType),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{GatherTypeLimits, LimitScope, MediaBudget, MediaLimits},
+        crate::gatherers::GatherType,
+    };
+
+    #[test]
+    fn an_unlimited_budget_takes_everything() {
+        let budget = MediaBudget::default();
+        for queued in 0..1_000 {
+            assert_eq!(
+                budget.take("onlyfans", Some("creator"), GatherType::Posts, queued),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            budget.exhausted("onlyfans", None, GatherType::Purchased, 1_000),
+            None
+        );
+    }
+
+    #[test]
+    fn the_type_limit_is_per_gather() {
+        let budget = MediaBudget::new(MediaLimits {
+            per_type: GatherTypeLimits {
+                posts: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(
+            budget.take("onlyfans", Some("creator"), GatherType::Posts, 1),
+            Ok(())
+        );
+        assert_eq!(
+            budget.take("onlyfans", Some("creator"), GatherType::Posts, 2),
+            Err(LimitScope::GatherType)
+        );
+        // Another gather starts back at zero, messages have no limit
+        assert_eq!(
+            budget.exhausted("onlyfans", Some("other"), GatherType::Posts, 0),
+            None
+        );
+        assert_eq!(
+            budget.exhausted("onlyfans", Some("creator"), GatherType::Messages, 50),
+            None
+        );
+    }
+
+    #[test]
+    fn subscriptions_are_counted_across_gather_types() {
+        let budget = MediaBudget::new(MediaLimits {
+            per_subscription: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(
+            budget.take("onlyfans", Some("creator"), GatherType::Posts, 0),
+            Ok(())
+        );
+        assert_eq!(
+            budget.take("onlyfans", Some("creator"), GatherType::Messages, 0),
+            Ok(())
+        );
+        assert_eq!(
+            budget.take("onlyfans", Some("creator"), GatherType::Stories, 0),
+            Err(LimitScope::Subscription)
+        );
+        // Same user name on another site is another subscription, purchased content belongs to none
+        assert_eq!(
+            budget.take("fansly", Some("creator"), GatherType::Posts, 0),
+            Ok(())
+        );
+        assert_eq!(
+            budget.take("onlyfans", None, GatherType::Purchased, 0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn the_run_limit_is_shared_by_everything() {
+        let budget = MediaBudget::new(MediaLimits {
+            per_run: Some(2),
+            per_subscription: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(
+            budget.take("onlyfans", Some("a"), GatherType::Posts, 0),
+            Ok(())
+        );
+        assert_eq!(
+            budget.take("fansly", None, GatherType::Purchased, 0),
+            Ok(())
+        );
+        // The run limit is reported before the subscription that is also used up
+        assert_eq!(
+            budget.exhausted("onlyfans", Some("a"), GatherType::Posts, 0),
+            Some(LimitScope::Run)
+        );
+        assert_eq!(
+            budget.take("onlyfans", Some("b"), GatherType::Posts, 0),
+            Err(LimitScope::Run)
+        );
+    }
+}
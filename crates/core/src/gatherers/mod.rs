@@ -4,18 +4,60 @@
 //! Initially this is designed around getting **PAID** content from subscription sites.
 
 mod errors;
-mod modifiers;
+mod filter;
+mod limits;
+pub mod modifiers;
+pub mod registry;
+mod report;
+mod sidecars;
 pub mod structs;
+mod templates;
 
-pub use self::{errors::GathererErrors, structs::*};
+pub use self::{
+    errors::GathererErrors,
+    filter::{ContentFilter, DateRange, MediaKind, PaidFilter},
+    limits::{GatherTypeLimits, LimitScope, MediaBudget, MediaLimits},
+    registry::{
+        find_gatherer, registered_gatherers, Capability, GathererFactory, GathererRegistration,
+    },
+    report::{GatherFailure, GatherReport, RunSummary, SkippedItems},
+    sidecars::{Sidecar, SidecarWriter},
+    structs::*,
+    templates::{
+        PathTemplate, PathTemplateConfig, PathTemplates, TemplateContext, DEFAULT_PATH_TEMPLATE,
+    },
+};
 use {
-    crate::{downloaders::Downloadable, Result},
+    crate::{
+        downloaders::Downloadable,
+        state::{WatermarkCursor, WatermarkKey},
+        Result,
+    },
     async_channel::Sender,
     async_trait::async_trait,
-    std::{fmt::Debug, path::PathBuf, sync::Arc},
+    futures::{
+        stream::{self, BoxStream},
+        Future, StreamExt, TryStreamExt,
+    },
+    serde::{Deserialize, Serialize},
+    std::{fmt::Debug, path::Path, sync::Arc},
     strum::IntoEnumIterator,
 };
 
+/// Media handed over as each page of the source is parsed, the stream ends after the first error
+pub type MediaStream<'a> = BoxStream<'a, Result<structs::Media>>;
+
+/// Turn a gather that returns everything at once into a [`MediaStream`]
+pub fn media_stream<'a, F>(gathered: F) -> MediaStream<'a>
+where
+    F: Future<Output = Result<Vec<structs::Media>>> + Send + 'a,
+{
+    stream::once(gathered)
+        .map_ok(|media| stream::iter(media.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+}
+
 #[async_trait]
 pub trait Gatherer: Debug + Sync + Send {
     /// Interface with the source site to get the subscriptions of the authed user
@@ -58,6 +100,27 @@ pub trait Gatherer: Debug + Sync + Send {
             feature: "messages".to_string(),
         }))
     }
+    /// Every post of the specified sub with its text and attached media, text only posts included
+    ///
+    /// Pagination stops at the subscription's watermark the same as [`Gatherer::gather_media_from_posts`].
+    async fn gather_posts(&self, _sub: &'_ structs::Subscription) -> Result<Vec<structs::Post>> {
+        Err(Box::new(GathererErrors::NotSupportedByGatherer {
+            gatherer_name: self.name().to_string(),
+            feature: "posts".to_string(),
+        }))
+    }
+    /// Both sides of the conversation with the specified sub, text only messages included
+    ///
+    /// Pagination stops at the subscription's watermark the same as [`Gatherer::gather_media_from_messages`].
+    async fn gather_messages(
+        &self,
+        _sub: &'_ structs::Subscription,
+    ) -> Result<Vec<structs::Message>> {
+        Err(Box::new(GathererErrors::NotSupportedByGatherer {
+            gatherer_name: self.name().to_string(),
+            feature: "messages".to_string(),
+        }))
+    }
     /// Interface with the source site to get the specified subs stories
     ///
     /// TODO: add more detail
@@ -81,6 +144,28 @@ pub trait Gatherer: Debug + Sync + Send {
             feature: "paid content".to_string(),
         }))
     }
+    /// Streaming [`Gatherer::gather_media_from_posts`], media reaches the downloader while later pages are requested
+    ///
+    /// Defaults to waiting for the whole `Vec`, gatherers that paginate should override it.
+    fn stream_media_from_posts<'a>(&'a self, sub: &'a structs::Subscription) -> MediaStream<'a> {
+        media_stream(self.gather_media_from_posts(sub))
+    }
+    /// Streaming [`Gatherer::gather_media_from_messages`]
+    fn stream_media_from_messages<'a>(&'a self, sub: &'a structs::Subscription) -> MediaStream<'a> {
+        media_stream(self.gather_media_from_messages(sub))
+    }
+    /// Streaming [`Gatherer::gather_media_from_bundles`]
+    fn stream_media_from_bundles<'a>(&'a self, sub: &'a structs::Subscription) -> MediaStream<'a> {
+        media_stream(self.gather_media_from_bundles(sub))
+    }
+    /// Streaming [`Gatherer::gather_media_from_stories`]
+    fn stream_media_from_stories<'a>(&'a self, sub: &'a structs::Subscription) -> MediaStream<'a> {
+        media_stream(self.gather_media_from_stories(sub))
+    }
+    /// Streaming [`Gatherer::gather_paid_content`]
+    fn stream_paid_content(&self) -> MediaStream<'_> {
+        media_stream(self.gather_paid_content())
+    }
     async fn gather_transaction_details(
         &self,
         _user_names: &[String],
@@ -98,7 +183,10 @@ pub trait Gatherer: Debug + Sync + Send {
     fn name(&self) -> &'static str;
 }
 
-#[derive(Debug, Clone, Copy, strum::Display, Eq, PartialEq, strum::EnumIter)]
+#[derive(
+    Debug, Clone, Copy, strum::Display, Eq, PartialEq, strum::EnumIter, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
 pub enum GatherType {
     Posts,
     Messages,
@@ -107,10 +195,87 @@ pub enum GatherType {
     Purchased,
 }
 
-pub async fn run_gatherer(info: structs::GathererInfo) -> Result<()> {
+/// Media of whole posts or messages as a stream, the origins of those without any are put in `text_only`
+fn whole_media(
+    gathered: impl Iterator<Item = (MediaOrigin, Vec<Media>)>,
+    text_only: &'_ mut Vec<MediaOrigin>,
+) -> MediaStream<'static> {
+    let mut all_media = Vec::new();
+    for (origin, mut media) in gathered {
+        if media.is_empty() {
+            text_only.push(origin);
+        } else {
+            all_media.append(&mut media);
+        }
+    }
+    stream::iter(all_media.into_iter().map(Ok)).boxed()
+}
+
+/// Gather one type of content for one subscription and queue it for download
+///
+/// Never fails outright, how far it got and why it stopped are in the returned report.
+pub async fn run_gatherer(info: structs::GathererInfo) -> GatherReport {
     let gatherer_name = info.name;
     let gather_type = info.gather_type;
-    let sub = info.subscription;
+    let mut sub = info.subscription;
+    let opts = info.options;
+    let mut report = GatherReport::new(
+        &gatherer_name,
+        (gather_type != GatherType::Purchased).then_some(sub.name.username.as_str()),
+        gather_type,
+    );
+    // Purchased content isn't tied to a subscription so there is nothing to keep a watermark for
+    let watermark_key = match (&opts.watermarks, gather_type) {
+        (Some(_), GatherType::Purchased) | (None, _) => None,
+        (Some(_), _) => Some(WatermarkKey {
+            gatherer: gatherer_name.clone(),
+            user_name: sub.name.username.clone(),
+            gather_type: gather_type.to_string(),
+        }),
+    };
+    // A date range may reach back past the watermark, so it walks everything like a full scan
+    let previous = match (&opts.watermarks, &watermark_key) {
+        (Some(watermarks), Some(key)) if !opts.full_scan && opts.date_range.is_unbounded() => {
+            watermarks.get(key)
+        }
+        _ => None,
+    };
+    if let Some(previous) = &previous {
+        log::debug!(
+            "{:>12}: Gathering {} for {} newer than {:?}",
+            gatherer_name,
+            gather_type,
+            sub.name.username,
+            previous
+        );
+    }
+    sub.watermark = WatermarkCursor::new(previous);
+    sub.date_range = opts.date_range;
+    if !opts.content_filter.allows_source(gather_type) {
+        log::debug!(
+            "{:>12}: Not gathering {} for {}, filtered out by the content types",
+            gatherer_name,
+            gather_type,
+            &sub.name.username
+        );
+        return report.finish(None);
+    }
+    let user_name = report.user_name.clone();
+    if let Some(scope) =
+        opts.limits
+            .media
+            .exhausted(&gatherer_name, user_name.as_deref(), gather_type, 0)
+    {
+        log::info!(
+            "{:>12}: Not gathering {} for {}, the {} media limit is reached",
+            gatherer_name,
+            gather_type,
+            &sub.name.username,
+            scope
+        );
+        report.limited = Some(scope);
+        return report.finish(None);
+    }
     log::info!(
         "{:>12}: Starting to gather {} for {}",
         gatherer_name,
@@ -118,22 +283,169 @@ pub async fn run_gatherer(info: structs::GathererInfo) -> Result<()> {
         &sub.name.username
     );
 
-    let all_media = match gather_type {
-        GatherType::Posts => info.gatherer.gather_media_from_posts(&sub),
-        GatherType::Messages => info.gatherer.gather_media_from_messages(&sub),
-        GatherType::Bundles => info.gatherer.gather_media_from_bundles(&sub),
-        GatherType::Stories => info.gatherer.gather_media_from_stories(&sub),
-        GatherType::Purchased => info.gatherer.gather_paid_content(),
-    }
-    .await;
+    let mut sidecars = opts.sidecars.then(SidecarWriter::new);
+    // Sidecars need the text of every post and message, so those are gathered whole instead of streamed
+    let mut text_only = Vec::new();
+    let mut all_media = match gather_type {
+        GatherType::Posts if sidecars.is_some() => match info.gatherer.gather_posts(&sub).await {
+            Ok(posts) => whole_media(
+                posts.into_iter().map(|post| (post.origin(), post.media)),
+                &mut text_only,
+            ),
+            Err(err) if GatherFailure::from(&*err).is_expected() => {
+                info.gatherer.stream_media_from_posts(&sub)
+            }
+            Err(err) => stream::once(async { Err(err) }).boxed(),
+        },
+        GatherType::Messages if sidecars.is_some() => {
+            match info.gatherer.gather_messages(&sub).await {
+                Ok(messages) => whole_media(
+                    messages
+                        .into_iter()
+                        .map(|message| (message.origin(), message.attached_media)),
+                    &mut text_only,
+                ),
+                Err(err) if GatherFailure::from(&*err).is_expected() => {
+                    info.gatherer.stream_media_from_messages(&sub)
+                }
+                Err(err) => stream::once(async { Err(err) }).boxed(),
+            }
+        }
+        GatherType::Posts => info.gatherer.stream_media_from_posts(&sub),
+        GatherType::Messages => info.gatherer.stream_media_from_messages(&sub),
+        GatherType::Bundles => info.gatherer.stream_media_from_bundles(&sub),
+        GatherType::Stories => info.gatherer.stream_media_from_stories(&sub),
+        GatherType::Purchased => info.gatherer.stream_paid_content(),
+    };
 
-    match all_media {
-        Ok(medias) => {
+    let mut gather_err = None;
+    // Each item is queued as soon as the gatherer parses it, a failure later on keeps what was already queued
+    while let Some(media) = all_media.next().await {
+        let media = match media {
+            Ok(media) => media,
+            Err(err) => {
+                gather_err = Some(err);
+                break;
+            }
+        };
+        report.found += 1;
+        if !opts.date_range.contains(media.dated_at()) {
+            log::trace!(
+                "{:>12}: {} is outside the date range",
+                gatherer_name,
+                media.file_name
+            );
+            report.skipped.filtered += 1;
+            continue;
+        }
+        if !opts.content_filter.allows(&media, gather_type) {
+            log::trace!("{:>12}: Filtered out {}", gatherer_name, media.file_name);
+            report.skipped.filtered += 1;
+            continue;
+        }
+        let downloadable_path = opts.base_path.join(opts.templates.render(&TemplateContext {
+            gatherer: &gatherer_name,
+            gather_type,
+            subscription: &sub,
+            media: &media,
+        }));
+        let mut item = Downloadable::from_media_at(&gatherer_name, &media, downloadable_path);
+        item.priority = opts.priorities.score(&media, gather_type);
+        let saved = matches!(&opts.state, Some(state) if state.is_saved(&item.get_state_key()));
+        // Dropping the stream once a limit is reached stops any further pages from being requested
+        if !saved {
+            if let Err(scope) = opts.limits.media.take(
+                &gatherer_name,
+                user_name.as_deref(),
+                gather_type,
+                report.queued,
+            ) {
+                log::info!(
+                    "{:>12}: Stopped gathering {} for {}, the {} media limit is reached",
+                    gatherer_name,
+                    gather_type,
+                    &sub.name.username,
+                    scope
+                );
+                report.limited = Some(scope);
+                break;
+            }
+        }
+        if let Some(sidecars) = &mut sidecars {
+            sidecars.add(&media, &item.get_file_path());
+        }
+        if saved {
+            log::trace!("{:>12}: Skipping saved item {}", gatherer_name, item);
+            report.skipped.previously_saved += 1;
+            continue;
+        }
+        match info.downloader.try_send(item) {
+            Ok(_) => {
+                log::debug!("{:>12}: Sent item to download queue", gatherer_name);
+                report.queued += 1;
+            }
+            Err(send_err) => {
+                log::error!(
+                    "{:>12}: Failed to send to queue. {:?}",
+                    gatherer_name,
+                    send_err
+                );
+                report.skipped.failed_to_queue += 1;
+            }
+        }
+    }
+    drop(all_media);
+
+    if report.skipped.previously_saved > 0 {
+        log::info!(
+            "{:>12}: Skipped {} items saved by a previous run",
+            gatherer_name,
+            report.skipped.previously_saved
+        );
+    }
+    if let Some(sidecars) = &mut sidecars {
+        // Text only posts go where a free file of theirs would
+        for origin in text_only
+            .into_iter()
+            .filter(|origin| opts.date_range.contains(origin.posted_at))
+        {
+            let media = Media {
+                file_name: Sidecar::new(&origin, &sub.name.username).file_name(),
+                user_name: sub.name.username.clone(),
+                created_at: origin.posted_at,
+                origin: Some(origin.clone()),
+                ..Default::default()
+            };
+            let path = opts.base_path.join(opts.templates.render(&TemplateContext {
+                gatherer: &gatherer_name,
+                gather_type,
+                subscription: &sub,
+                media: &media,
+            }));
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            sidecars.add_text_only(&origin, &sub.name.username, dir);
+        }
+    }
+    if let Some(sidecars) = sidecars.filter(|sidecars| !sidecars.is_empty()) {
+        match sidecars.write_all().await {
+            Ok(written) => {
+                log::debug!("{:>12}: Wrote {} sidecars", gatherer_name, written)
+            }
+            Err(sidecar_err) => log::error!(
+                "{:>12}: Failed to write sidecars. {:?}",
+                gatherer_name,
+                sidecar_err
+            ),
+        }
+    }
+
+    match gather_err {
+        None => {
             if gather_type == GatherType::Purchased {
                 log::info!(
                     "{:>12}: Completed gathering all paid content. Discovered [{}] items",
                     gatherer_name,
-                    medias.len()
+                    report.found
                 )
             } else {
                 log::info!(
@@ -141,64 +453,75 @@ pub async fn run_gatherer(info: structs::GathererInfo) -> Result<()> {
                     gatherer_name,
                     gather_type,
                     sub.name.username,
-                    medias.len()
+                    report.found
                 )
             };
-            for media in medias.iter() {
-                let downloadable_path = info
-                    .base_path
-                    .clone()
-                    .join(&media.user_name)
-                    .join(if media.paid { "paid" } else { "free" });
-                // let item = Downloadable::from_media_with_path(media, downloadable_path);
-                match info
-                    .downloader
-                    .try_send(Downloadable::from_media_with_path(media, downloadable_path))
-                {
-                    Ok(_) => {
-                        log::debug!("{:>12}: Sent item to download queue", gatherer_name)
-                    }
-                    Err(send_err) => {
-                        log::error!(
-                            "{:>12}: Failed to send to queue. {:?}",
-                            gatherer_name,
-                            send_err
-                        )
-                    }
+            // Only move forward once everything up to the newest item has been queued, a limited, date ranged
+            // or filtered gather left items behind that the next run still has to pick up
+            if let (Some(watermarks), Some(key), Some(newest), None, true, true) = (
+                &opts.watermarks,
+                watermark_key,
+                sub.watermark.newest(),
+                report.limited,
+                opts.date_range.is_unbounded(),
+                opts.content_filter.keeps_all_of(gather_type),
+            ) {
+                if report.queued == 0 {
+                    watermarks.advance(key, newest);
+                } else {
+                    watermarks.advance_after_run(key, newest);
                 }
             }
-            Ok(())
+            report.finish(None)
         }
-        Err(gather_err) => {
-            let err_msg = format!(
-                "{:>12}: Failed to gather {:^10}. Error: {:?}",
-                gatherer_name, gather_type, gather_err
-            );
-            log::error!("{}", err_msg);
-            Err(err_msg.into())
+        Some(gather_err) => {
+            let failure = GatherFailure::from(gather_err.as_ref());
+            if failure.is_expected() {
+                log::debug!(
+                    "{:>12}: Skipping {:^10}. {}",
+                    gatherer_name,
+                    gather_type,
+                    failure
+                );
+            } else {
+                log::error!(
+                    "{:>12}: Failed to gather {:^10} after queueing {} items. Error: {:?}",
+                    gatherer_name,
+                    gather_type,
+                    report.queued,
+                    gather_err
+                );
+            }
+            report.finish(Some(failure))
         }
     }
 }
 
+/// Run every gather type for every subscription of the gatherer, plus purchased content when no users are picked
 pub async fn run_gatherer_for_all(
     gatherer: Arc<dyn Gatherer>,
-    base_path: impl Into<PathBuf>,
     download_tx: Sender<Downloadable>,
-    limits: structs::RunLimits,
-    user_names: &[String],
-    ignored_user_names: &[String],
-) -> Result<()> {
-    let base_path: PathBuf = base_path.into();
+    opts: structs::RunOptions,
+) -> RunSummary {
+    let mut summary = RunSummary::default();
     let mut subs_tasks = Vec::new();
     let gatherer_name = gatherer.name();
-    if user_names.is_empty() {
+    // Gatherers that aren't registered are asked for everything
+    let registration = find_gatherer(gatherer_name);
+    let is_supported = |gather_type: GatherType| {
+        registration.is_none_or(|registration| registration.supports(Capability::from(gather_type)))
+    };
+    if opts.user_names.is_empty() && is_supported(GatherType::Purchased) {
         subs_tasks.push(run_gatherer(GathererInfo {
-            base_path: base_path.clone().join(gatherer.name().to_ascii_lowercase()),
             gather_type: GatherType::Purchased,
             gatherer: gatherer.clone(),
             subscription: Default::default(),
             downloader: download_tx.clone(),
             name: gatherer_name.to_string(),
+            options: RunOptions {
+                watermarks: None,
+                ..opts.clone()
+            },
         }));
     }
     println!("{}: Getting subscriptions.", gatherer_name);
@@ -206,32 +529,32 @@ pub async fn run_gatherer_for_all(
     match sub_result {
         Ok(all_subscriptions) => {
             let total_subs = all_subscriptions.len();
-            let subscriptions = if let Some(subs_limit) = limits.subscriptions {
+            let subscriptions = if let Some(subs_limit) = opts.limits.subscriptions {
                 log::info!(
                     "{:>12}: Limiting to only {} subscriptions",
                     gatherer_name,
                     subs_limit
                 );
                 all_subscriptions.into_iter().take(subs_limit).collect()
-            } else if !user_names.is_empty() {
+            } else if !opts.user_names.is_empty() {
                 log::info!(
                     "{:>12}: Omitting all but {} subscriptions",
                     gatherer_name,
-                    user_names.len()
+                    opts.user_names.len()
                 );
                 all_subscriptions
                     .into_iter()
-                    .filter(|sub| user_names.contains(&sub.name.username))
+                    .filter(|sub| opts.user_names.contains(&sub.name.username))
                     .collect()
-            } else if !ignored_user_names.is_empty() {
+            } else if !opts.ignored_user_names.is_empty() {
                 log::info!(
                     "{:>12}: Removing {} ignored users from the list",
                     gatherer_name,
-                    ignored_user_names.len()
+                    opts.ignored_user_names.len()
                 );
                 all_subscriptions
                     .into_iter()
-                    .filter(|sub| !ignored_user_names.contains(&sub.name.username))
+                    .filter(|sub| !opts.ignored_user_names.contains(&sub.name.username))
                     .collect()
             } else {
                 all_subscriptions
@@ -250,12 +573,9 @@ pub async fn run_gatherer_for_all(
                     total_subs
                 );
             }
-            // Start building output directory for our gatherer
-            let base_path = base_path.clone().join(gatherer.name().to_ascii_lowercase());
-
             // Get a custom iter over our gather-able types, filtering out unneeded values for this function
             let sub_gatherables: Vec<_> = GatherType::iter()
-                .filter(|t| !t.eq(&GatherType::Purchased))
+                .filter(|t| !t.eq(&GatherType::Purchased) && is_supported(*t))
                 .collect();
             // Start looping through the subscriptions found for the gatherer
             for sub in subscriptions.iter() {
@@ -263,26 +583,212 @@ pub async fn run_gatherer_for_all(
                     // TODO: likely a better way to achieve something like this in rust, defaulted to go style :'(
                     // This is the parameters fed into our gatherer
                     let info = structs::GathererInfo {
-                        // Add the users name to the gatherers base path for output dir
-                        base_path: base_path.clone(),
                         gather_type: *gather_type,
                         gatherer: gatherer.clone(),
                         subscription: sub.clone(),
                         downloader: download_tx.clone(),
                         name: gatherer_name.into(),
+                        options: opts.clone(),
                     };
                     subs_tasks.push(run_gatherer(info));
                 }
             }
         }
-        Err(sub_err) => return Err(sub_err),
+        Err(sub_err) => {
+            log::error!(
+                "{:>12}: Failed to get subscriptions. {:?}",
+                gatherer_name,
+                sub_err
+            );
+            summary.add_gatherer_failure(gatherer_name, GatherFailure::from(sub_err.as_ref()));
+        }
     }
 
-    futures::future::join_all(subs_tasks).await;
+    for report in futures::future::join_all(subs_tasks).await {
+        summary.add(report);
+    }
     log::info!(
         "{:>12}: Completed gathering everything for all subs",
         gatherer_name
     );
 
-    Ok(())
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::state::Watermarks,
+        chrono::{TimeZone, Utc},
+        futures::executor::block_on,
+    };
+
+    /// Two posts, an image and a newer video, observed the way the real gatherers do while paginating
+    #[derive(Debug)]
+    struct TwoPosts;
+
+    #[async_trait]
+    impl Gatherer for TwoPosts {
+        async fn gather_subscriptions(&self) -> Result<Vec<Subscription>> {
+            Ok(Vec::new())
+        }
+
+        async fn gather_media_from_posts(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
+            let media = vec![
+                Media {
+                    id: "2".to_string(),
+                    file_name: "2.mp4".to_string(),
+                    user_name: sub.name.username.clone(),
+                    kind: MediaKind::Video,
+                    created_at: Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap()),
+                    ..Default::default()
+                },
+                Media {
+                    id: "1".to_string(),
+                    file_name: "1.jpg".to_string(),
+                    user_name: sub.name.username.clone(),
+                    kind: MediaKind::Image,
+                    created_at: Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+                    ..Default::default()
+                },
+            ];
+            for item in &media {
+                sub.watermark.observe(&item.id, item.created_at);
+            }
+            Ok(media)
+        }
+
+        /// The media above in a post of their own and a text only post
+        async fn gather_posts(&self, sub: &'_ Subscription) -> Result<Vec<Post>> {
+            let posted_at = Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+            let origin = MediaOrigin::new(MediaOriginKind::Post, "11", posted_at);
+            let media = self
+                .gather_media_from_posts(sub)
+                .await?
+                .into_iter()
+                .map(|mut media| {
+                    media.origin = Some(origin.clone());
+                    media
+                })
+                .collect();
+            Ok(vec![
+                Post {
+                    id: "10".to_string(),
+                    content: "Text only".to_string(),
+                    posted_at: Some(Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()),
+                    ..Default::default()
+                },
+                Post {
+                    id: "11".to_string(),
+                    posted_at,
+                    media,
+                    ..Default::default()
+                },
+            ])
+        }
+
+        fn name(&self) -> &'static str {
+            "two_posts"
+        }
+    }
+
+    fn run_posts(watermarks: &'_ Arc<Watermarks>, content_filter: ContentFilter) -> GatherReport {
+        let (info, _rx) = posts_info(watermarks, content_filter);
+        block_on(run_gatherer(info))
+    }
+
+    /// The receiver has to outlive the gather, items sent to a closed queue are dropped
+    fn posts_info(
+        watermarks: &'_ Arc<Watermarks>,
+        content_filter: ContentFilter,
+    ) -> (GathererInfo, async_channel::Receiver<Downloadable>) {
+        let (tx, rx) = async_channel::unbounded();
+        let info = GathererInfo {
+            gather_type: GatherType::Posts,
+            gatherer: Arc::new(TwoPosts),
+            subscription: Subscription {
+                name: SubscriptionName {
+                    username: "creator".to_string(),
+                    display_name: None,
+                },
+                ..Default::default()
+            },
+            downloader: tx,
+            name: "two_posts".to_string(),
+            options: RunOptions {
+                watermarks: Some(Arc::clone(watermarks)),
+                content_filter: Arc::new(content_filter),
+                ..Default::default()
+            },
+        };
+        (info, rx)
+    }
+
+    fn posts_key() -> WatermarkKey {
+        WatermarkKey {
+            gatherer: "two_posts".to_string(),
+            user_name: "creator".to_string(),
+            gather_type: GatherType::Posts.to_string(),
+        }
+    }
+
+    #[test]
+    fn filtered_gather_leaves_the_watermark_alone() {
+        let watermarks = Arc::new(Watermarks::default());
+        let report = run_posts(&watermarks, "videos".parse().unwrap());
+        assert_eq!(report.queued, 1);
+        assert_eq!(report.skipped.filtered, 1);
+        assert_eq!(watermarks.get(&posts_key()), None);
+    }
+
+    #[test]
+    fn unfiltered_gather_moves_the_watermark_to_the_newest_item() {
+        let watermarks = Arc::new(Watermarks::default());
+        // Naming the source being gathered still keeps everything it finds
+        let report = run_posts(&watermarks, "posts".parse().unwrap());
+        assert_eq!(report.queued, 2);
+        // Held back until the run ends since it queued downloads
+        assert_eq!(watermarks.get(&posts_key()), None);
+        watermarks.commit_pending();
+        assert_eq!(
+            watermarks
+                .get(&posts_key())
+                .and_then(|watermark| watermark.id),
+            Some("2".to_string())
+        );
+    }
+
+    #[test]
+    fn text_only_posts_get_a_sidecar() {
+        let base_path = std::env::temp_dir().join(format!(
+            "gatherers-sidecars-{}-{}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        let watermarks = Arc::new(Watermarks::default());
+        let (mut info, _rx) = posts_info(&watermarks, ContentFilter::default());
+        info.options.base_path = base_path.clone();
+        info.options.sidecars = true;
+        let report = block_on(run_gatherer(info));
+        assert_eq!(report.queued, 2);
+        // Watermarks are still observed when posts are gathered whole
+        watermarks.commit_pending();
+        assert_eq!(
+            watermarks
+                .get(&posts_key())
+                .and_then(|watermark| watermark.id),
+            Some("2".to_string())
+        );
+
+        let dir = base_path.join("two_posts/creator/free");
+        let text_only: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join("post-10.json")).unwrap()).unwrap();
+        assert_eq!(text_only["text"], "Text only");
+        assert_eq!(text_only["files"], serde_json::json!([]));
+        let with_media: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join("post-11.json")).unwrap()).unwrap();
+        assert_eq!(with_media["files"], serde_json::json!(["2.mp4", "1.jpg"]));
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
 }
@@ -0,0 +1,140 @@
+use {
+    super::{GatherType, Gatherer},
+    crate::Result,
+    futures::future::BoxFuture,
+    std::sync::Arc,
+};
+
+/// Something a gatherer can do, anything missing is never asked of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Capability {
+    Posts,
+    Messages,
+    Bundles,
+    Stories,
+    Purchased,
+    Transactions,
+}
+
+impl From<GatherType> for Capability {
+    fn from(gather_type: GatherType) -> Self {
+        match gather_type {
+            GatherType::Posts => Self::Posts,
+            GatherType::Messages => Self::Messages,
+            GatherType::Bundles => Self::Bundles,
+            GatherType::Stories => Self::Stories,
+            GatherType::Purchased => Self::Purchased,
+        }
+    }
+}
+
+/// Builds a gatherer from its config section, the section is whatever the user has under `config_section`
+pub type GathererFactory = fn(serde_json::Value) -> BoxFuture<'static, Result<Arc<dyn Gatherer>>>;
+
+/// How the app finds a gatherer, each gatherer crate submits one with [`crate::register_gatherer`]
+pub struct GathererRegistration {
+    /// Same as [`Gatherer::name`]
+    pub name: &'static str,
+    /// The config table holding the gatherer's settings
+    pub config_section: &'static str,
+    /// Other names it can be picked with on the command line
+    pub aliases: &'static [&'static str],
+    pub capabilities: &'static [Capability],
+    pub factory: GathererFactory,
+    /// Written to the config file when there is no section for the gatherer yet
+    pub default_config: fn() -> serde_json::Value,
+}
+
+inventory::collect!(GathererRegistration);
+
+impl GathererRegistration {
+    /// Whether `name` is the gatherer's name or one of its aliases, case is ignored
+    pub fn is_named(&self, name: &'_ str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    pub fn build(
+        &self,
+        config: serde_json::Value,
+    ) -> BoxFuture<'static, Result<Arc<dyn Gatherer>>> {
+        (self.factory)(config)
+    }
+}
+
+impl std::fmt::Debug for GathererRegistration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GathererRegistration")
+            .field("name", &self.name)
+            .field("config_section", &self.config_section)
+            .field("aliases", &self.aliases)
+            .field("capabilities", &self.capabilities)
+            .finish()
+    }
+}
+
+/// Every gatherer linked into the app
+pub fn registered_gatherers() -> impl Iterator<Item = &'static GathererRegistration> {
+    inventory::iter::<GathererRegistration>.into_iter()
+}
+
+/// Look a gatherer up by its name or one of its aliases
+pub fn find_gatherer(name: &'_ str) -> Option<&'static GathererRegistration> {
+    registered_gatherers().find(|registration| registration.is_named(name))
+}
+
+/// Register a gatherer with the app, the crate only has to be linked in for it to show up
+///
+/// The config type is deserialized from the gatherer's config section and handed to `$gatherer::new`.
+///
+/// ```ignore
+/// gatherer_core::register_gatherer! {
+///     name: "fansly",
+///     config_section: "fansly",
+///     aliases: [],
+///     capabilities: [Posts, Messages],
+///     gatherer: Fansly,
+///     config: FanslyConfig,
+/// }
+/// ```
+#[macro_export]
+macro_rules! register_gatherer {
+    (
+        name: $name:literal,
+        config_section: $section:literal,
+        aliases: [$($alias:literal),* $(,)?],
+        capabilities: [$($capability:ident),* $(,)?],
+        gatherer: $gatherer:ty,
+        config: $config:ty $(,)?
+    ) => {
+        $crate::inventory::submit! {
+            $crate::gatherers::GathererRegistration {
+                name: $name,
+                config_section: $section,
+                aliases: &[$($alias),*],
+                capabilities: &[$($crate::gatherers::Capability::$capability),*],
+                factory: |config| {
+                    ::std::boxed::Box::pin(async move {
+                        let config: $config = $crate::serde_json::from_value(config)?;
+                        let gatherer = <$gatherer>::new(config).await?;
+                        let gatherer: ::std::sync::Arc<dyn $crate::gatherers::Gatherer> =
+                            ::std::sync::Arc::new(gatherer);
+                        Ok(gatherer)
+                    })
+                },
+                default_config: || {
+                    $crate::serde_json::to_value(<$config as ::std::default::Default>::default())
+                        .unwrap_or_default()
+                },
+            }
+        }
+    };
+}
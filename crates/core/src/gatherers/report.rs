@@ -0,0 +1,302 @@
+use {
+    crate::{
+        downloaders::DownloaderStats,
+        gatherers::{GatherType, GathererErrors, LimitScope},
+        http::HttpErrors,
+        Result,
+    },
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter},
+        path::Path,
+        time::Duration,
+    },
+};
+
+/// Why a gather stopped early
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum GatherFailure {
+    /// The gatherer doesn't offer this gather type, nothing was requested
+    NotSupported {
+        feature: String,
+    },
+    NotEnabled,
+    InvalidCredentials {
+        msg: String,
+    },
+    /// The source answered with an unexpected status
+    BadStatus {
+        status: u16,
+    },
+    Request(String),
+    Other(String),
+}
+
+impl GatherFailure {
+    /// Unsupported gather types are expected and don't count as a failed run
+    pub fn is_expected(&self) -> bool {
+        matches!(self, Self::NotSupported { .. })
+    }
+}
+
+impl From<&'_ (dyn Error + Send + Sync + 'static)> for GatherFailure {
+    fn from(err: &'_ (dyn Error + Send + Sync + 'static)) -> Self {
+        if let Some(gatherer_err) = err.downcast_ref::<GathererErrors>() {
+            return match gatherer_err {
+                GathererErrors::NotSupportedByGatherer { feature, .. } => Self::NotSupported {
+                    feature: feature.clone(),
+                },
+                GathererErrors::NotEnabled { .. } => Self::NotEnabled,
+                GathererErrors::InvalidCredentials { msg, .. } => {
+                    Self::InvalidCredentials { msg: msg.clone() }
+                }
+                GathererErrors::HttpError { status, .. } => Self::BadStatus {
+                    status: *status as u16,
+                },
+                GathererErrors::HttpClientError(http_err) => Self::from_http(http_err),
+                other => Self::Other(other.to_string()),
+            };
+        }
+        match err.downcast_ref::<HttpErrors>() {
+            Some(http_err) => Self::from_http(http_err),
+            None => Self::Other(err.to_string()),
+        }
+    }
+}
+
+impl GatherFailure {
+    fn from_http(http_err: &'_ HttpErrors) -> Self {
+        match http_err {
+            HttpErrors::BadStatus { status_code, .. } => Self::BadStatus {
+                status: *status_code as u16,
+            },
+            HttpErrors::InternalHttpClientError(client_err) => {
+                Self::Request(client_err.to_string())
+            }
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for GatherFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotSupported { feature } => write!(f, "{} are not supported", feature),
+            Self::NotEnabled => write!(f, "the gatherer is not enabled"),
+            Self::InvalidCredentials { msg } => write!(f, "invalid credentials. {}", msg),
+            Self::BadStatus { status } => write!(f, "unexpected status {}", status),
+            Self::Request(msg) => write!(f, "request failed. {}", msg),
+            Self::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Items that were found but not queued for download
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SkippedItems {
+    /// Already in the download state
+    pub previously_saved: usize,
+    /// The download queue was closed
+    pub failed_to_queue: usize,
+    /// Didn't pass the content filter or was outside the date range
+    pub filtered: usize,
+}
+
+impl SkippedItems {
+    pub fn total(&self) -> usize {
+        self.previously_saved + self.failed_to_queue + self.filtered
+    }
+
+    fn add(&mut self, other: &'_ Self) {
+        self.previously_saved += other.previously_saved;
+        self.failed_to_queue += other.failed_to_queue;
+        self.filtered += other.filtered;
+    }
+}
+
+/// The outcome of one gather type for one subscription, see [`super::run_gatherer`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GatherReport {
+    pub gatherer: String,
+    /// `None` for purchased content which isn't tied to a subscription
+    pub user_name: Option<String>,
+    pub gather_type: GatherType,
+    pub started_at: DateTime<Utc>,
+    #[serde(with = "duration_secs")]
+    pub duration: Duration,
+    /// Every item the gatherer handed over
+    pub found: usize,
+    /// Items sent to the downloader
+    pub queued: usize,
+    pub skipped: SkippedItems,
+    /// Set when a media limit stopped the gather before it reached the end
+    pub limited: Option<LimitScope>,
+    /// Set when the gather stopped early, anything queued before that is still downloaded
+    pub failure: Option<GatherFailure>,
+}
+
+impl GatherReport {
+    pub fn new(gatherer: &'_ str, user_name: Option<&'_ str>, gather_type: GatherType) -> Self {
+        Self {
+            gatherer: gatherer.to_string(),
+            user_name: user_name.map(String::from),
+            gather_type,
+            started_at: Utc::now(),
+            duration: Duration::default(),
+            found: 0,
+            queued: 0,
+            skipped: SkippedItems::default(),
+            limited: None,
+            failure: None,
+        }
+    }
+
+    /// Stop the clock, `failure` is kept when the gather ended with an error
+    pub fn finish(mut self, failure: Option<GatherFailure>) -> Self {
+        self.duration = (Utc::now() - self.started_at).to_std().unwrap_or_default();
+        self.failure = failure;
+        self
+    }
+
+    /// Failed for a reason other than the gather type not being supported
+    pub fn is_failed(&self) -> bool {
+        matches!(&self.failure, Some(failure) if !failure.is_expected())
+    }
+}
+
+impl Display for GatherReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} for {}: found {}, queued {}, skipped {} in {:.2}s",
+            self.gatherer,
+            self.gather_type,
+            self.user_name.as_deref().unwrap_or("purchased"),
+            self.found,
+            self.queued,
+            self.skipped.total(),
+            self.duration.as_secs_f64()
+        )?;
+        if let Some(scope) = self.limited {
+            write!(f, ", reached the {} media limit", scope)?;
+        }
+        if let Some(failure) = &self.failure {
+            write!(f, ", stopped: {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every [`GatherReport`] of a run along with how the downloads went
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunSummary {
+    pub reports: Vec<GatherReport>,
+    /// Gatherers that couldn't get as far as their subscriptions
+    pub gatherer_failures: Vec<(String, GatherFailure)>,
+    pub downloads: Option<DownloaderStats>,
+}
+
+impl RunSummary {
+    pub fn add(&mut self, report: GatherReport) {
+        self.reports.push(report);
+    }
+
+    pub fn add_gatherer_failure(&mut self, gatherer: &'_ str, failure: GatherFailure) {
+        self.gatherer_failures.push((gatherer.to_string(), failure));
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.reports.extend(other.reports);
+        self.gatherer_failures.extend(other.gatherer_failures);
+        if other.downloads.is_some() {
+            self.downloads = other.downloads;
+        }
+    }
+
+    pub fn found(&self) -> usize {
+        self.reports.iter().map(|report| report.found).sum()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.reports.iter().map(|report| report.queued).sum()
+    }
+
+    pub fn skipped(&self) -> SkippedItems {
+        let mut skipped = SkippedItems::default();
+        for report in &self.reports {
+            skipped.add(&report.skipped);
+        }
+        skipped
+    }
+
+    /// Gathers a media limit stopped before they reached the end
+    pub fn limited(&self) -> usize {
+        self.reports
+            .iter()
+            .filter(|report| report.limited.is_some())
+            .count()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &'_ GatherReport> {
+        self.reports.iter().filter(|report| report.is_failed())
+    }
+
+    pub fn save(&self, path: &'_ Path) -> Result<()> {
+        crate::state::save_json(path, self)
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let skipped = self.skipped();
+        writeln!(
+            f,
+            "Gathered {} items from {} gathers: {} queued, {} previously saved, {} filtered, {} not queued",
+            self.found(),
+            self.reports.len(),
+            self.queued(),
+            skipped.previously_saved,
+            skipped.filtered,
+            skipped.failed_to_queue
+        )?;
+        if self.limited() > 0 {
+            writeln!(f, "{} gathers stopped at a media limit", self.limited())?;
+        }
+        if let Some(downloads) = &self.downloads {
+            writeln!(
+                f,
+                "Downloaded {} of {} items, {} failed, {} previously saved",
+                downloads.success, downloads.total, downloads.failed, downloads.previously_saved
+            )?;
+        }
+        for (gatherer, failure) in &self.gatherer_failures {
+            writeln!(f, "  {} failed: {}", gatherer, failure)?;
+        }
+        for report in self.failed() {
+            writeln!(f, "  {}", report)?;
+        }
+        Ok(())
+    }
+}
+
+mod duration_secs {
+    use {
+        serde::{Deserialize, Deserializer, Serializer},
+        std::time::Duration,
+    };
+
+    pub fn serialize<S: Serializer>(
+        duration: &'_ Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Ok(Duration::try_from_secs_f64(secs).unwrap_or_default())
+    }
+}
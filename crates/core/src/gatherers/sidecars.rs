@@ -0,0 +1,128 @@
+use {
+    crate::{
+        gatherers::{Media, MediaOrigin, MediaOriginKind},
+        Result,
+    },
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    },
+};
+
+/// What is kept of a post or message once its media has been reduced to files
+#[derive(Debug, Clone, Serialize)]
+pub struct Sidecar {
+    pub id: String,
+    pub kind: String,
+    pub user_name: String,
+    pub text: Option<String>,
+    pub posted_at: Option<DateTime<Utc>>,
+    pub price: Option<f64>,
+    /// Any of the media was paid for
+    pub paid: bool,
+    pub opened: Option<bool>,
+    /// File names of the media, in the order the source listed them
+    pub files: Vec<String>,
+}
+
+impl Sidecar {
+    pub fn new(origin: &'_ MediaOrigin, user_name: &'_ str) -> Self {
+        Self {
+            id: origin.id.clone(),
+            kind: origin.kind.to_string(),
+            user_name: user_name.to_string(),
+            text: origin.text.clone(),
+            posted_at: origin.posted_at,
+            price: origin.price,
+            paid: false,
+            opened: origin.opened,
+            files: Vec::new(),
+        }
+    }
+
+    /// `<kind>-<id>.json`, posts and messages have their own ids so both are part of the name
+    pub fn file_name(&self) -> String {
+        format!("{}-{}.json", self.kind, self.id)
+    }
+}
+
+/// Groups gathered media by the post or message it came from and writes one sidecar for each
+///
+/// A sidecar goes in the directory of the first media file of its post, media from anywhere other than
+/// a post or message is ignored. Text only posts and messages are added with [`SidecarWriter::add_text_only`].
+#[derive(Debug, Default)]
+pub struct SidecarWriter {
+    sidecars: Vec<(PathBuf, Sidecar)>,
+    index: HashMap<(MediaOriginKind, String), usize>,
+}
+
+impl SidecarWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, media: &'_ Media, file_path: &'_ Path) {
+        let origin = match &media.origin {
+            Some(origin)
+                if matches!(
+                    origin.kind,
+                    MediaOriginKind::Post | MediaOriginKind::Message
+                ) =>
+            {
+                origin
+            }
+            _ => return,
+        };
+        let key = (origin.kind, origin.id.clone());
+        let position = match self.index.get(&key) {
+            Some(position) => *position,
+            None => {
+                let dir = file_path
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                self.sidecars
+                    .push((dir, Sidecar::new(origin, &media.user_name)));
+                self.index.insert(key, self.sidecars.len() - 1);
+                self.sidecars.len() - 1
+            }
+        };
+        let (_, sidecar) = &mut self.sidecars[position];
+        sidecar.paid |= media.paid;
+        if let Some(file_name) = file_path.file_name() {
+            sidecar.files.push(file_name.to_string_lossy().to_string());
+        }
+    }
+
+    /// A post or message without any media, written to `dir`
+    pub fn add_text_only(&mut self, origin: &'_ MediaOrigin, user_name: &'_ str, dir: PathBuf) {
+        let key = (origin.kind, origin.id.clone());
+        if self.index.contains_key(&key) {
+            return;
+        }
+        self.sidecars.push((dir, Sidecar::new(origin, user_name)));
+        self.index.insert(key, self.sidecars.len() - 1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sidecars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sidecars.is_empty()
+    }
+
+    /// Write every sidecar, an existing one is replaced so it picks up changes like a post being opened
+    pub async fn write_all(self) -> Result<usize> {
+        let mut written = 0;
+        for (dir, sidecar) in self.sidecars {
+            async_fs::create_dir_all(&dir).await?;
+            let contents = serde_json::to_vec_pretty(&sidecar)?;
+            async_fs::write(dir.join(sidecar.file_name()), contents).await?;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
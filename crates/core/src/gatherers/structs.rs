@@ -1,7 +1,10 @@
 use {
     crate::{
-        downloaders::Downloadable,
-        gatherers::{GatherType, Gatherer},
+        downloaders::{Downloadable, PriorityRules},
+        gatherers::{
+            ContentFilter, DateRange, GatherType, Gatherer, MediaBudget, MediaKind, PathTemplates,
+        },
+        state::{DownloadState, WatermarkCursor, Watermarks},
     },
     async_channel::Sender,
     chrono::Utc,
@@ -12,19 +15,45 @@ use {
     },
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Default)]
 pub struct RunLimits {
-    pub media: Option<usize>,
+    /// Shared by every gatherer of the run so the per run limit holds across all of them
+    pub media: Arc<MediaBudget>,
     pub subscriptions: Option<usize>,
 }
 
-pub struct GathererInfo {
+/// Options shared by every gatherer started from [`super::run_gatherer_for_all`]
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// The download directory, output paths are rendered from the templates relative to this
     pub base_path: PathBuf,
+    pub limits: RunLimits,
+    pub user_names: Vec<String>,
+    pub ignored_user_names: Vec<String>,
+    pub state: Option<Arc<DownloadState>>,
+    pub templates: Arc<PathTemplates>,
+    pub priorities: Arc<PriorityRules>,
+    /// Where each gather type stopped last time, `None` always walks the full history
+    pub watermarks: Option<Arc<Watermarks>>,
+    /// Walk the full history even with watermarks, they are still moved forward afterwards
+    pub full_scan: bool,
+    /// Write a JSON sidecar next to the media of every post and message
+    pub sidecars: bool,
+    /// Only media passing the filter is queued, a gather that filters anything out leaves its watermark alone
+    pub content_filter: Arc<ContentFilter>,
+    /// Gathers skip anything outside the range and the watermarks are left alone
+    pub date_range: DateRange,
+}
+
+/// One gather type for one subscription, run by [`super::run_gatherer`]
+pub struct GathererInfo {
     pub gather_type: GatherType,
     pub gatherer: Arc<dyn Gatherer>,
     pub subscription: Subscription,
     pub downloader: Sender<Downloadable>,
     pub name: String,
+    /// The options of the whole run, `limits.media` stops the gather once a limit is reached
+    pub options: RunOptions,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -32,10 +61,26 @@ pub struct Post {
     pub id: String,
     pub title: String,
     pub content: String,
+    pub posted_at: Option<chrono::DateTime<Utc>>,
+    /// What the creator asks to unlock it, `None` when it is free
+    pub price: Option<f64>,
+    /// Whether the authed user has unlocked it, `None` when the source doesn't say
+    pub opened: Option<bool>,
+    /// Empty for text only posts
     pub media: Vec<Media>,
     pub paid: bool,
 }
 
+impl Post {
+    /// The post as the origin of its media
+    pub fn origin(&self) -> MediaOrigin {
+        MediaOrigin::new(MediaOriginKind::Post, &self.id, self.posted_at)
+            .with_text(Some(self.content.clone()))
+            .with_price(self.price)
+            .with_opened(self.opened)
+    }
+}
+
 impl Display for Post {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Post(id={}; title={:?})", self.id, self.title)
@@ -44,12 +89,29 @@ impl Display for Post {
 
 #[derive(Debug, Clone, Default)]
 pub struct Message {
+    pub id: String,
     pub from: SubscriptionName,
     pub to: SubscriptionName,
     pub message: String,
+    pub sent_at: Option<chrono::DateTime<Utc>>,
+    /// What the sender asks to unlock it, `None` when it is free
+    pub price: Option<f64>,
+    /// Whether the authed user has unlocked it, `None` when the source doesn't say
+    pub opened: Option<bool>,
+    /// Empty for text only messages
     pub attached_media: Vec<Media>,
 }
 
+impl Message {
+    /// The message as the origin of its media
+    pub fn origin(&self) -> MediaOrigin {
+        MediaOrigin::new(MediaOriginKind::Message, &self.id, self.sent_at)
+            .with_text(Some(self.message.clone()))
+            .with_price(self.price)
+            .with_opened(self.opened)
+    }
+}
+
 //  This may need to get abstracted out into a multiple types of subs
 #[derive(Debug, Clone, Default)]
 pub struct Subscription {
@@ -63,6 +125,10 @@ pub struct Subscription {
     pub video_count: i32,
     pub image_count: i32,
     pub bundle_count: i32,
+    /// How far the gather type being run got last time, set by [`super::run_gatherer`]
+    pub watermark: WatermarkCursor,
+    /// Only content from inside the range is wanted, set by [`super::run_gatherer`]
+    pub date_range: DateRange,
 }
 
 impl Display for Subscription {
@@ -110,11 +176,106 @@ impl Display for SubscriptionName {
 
 #[derive(Debug, Clone, Default)]
 pub struct Media {
+    pub id: String,
     pub file_name: String,
+    pub created_at: Option<chrono::DateTime<Utc>>,
+    /// When the source will stop serving this media, if it ever does
+    pub expires_at: Option<chrono::DateTime<Utc>>,
     pub paid: bool,
     pub mime_type: String,
+    pub kind: MediaKind,
     pub url: String,
     pub user_name: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Play time of videos and audio
+    pub duration: Option<std::time::Duration>,
+    /// Size in bytes as reported by the source, the downloaded file may differ
+    pub size: Option<u64>,
+    /// The post, message or story the media was attached to
+    pub origin: Option<MediaOrigin>,
+    /// When the authed user bought it, only set for purchased content when the source says
+    pub purchased_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl Media {
+    /// The time a [`super::DateRange`] is checked against
+    ///
+    /// When it was bought, otherwise when the post or message it came from went up, otherwise when it was uploaded.
+    pub fn dated_at(&self) -> Option<chrono::DateTime<Utc>> {
+        self.purchased_at
+            .or_else(|| self.origin.as_ref().and_then(|origin| origin.posted_at))
+            .or(self.created_at)
+    }
+}
+
+/// Where a [`Media`] was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaOrigin {
+    pub kind: MediaOriginKind,
+    pub id: String,
+    /// When the post went up or the message was sent
+    pub posted_at: Option<chrono::DateTime<Utc>>,
+    pub text: Option<String>,
+    /// What the creator asks to unlock it, `None` when it is free
+    pub price: Option<f64>,
+    /// Whether the authed user has unlocked it, `None` when the source doesn't say
+    pub opened: Option<bool>,
+}
+
+impl MediaOrigin {
+    pub fn new(
+        kind: MediaOriginKind,
+        id: impl ToString,
+        posted_at: Option<chrono::DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            kind,
+            id: id.to_string(),
+            posted_at,
+            text: None,
+            price: None,
+            opened: None,
+        }
+    }
+
+    pub fn with_text(mut self, text: Option<String>) -> Self {
+        self.text = text.filter(|text| !text.is_empty());
+        self
+    }
+
+    pub fn with_price(mut self, price: Option<f64>) -> Self {
+        self.price = price.filter(|price| *price > 0.);
+        self
+    }
+
+    pub fn with_opened(mut self, opened: Option<bool>) -> Self {
+        self.opened = opened;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaOriginKind {
+    Post,
+    Message,
+    Story,
+    Bundle,
+    /// Avatars and banners
+    Profile,
+}
+
+impl Display for MediaOriginKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::Post => "post",
+            Self::Message => "message",
+            Self::Story => "story",
+            Self::Bundle => "bundle",
+            Self::Profile => "profile",
+        };
+        write!(f, "{}", kind)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -0,0 +1,316 @@
+use {
+    super::{GatherType, Media, Subscription},
+    crate::Result,
+    chrono::format::{Item, StrftimeItems},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        path::{Component, Path, PathBuf},
+    },
+};
+
+/// Matches the layout used before templates were configurable, `fansly/some_user/paid/file.jpg`
+pub const DEFAULT_PATH_TEMPLATE: &str = "{gatherer}/{username}/{paid}/{file_name}";
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Output path templates as they are written in the config
+///
+/// Templates are relative to the download directory and can use these placeholders:
+/// `{gatherer}`, `{username}`, `{display_name}`, `{gather_type}`, `{paid}` (`paid` or `free`),
+/// `{date}` or `{date:<chrono format>}`, `{media_id}`, `{file_name}`, `{file_stem}` and `{ext}`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PathTemplateConfig {
+    pub default: String,
+    /// Templates for specific creators, keyed by user name
+    pub creators: HashMap<String, String>,
+}
+
+impl Default for PathTemplateConfig {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_PATH_TEMPLATE.to_string(),
+            creators: HashMap::new(),
+        }
+    }
+}
+
+impl PathTemplateConfig {
+    /// Parse every template so a typo is reported before a run starts rather than for each item
+    pub fn build(&self) -> Result<PathTemplates> {
+        let mut creators = HashMap::new();
+        for (user_name, template) in self.creators.iter() {
+            creators.insert(user_name.clone(), PathTemplate::parse(template)?);
+        }
+        Ok(PathTemplates {
+            default: PathTemplate::parse(&self.default)?,
+            creators,
+        })
+    }
+}
+
+/// Parsed [`PathTemplateConfig`], picks the template for a creator and renders it
+#[derive(Debug, Clone)]
+pub struct PathTemplates {
+    default: PathTemplate,
+    creators: HashMap<String, PathTemplate>,
+}
+
+impl Default for PathTemplates {
+    fn default() -> Self {
+        Self {
+            default: PathTemplate::parse(DEFAULT_PATH_TEMPLATE).unwrap(),
+            creators: HashMap::new(),
+        }
+    }
+}
+
+impl PathTemplates {
+    /// Where a media should be saved, relative to the download directory
+    pub fn render(&self, ctx: &'_ TemplateContext<'_>) -> PathBuf {
+        self.creators
+            .get(&ctx.media.user_name)
+            .unwrap_or(&self.default)
+            .render(ctx)
+    }
+}
+
+/// Everything a template can pull values from
+pub struct TemplateContext<'a> {
+    pub gatherer: &'a str,
+    pub gather_type: GatherType,
+    pub subscription: &'a Subscription,
+    pub media: &'a Media,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Gatherer,
+    UserName,
+    DisplayName,
+    GatherType,
+    Paid,
+    Date(String),
+    MediaId,
+    FileName,
+    FileStem,
+    Ext,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &'_ str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => {
+                    return Err(format!("Unclosed placeholder in template {:?}", template).into())
+                }
+            };
+            let placeholder = &rest[start + 1..end];
+            let (name, arg) = match placeholder.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (placeholder, None),
+            };
+            segments.push(match (name, arg) {
+                ("gatherer", None) => Segment::Gatherer,
+                ("username", None) => Segment::UserName,
+                ("display_name", None) => Segment::DisplayName,
+                ("gather_type", None) => Segment::GatherType,
+                ("paid", None) => Segment::Paid,
+                ("date", format) => {
+                    let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+                    // chrono panics while rendering a bad format, catch it here instead
+                    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                        return Err(format!(
+                            "Invalid date format {:?} in template {:?}",
+                            format, template
+                        )
+                        .into());
+                    }
+                    Segment::Date(format.to_string())
+                }
+                ("media_id", None) => Segment::MediaId,
+                ("file_name", None) => Segment::FileName,
+                ("file_stem", None) => Segment::FileStem,
+                ("ext", None) => Segment::Ext,
+                _ => {
+                    return Err(format!(
+                        "Unknown placeholder {{{}}} in template {:?}",
+                        placeholder, template
+                    )
+                    .into())
+                }
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn render(&self, ctx: &'_ TemplateContext<'_>) -> PathBuf {
+        let media = ctx.media;
+        let file_path = Path::new(&media.file_name);
+        let mut rendered = String::new();
+        for segment in self.segments.iter() {
+            let value = match segment {
+                Segment::Literal(literal) => {
+                    rendered.push_str(literal);
+                    continue;
+                }
+                Segment::Gatherer => ctx.gatherer.to_ascii_lowercase(),
+                Segment::UserName => media.user_name.clone(),
+                Segment::DisplayName => ctx
+                    .subscription
+                    .name
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| media.user_name.clone()),
+                Segment::GatherType => ctx.gather_type.to_string().to_ascii_lowercase(),
+                Segment::Paid => if media.paid { "paid" } else { "free" }.to_string(),
+                Segment::Date(format) => match media.created_at {
+                    Some(created_at) => created_at.format(format).to_string(),
+                    None => "undated".to_string(),
+                },
+                Segment::MediaId => media.id.clone(),
+                Segment::FileName => media.file_name.clone(),
+                Segment::FileStem => file_path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                Segment::Ext => file_path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_string())
+                    .or_else(|| media.mime_type.split('/').nth(1).map(String::from))
+                    .unwrap_or_default(),
+            };
+            rendered.push_str(&sanitize(&value));
+        }
+        // Only plain folder names are kept, a template can't climb out of the download directory
+        Path::new(&rendered)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect()
+    }
+}
+
+/// Values come from the source sites, keep them from adding folders or characters most filesystems reject
+fn sanitize(value: &'_ str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{PathTemplate, PathTemplateConfig, TemplateContext},
+        crate::gatherers::{GatherType, Media, Subscription, SubscriptionName},
+        chrono::{TimeZone, Utc},
+        std::path::PathBuf,
+    };
+
+    fn media() -> Media {
+        Media {
+            id: "42".to_string(),
+            file_name: "clip.mp4".to_string(),
+            created_at: Some(Utc.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap()),
+            paid: true,
+            mime_type: "video/mp4".to_string(),
+            user_name: "creator".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn render(template: &'_ str, media: &'_ Media) -> PathBuf {
+        let subscription = Subscription {
+            name: SubscriptionName {
+                username: "creator".to_string(),
+                display_name: Some("The Creator".to_string()),
+            },
+            ..Default::default()
+        };
+        PathTemplate::parse(template)
+            .unwrap()
+            .render(&TemplateContext {
+                gatherer: "OnlyFans",
+                gather_type: GatherType::Posts,
+                subscription: &subscription,
+                media,
+            })
+    }
+
+    #[test]
+    fn every_placeholder_is_rendered() {
+        assert_eq!(
+            render(
+                "{gatherer}/{display_name}/{gather_type}/{paid}/{date:%Y}/{media_id}-{file_stem}.{ext}",
+                &media()
+            ),
+            PathBuf::from("onlyfans/The Creator/posts/paid/2024/42-clip.mp4")
+        );
+        assert_eq!(
+            render("{username}/{date}/{file_name}", &media()),
+            PathBuf::from("creator/2024-03-09/clip.mp4")
+        );
+    }
+
+    #[test]
+    fn missing_values_fall_back() {
+        let media = Media {
+            file_name: "photo".to_string(),
+            mime_type: "image/jpeg".to_string(),
+            created_at: None,
+            paid: false,
+            ..media()
+        };
+        assert_eq!(
+            render("{paid}/{date}/{file_stem}.{ext}", &media),
+            PathBuf::from("free/undated/photo.jpeg")
+        );
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        assert!(PathTemplate::parse("{username").is_err());
+        assert!(PathTemplate::parse("{user}/{file_name}").is_err());
+        assert!(PathTemplate::parse("{date:%Q}").is_err());
+        assert!(PathTemplate::parse("{file_name:x}").is_err());
+        let config = PathTemplateConfig {
+            creators: [("creator".to_string(), "{nope}".to_string())].into(),
+            ..Default::default()
+        };
+        assert!(config.build().is_err());
+    }
+
+    #[test]
+    fn values_cannot_add_folders_or_climb_out() {
+        let media = Media {
+            user_name: "../a/b:c".to_string(),
+            file_name: "x?.jpg".to_string(),
+            ..media()
+        };
+        assert_eq!(
+            render("{username}/{file_name}", &media),
+            PathBuf::from(".._a_b_c/x_.jpg")
+        );
+        assert_eq!(render("/../{file_name}", &media), PathBuf::from("x_.jpg"));
+    }
+}
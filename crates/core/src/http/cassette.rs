@@ -0,0 +1,364 @@
+//! Cassettes
+//!
+//! Recorded request/response pairs so the gatherers can be run without a network. A client in record mode sends
+//! requests as usual and writes each exchange to the cassette file, one in replay mode never touches the network
+//! and answers from the file instead.
+//!
+//! Scrubbing only covers the headers in [`SCRUBBED_HEADERS`] and the JSON keys and query parameters in
+//! [`SCRUBBED_KEYS`] plus the cassette's own `scrub_keys`. Anything else sent or received is written as is, so check a
+//! recording before sharing it.
+
+use {
+    super::HttpErrors,
+    crate::Result,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+    surf::http::{headers::CONTENT_TYPE, Url},
+};
+
+/// Headers that carry credentials, their values never make it into a cassette
+pub const SCRUBBED_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "set-cookie",
+    "sign",
+    "app-token",
+    "x-bc",
+    "user-id",
+];
+
+/// JSON keys and query parameters whose values are replaced in recordings, compared without case
+pub const SCRUBBED_KEYS: &[&str] = &[
+    "password",
+    "token",
+    "access_token",
+    "refresh_token",
+    "authorization",
+    "cookie",
+    "sess",
+    "auth_id",
+    "app_token",
+    "email",
+];
+
+const SCRUBBED: &str = "[scrubbed]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    /// Send requests and save every exchange, an existing cassette is overwritten
+    Record,
+    /// Answer from the cassette, a request that wasn't recorded is an error
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub path: PathBuf,
+    /// More JSON keys and query parameters to scrub on top of [`SCRUBBED_KEYS`]
+    #[serde(default)]
+    pub scrub_keys: Vec<String>,
+}
+
+impl CassetteConfig {
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            mode: CassetteMode::Record,
+            path: path.into(),
+            scrub_keys: Vec::new(),
+        }
+    }
+
+    pub fn replay(path: impl Into<PathBuf>) -> Self {
+        Self {
+            mode: CassetteMode::Replay,
+            path: path.into(),
+            scrub_keys: Vec::new(),
+        }
+    }
+
+    pub fn with_scrub_keys(mut self, keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scrub_keys.extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    fn scrubs_key(&self, key: &'_ str) -> bool {
+        SCRUBBED_KEYS
+            .iter()
+            .copied()
+            .chain(self.scrub_keys.iter().map(String::as_str))
+            .any(|scrubbed| scrubbed.eq_ignore_ascii_case(key))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CassetteFile {
+    pub interactions: Vec<Interaction>,
+}
+
+/// A single request and the response it got
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Bodies are kept as text, the APIs only send JSON
+    #[serde(default)]
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// Recorded URLs are scrubbed, so the request is compared the same way
+    fn matches(&self, req: &'_ surf::Request, config: &'_ CassetteConfig) -> bool {
+        self.method.eq_ignore_ascii_case(req.method().as_ref())
+            && self.url == scrub_url(req.url(), config)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Cassette {
+    config: CassetteConfig,
+    state: Mutex<CassetteState>,
+    /// Held while the file is written so an older copy of the cassette never lands after a newer one
+    writing: futures::lock::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    /// Replay reads the file on the first request, so a missing cassette is reported where it is used
+    loaded: bool,
+    interactions: Vec<Interaction>,
+    /// Which interactions have been replayed, each one answers a single request
+    used: Vec<bool>,
+}
+
+impl Cassette {
+    pub(crate) fn new(config: CassetteConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(CassetteState::default()),
+            writing: futures::lock::Mutex::new(()),
+        }
+    }
+
+    pub(crate) fn is_replaying(&self) -> bool {
+        self.config.mode == CassetteMode::Replay
+    }
+
+    /// The first recorded response for the same method and URL that hasn't been handed out yet
+    pub(crate) fn replay(&self, req: &'_ surf::Request) -> Result<surf::Response> {
+        let mut state = self.state.lock().unwrap();
+        if !state.loaded {
+            state.interactions = read_cassette(&self.config.path)?.interactions;
+            state.used = vec![false; state.interactions.len()];
+            state.loaded = true;
+        }
+        let state = &mut *state;
+        let found = state
+            .interactions
+            .iter()
+            .zip(state.used.iter_mut())
+            .find(|(interaction, used)| !**used && interaction.request.matches(req, &self.config));
+        match found {
+            Some((interaction, used)) => {
+                *used = true;
+                log::debug!("Replaying {} {}", req.method(), req.url());
+                Ok(interaction.response.to_surf()?)
+            }
+            None => Err(Box::new(HttpErrors::NoRecordedResponse {
+                method: req.method().to_string(),
+                url: req.url().to_string(),
+            })),
+        }
+    }
+
+    /// Save the exchange and hand back a response with the same body, the original body is used up by reading it
+    ///
+    /// The whole cassette is written out after every request so it is complete even when the run is cut short.
+    pub(crate) async fn record(
+        &self,
+        req: &'_ surf::Request,
+        req_body: &'_ [u8],
+        mut resp: surf::Response,
+    ) -> Result<surf::Response> {
+        let resp_body = resp.body_bytes().await?;
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: req.method().to_string(),
+                url: scrub_url(req.url(), &self.config),
+                headers: scrub_headers(
+                    req.iter()
+                        .map(|(name, values)| (name.as_str(), values.as_str())),
+                ),
+                body: (!req_body.is_empty()).then(|| scrub_body(req_body, &self.config)),
+            },
+            response: RecordedResponse {
+                status: resp.status() as u16,
+                headers: scrub_headers(
+                    resp.iter()
+                        .map(|(name, values)| (name.as_str(), values.as_str())),
+                ),
+                body: scrub_body(&resp_body, &self.config),
+            },
+        };
+        let content_type = resp
+            .header(CONTENT_TYPE)
+            .map(|values| values.as_str().to_string());
+        resp.set_body(resp_body);
+        if let Some(content_type) = content_type {
+            resp.insert_header(CONTENT_TYPE, content_type.as_str());
+        }
+
+        let _writing = self.writing.lock().await;
+        let cassette = {
+            let mut state = self.state.lock().unwrap();
+            state.interactions.push(interaction);
+            CassetteFile {
+                interactions: state.interactions.clone(),
+            }
+        };
+        write_cassette(&self.config.path, &cassette).await?;
+        Ok(resp)
+    }
+}
+
+impl RecordedResponse {
+    fn to_surf(&self) -> Result<surf::Response> {
+        let status = surf::StatusCode::try_from(self.status).map_err(|_| {
+            format!(
+                "The cassette has a response with an invalid status {}",
+                self.status
+            )
+        })?;
+        let mut resp = surf::http::Response::new(status);
+        resp.set_body(self.body.as_str());
+        // After the body, which sets its own content type. The body is already in memory so the framing headers
+        // of the original response don't apply
+        for (name, value) in self
+            .headers
+            .iter()
+            .filter(|(name, _)| !matches!(name.as_str(), "content-length" | "transfer-encoding"))
+        {
+            resp.insert_header(name.as_str(), value.as_str());
+        }
+        Ok(resp.into())
+    }
+}
+
+fn scrub_headers<'a>(
+    headers: impl Iterator<Item = (&'a str, &'a str)>,
+) -> BTreeMap<String, String> {
+    headers
+        .map(|(name, value)| {
+            let name = name.to_lowercase();
+            let value = if SCRUBBED_HEADERS.contains(&name.as_str()) {
+                SCRUBBED.to_string()
+            } else {
+                value.to_string()
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+/// The URL with the values of scrubbed query parameters replaced
+fn scrub_url(url: &'_ Url, config: &'_ CassetteConfig) -> String {
+    if !url.query_pairs().any(|(key, _)| config.scrubs_key(&key)) {
+        return url.to_string();
+    }
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| {
+            let value = if config.scrubs_key(&key) {
+                SCRUBBED.to_string()
+            } else {
+                value.into_owned()
+            };
+            (key.into_owned(), value)
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url.to_string()
+}
+
+/// The body as text, with the values of scrubbed keys replaced anywhere in it when it is JSON
+fn scrub_body(body: &'_ [u8], config: &'_ CassetteConfig) -> String {
+    if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(body) {
+        if scrub_json(&mut json, config) {
+            return json.to_string();
+        }
+    }
+    String::from_utf8_lossy(body).into_owned()
+}
+
+/// Returns whether anything was replaced, an untouched body is kept exactly as it was sent
+fn scrub_json(json: &'_ mut serde_json::Value, config: &'_ CassetteConfig) -> bool {
+    match json {
+        serde_json::Value::Object(map) => {
+            let mut scrubbed = false;
+            for (key, value) in map.iter_mut() {
+                if config.scrubs_key(key) && !value.is_null() {
+                    *value = serde_json::Value::String(SCRUBBED.to_string());
+                    scrubbed = true;
+                } else {
+                    scrubbed |= scrub_json(value, config);
+                }
+            }
+            scrubbed
+        }
+        serde_json::Value::Array(values) => {
+            let mut scrubbed = false;
+            for value in values {
+                scrubbed |= scrub_json(value, config);
+            }
+            scrubbed
+        }
+        _ => false,
+    }
+}
+
+pub fn read_cassette(path: &'_ Path) -> Result<CassetteFile> {
+    let contents = std::fs::read(path).map_err(|err| HttpErrors::CassetteError {
+        path: path.to_path_buf(),
+        msg: err.to_string(),
+    })?;
+    Ok(
+        serde_json::from_slice(&contents).map_err(|err| HttpErrors::CassetteError {
+            path: path.to_path_buf(),
+            msg: err.to_string(),
+        })?,
+    )
+}
+
+async fn write_cassette(path: &'_ Path, cassette: &'_ CassetteFile) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        async_fs::create_dir_all(parent).await?;
+    }
+    async_fs::write(path, serde_json::to_vec_pretty(cassette)?).await?;
+    Ok(())
+}
@@ -1,5 +1,25 @@
-use {crate::Result, std::collections::HashMap};
+use {
+    crate::{directories::Directories, state, Result},
+    chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc},
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{collections::HashMap, net::IpAddr, path::PathBuf, sync::Mutex},
+    url::Url,
+};
 
+/// Directory in the state dir holding one cookie jar per gatherer
+const COOKIE_DIR: &str = "cookies";
+
+/// `Expires` formats seen in the wild, the first is the one RFC 6265 asks servers to send
+const EXPIRES_FORMATS: &[&str] = &[
+    "%a, %d %b %Y %H:%M:%S GMT",
+    "%a, %d-%b-%Y %H:%M:%S GMT",
+    "%a, %d-%b-%y %H:%M:%S GMT",
+    "%A, %d-%b-%y %H:%M:%S GMT",
+    "%a %b %e %H:%M:%S %Y",
+];
+
+/// The `name=value` pairs of a `Cookie` header, such as the one copied out of a browser into the config
 #[derive(Default, Debug)]
 pub struct Cookie {
     crumbs: HashMap<String, String>,
@@ -8,14 +28,16 @@ pub struct Cookie {
 impl Cookie {
     pub fn parse(input: &'_ str) -> Result<Cookie> {
         let mut hmap = Cookie::default();
-        let input = String::from(input);
-        for crumb in input.split(';') {
-            let crumb_pieces: Vec<_> = crumb.split('=').collect();
-            let key = crumb_pieces[0].trim().to_string();
-            if crumb_pieces.len() > 1 {
-                hmap.crumbs.insert(key, crumb_pieces[1].trim().into());
-            } else {
-                hmap.crumbs.insert(key, "true".into());
+        for crumb in input.split(';').filter(|crumb| !crumb.trim().is_empty()) {
+            // Only the first `=` separates the name, base64 values end with more of them
+            match crumb.split_once('=') {
+                Some((key, value)) => {
+                    hmap.crumbs
+                        .insert(key.trim().to_string(), value.trim().into());
+                }
+                None => {
+                    hmap.crumbs.insert(crumb.trim().to_string(), "true".into());
+                }
             }
         }
 
@@ -28,7 +50,10 @@ impl Cookie {
         } else {
             "".into()
         }
-        // self.crumbs.get(key).or_else(|| Some("")).unwrap()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'_ String, &'_ String)> {
+        self.crumbs.iter()
     }
 }
 
@@ -41,3 +66,394 @@ impl From<Cookie> for String {
             .join("; ")
     }
 }
+
+/// A cookie held by the [`CookieJar`], with the scope it was set for
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    /// Set without a `Domain` attribute, only sent back to that exact host
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for a session cookie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub http_only: bool,
+    pub created: DateTime<Utc>,
+}
+
+impl StoredCookie {
+    /// Parse a `Set-Cookie` value received from `url`, `None` when the cookie has to be ignored
+    pub fn parse(url: &'_ Url, set_cookie: &'_ str, now: DateTime<Utc>) -> Option<Self> {
+        let host = url.host_str()?.to_lowercase();
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+            created: now,
+        };
+        let mut max_age = None;
+        let mut expires = None;
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_lowercase().as_str() {
+                "max-age" => {
+                    // Not a number means the attribute is ignored, not the cookie
+                    if let Ok(secs) = value.parse::<i64>() {
+                        max_age = Some(secs);
+                    }
+                }
+                "expires" => expires = parse_cookie_date(value).or(expires),
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_lowercase();
+                    if domain.is_empty() {
+                        continue;
+                    }
+                    // A server may only widen a cookie to a domain it is part of
+                    if !domain_matches(&host, &domain) {
+                        log::debug!("Ignoring cookie {} set by {} for {}", name, host, domain);
+                        return None;
+                    }
+                    // and never to a whole top level domain, unless that is the host itself
+                    if is_public_suffix(&domain) {
+                        if domain == host {
+                            continue;
+                        }
+                        log::debug!("Ignoring cookie {} set by {} for {}", name, host, domain);
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires when both are set, anything at or below 0 expires the cookie straight away
+        cookie.expires = match max_age {
+            Some(secs) if secs <= 0 => Some(DateTime::<Utc>::MIN_UTC),
+            // Too far out to represent, it never expires
+            Some(secs) => Some(
+                Duration::try_seconds(secs)
+                    .and_then(|max_age| now.checked_add_signed(max_age))
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            ),
+            None => expires,
+        };
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Whether the cookie is sent with a request to `url`
+    pub fn matches(&self, url: &'_ Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+
+    /// Cookies with the same name, domain and path replace each other
+    fn same_slot(&self, other: &'_ Self) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CookieJarFile {
+    /// Hash of the cookie string the jar was seeded with, a different one means the user logged in again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<String>,
+    cookies: Vec<StoredCookie>,
+}
+
+/// Cookies kept the way a browser keeps them (RFC 6265)
+///
+/// Every `Set-Cookie` the client receives goes into the jar and the matching cookies are sent with each request.
+/// A jar loaded from a file is written back whenever it changes, session cookies included, so a session that was
+/// refreshed during a run is still there for the next one.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    path: Option<PathBuf>,
+    state: Mutex<CookieJarFile>,
+}
+
+impl CookieJar {
+    /// Load the jar kept for `name` in the default state directory
+    pub fn load_default(name: &'_ str) -> Result<Self> {
+        Self::load(
+            Directories::new()
+                .get_default_state_dir()
+                .join(COOKIE_DIR)
+                .join(format!("{}.json", name)),
+        )
+    }
+
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path: PathBuf = path.into();
+        let mut jar_file: CookieJarFile = state::load_json(&path)?;
+        let now = Utc::now();
+        jar_file.cookies.retain(|cookie| !cookie.is_expired(now));
+        log::debug!("Loaded {} cookies from {:?}", jar_file.cookies.len(), path);
+        Ok(Self {
+            path: Some(path),
+            state: Mutex::new(jar_file),
+        })
+    }
+
+    /// Fill the jar from a `Cookie` header string, scoped to the whole domain of `url`
+    ///
+    /// Nothing changes when the jar was already seeded with the same string, the cookies in it are newer than the
+    /// ones in the string. A different string replaces everything in the jar.
+    pub fn seed(&self, url: &'_ Url, cookie: &'_ str) -> Result<()> {
+        let hash = format!("{:x}", Sha256::digest(cookie.as_bytes()));
+        let domain = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host to scope the cookies to", url))?
+            .to_lowercase();
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.seed.as_deref() == Some(hash.as_str()) {
+                log::debug!(
+                    "Cookie jar already seeded, keeping its {} cookies",
+                    state.cookies.len()
+                );
+                return Ok(());
+            }
+            let now = Utc::now();
+            state.cookies = Cookie::parse(cookie)?
+                .iter()
+                .map(|(name, value)| StoredCookie {
+                    name: name.clone(),
+                    value: value.clone(),
+                    domain: domain.clone(),
+                    host_only: false,
+                    path: "/".to_string(),
+                    expires: None,
+                    secure: false,
+                    http_only: false,
+                    created: now,
+                })
+                .collect();
+            state.seed = Some(hash);
+        }
+        self.save()
+    }
+
+    /// Store a `Set-Cookie` received from `url`, returns whether the jar changed
+    pub fn set_cookie(&self, url: &'_ Url, set_cookie: &'_ str) -> bool {
+        let now = Utc::now();
+        let cookie = match StoredCookie::parse(url, set_cookie, now) {
+            Some(cookie) => cookie,
+            None => return false,
+        };
+        let mut state = self.state.lock().unwrap();
+        let existing = state.cookies.iter().position(|old| old.same_slot(&cookie));
+        if cookie.is_expired(now) {
+            log::trace!("Removing cookie {} for {}", cookie.name, cookie.domain);
+            return existing.map(|index| state.cookies.remove(index)).is_some();
+        }
+        log::trace!("Storing cookie {} for {}", cookie.name, cookie.domain);
+        match existing {
+            Some(index) => {
+                let old = &mut state.cookies[index];
+                let changed = old.value != cookie.value || old.expires != cookie.expires;
+                // The creation time of the original is kept, it decides the order cookies are sent in
+                *old = StoredCookie {
+                    created: old.created,
+                    ..cookie
+                };
+                changed
+            }
+            None => {
+                state.cookies.push(cookie);
+                true
+            }
+        }
+    }
+
+    /// Store every `Set-Cookie` of a response and save the jar when any of them changed it
+    pub fn store_response<'a>(&self, url: &'_ Url, set_cookies: impl Iterator<Item = &'a str>) {
+        let mut changed = false;
+        for set_cookie in set_cookies {
+            changed |= self.set_cookie(url, set_cookie);
+        }
+        if changed {
+            if let Err(err) = self.save() {
+                log::warn!("Failed to save the cookie jar. {:?}", err);
+            }
+        }
+    }
+
+    /// The `Cookie` header for a request to `url`, longest paths first, then oldest first
+    pub fn header_for(&self, url: &'_ Url) -> Option<String> {
+        let now = Utc::now();
+        let state = self.state.lock().unwrap();
+        let mut cookies: Vec<_> = state
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(url))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created.cmp(&b.created))
+        });
+        Some(
+            cookies
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// Value of the first live cookie with this name, whatever its scope
+    pub fn get(&self, name: &'_ str) -> Option<String> {
+        let now = Utc::now();
+        self.state
+            .lock()
+            .unwrap()
+            .cookies
+            .iter()
+            .find(|cookie| cookie.name == name && !cookie.is_expired(now))
+            .map(|cookie| cookie.value.clone())
+    }
+
+    pub fn cookies(&self) -> Vec<StoredCookie> {
+        self.state.lock().unwrap().cookies.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist the jar to where it was loaded from, leaving out expired cookies, in-memory jars are a no-op
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let now = Utc::now();
+        let state = self.state.lock().unwrap();
+        let jar_file = CookieJarFile {
+            seed: state.seed.clone(),
+            cookies: state
+                .cookies
+                .iter()
+                .filter(|cookie| !cookie.is_expired(now))
+                .cloned()
+                .collect(),
+        };
+        state::save_json(path, &jar_file)
+    }
+}
+
+/// `host` is `domain` or a subdomain of it, IP addresses only ever match themselves
+fn domain_matches(host: &'_ str, domain: &'_ str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<IpAddr>().is_err())
+}
+
+/// Registries where anyone can get a name below, cookies can't be set for them.
+///
+/// Not the whole Public Suffix List, only the common second level ones. Every single label domain counts as well.
+const PUBLIC_SUFFIXES: &[&str] = &[
+    "ac.uk",
+    "co.uk",
+    "gov.uk",
+    "ltd.uk",
+    "me.uk",
+    "net.uk",
+    "org.uk",
+    "plc.uk",
+    "com.au",
+    "net.au",
+    "org.au",
+    "co.nz",
+    "net.nz",
+    "org.nz",
+    "co.jp",
+    "ne.jp",
+    "or.jp",
+    "co.kr",
+    "com.br",
+    "com.cn",
+    "com.mx",
+    "com.tr",
+    "co.in",
+    "co.za",
+    "github.io",
+    "herokuapp.com",
+    "appspot.com",
+];
+
+fn is_public_suffix(domain: &'_ str) -> bool {
+    !domain.contains('.') || PUBLIC_SUFFIXES.contains(&domain)
+}
+
+/// A request path is inside the cookie path when it is the same or continues it at a `/`
+fn path_matches(request_path: &'_ str, cookie_path: &'_ str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// Path a cookie gets without a `Path` attribute, the directory of the request path
+fn default_path(url: &'_ Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => url.path()[..index].to_string(),
+    }
+}
+
+fn parse_cookie_date(value: &'_ str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+    EXPIRES_FORMATS.iter().find_map(|format| {
+        NaiveDateTime::parse_from_str(value, format)
+            .ok()
+            .map(|date| Utc.from_utc_datetime(&date))
+    })
+}
@@ -5,7 +5,7 @@ pub enum HttpErrors {
     #[error("Status code [{status_code}] is not expected. Response: {resp:?}")]
     BadStatus {
         status_code: surf::StatusCode,
-        resp: super::Response,
+        resp: Box<super::Response>,
     },
     #[error("Internal HTTP client library failed. {0:?}")]
     InternalHttpClientError(surf::Error),
@@ -15,4 +15,11 @@ pub enum HttpErrors {
     InvalidUrl(#[from] url::ParseError),
     #[error("The response body was invalid: {0}")]
     InvalidBody(String),
+    #[error("Nothing was recorded for {method} {url}")]
+    NoRecordedResponse { method: String, url: String },
+    #[error("Cassette {path:?} could not be used. {msg}")]
+    CassetteError {
+        path: std::path::PathBuf,
+        msg: String,
+    },
 }
@@ -11,7 +11,7 @@ macro_rules! http_request {
             U: AsRef<str>,
         {
             let request: surf::Request = self.client.$methd(endpoint).build();
-            Ok(self.execute(headers, Request::from(request)).await?)
+            self.execute(headers, Request::from(request)).await
         }
     };
 }
@@ -37,7 +37,7 @@ macro_rules! http_request_with_body {
             } else {
                 self.client.$methd(endpoint).build()
             };
-            Ok(self.execute(headers, Request::from(req)).await?)
+            self.execute(headers, Request::from(req)).await
         }
     };
 }
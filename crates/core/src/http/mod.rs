@@ -1,22 +1,35 @@
 mod errors;
 #[macro_use]
 mod macros;
+pub mod cassette;
 mod cookies;
+pub mod proxy;
 mod request;
 mod response;
+mod retry;
 
 pub use {
-    self::{cookies::Cookie, errors::HttpErrors, request::*, response::Response},
+    self::{
+        cassette::{CassetteConfig, CassetteMode},
+        cookies::{Cookie, CookieJar, StoredCookie},
+        errors::HttpErrors,
+        proxy::{ProxyConfig, ProxyRoutes},
+        request::*,
+        response::Response,
+        retry::{HttpRetryPolicy, RateLimit},
+    },
     serde_json::json,
 };
 
 use {
-    crate::Result,
+    crate::{gatherers::GathererErrors, rate_limit::TokenBucket, Result},
+    cassette::Cassette,
     serde::{Deserialize, Serialize},
-    std::{collections::HashMap, convert::TryInto, str::FromStr},
+    std::{collections::HashMap, convert::TryInto, str::FromStr, sync::Arc},
     surf::{
-        http::headers::{HeaderValue, COOKIE},
-        Config,
+        http::headers::{COOKIE, RETRY_AFTER, SET_COOKIE},
+        http::Mime,
+        Body, Config, StatusCode,
     },
 };
 
@@ -25,13 +38,34 @@ pub type Headers = HashMap<String, String>;
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct ClientConfig {
+    /// Names the gatherer in credential errors, the host is used when it isn't set
+    #[serde(default)]
+    pub name: Option<String>,
     pub base_url: Option<String>,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub retry: HttpRetryPolicy,
+    /// Record the traffic to a cassette or replay it from one instead of using the network
+    #[serde(default)]
+    pub cassette: Option<CassetteConfig>,
+    /// Send every request through a proxy
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Client {
     client: surf::Client,
-    cookies: Option<Headers>,
+    /// Shared between clones, cookies set on any of them are sent by all of them
+    cookies: Arc<CookieJar>,
+    /// Shared between clones so the whole gatherer stays under the limit
+    limiter: Option<Arc<TokenBucket>>,
+    retry: HttpRetryPolicy,
+    name: Option<String>,
+    /// Non-2xx statuses returned as a response rather than an error
+    accepted_statuses: Vec<u16>,
+    cassette: Option<Arc<Cassette>>,
 }
 
 impl Client {
@@ -41,15 +75,40 @@ impl Client {
             let base = Url::parse(&base).unwrap();
             config = config.set_base_url(base);
         };
+        if let Some(proxy) = &cfg.proxy {
+            config = config.set_http_client(proxy::ProxyClient::new(proxy));
+        }
         let client: surf::Client = config
             .try_into()
             .expect("Failed to create a client from the base config");
+        let limiter = (!cfg.rate_limit.is_unlimited()).then(|| {
+            Arc::new(TokenBucket::with_capacity(
+                cfg.rate_limit.requests_per_sec,
+                cfg.rate_limit.burst as f64,
+            ))
+        });
         Self {
             client,
-            cookies: None,
+            cookies: Arc::new(CookieJar::default()),
+            limiter,
+            retry: cfg.retry,
+            name: cfg.name,
+            accepted_statuses: Vec::new(),
+            cassette: cfg
+                .cassette
+                .map(|cassette| Arc::new(Cassette::new(cassette))),
         }
     }
 
+    /// A copy of the client that hands back responses with these statuses instead of failing on them
+    ///
+    /// Every other status outside of 2xx is still an error. The copy shares the rate limiter with this client.
+    pub fn accepting(&self, statuses: &'_ [u16]) -> Self {
+        let mut client = self.clone();
+        client.accepted_statuses = statuses.to_vec();
+        client
+    }
+
     http_request!(get);
     http_request_with_body!(delete);
     http_request_with_body!(post);
@@ -88,24 +147,267 @@ impl Client {
             )
             .await?;
         }
-        if let Some(cookies) = self.cookies.clone() {
-            let cookies = cookies
-                .into_iter()
-                .map(|(key, value)| format!("{}={}", key, value))
-                .collect::<Vec<_>>();
-            log::debug!("Adding {} items to the {} header", cookies.len(), COOKIE);
-            log::trace!("Cookies: {:?}", &cookies);
-            req.add_header(COOKIE.as_str(), &cookies.join("; "))
-        };
         log::debug!("Making a {} request to {}", req.method(), req.url());
         let headers = req.header_names();
         log::trace!("Headers: {:?}", headers);
-        let req: surf::Request = req.into();
-        let resp = self.client.send(req).await?;
-        Ok(Response::from_surf(resp))
+        let mut req: surf::Request = req.into();
+        let url = req.url().clone();
+        // A cookie header from the caller is sent as is
+        if req.header(COOKIE).is_none() {
+            if let Some(cookies) = self.cookies.header_for(&url) {
+                log::trace!(
+                    "Cookies: {:?}",
+                    cookies
+                        .split("; ")
+                        .map(|cookie| cookie.split('=').next().unwrap_or_default())
+                        .collect::<Vec<_>>()
+                );
+                req.insert_header(COOKIE, cookies);
+            }
+        }
+        // Read up front since it has to be sent again with each attempt
+        let body = req.take_body();
+        let mime = body.mime().clone();
+        let body = body.into_bytes().await?;
+        let resp = match &self.cassette {
+            Some(cassette) if cassette.is_replaying() => cassette.replay(&req)?,
+            Some(cassette) => {
+                let resp = self.send_with_retry(&req, &body, &mime).await?;
+                cassette.record(&req, &body, resp).await?
+            }
+            None => self.send_with_retry(&req, &body, &mime).await?,
+        };
+        if let Some(set_cookies) = resp.header(SET_COOKIE) {
+            self.cookies
+                .store_response(&url, set_cookies.iter().map(|value| value.as_str()));
+        }
+        self.check_status(&url, Response::from_surf(resp)).await
+    }
+
+    /// Turn statuses the caller didn't ask for into errors, 401 and 403 are reported as bad credentials
+    async fn check_status(&self, url: &'_ Url, resp: Response) -> Result<Response> {
+        let status = resp.status();
+        if status.is_success() || self.accepted_statuses.contains(&(status as u16)) {
+            return Ok(resp);
+        }
+        log::debug!("{} returned status {}", url, status);
+        match status {
+            StatusCode::Unauthorized | StatusCode::Forbidden => {
+                let body = resp.as_string().await.unwrap_or_default();
+                let name = match &self.name {
+                    Some(name) => name.clone(),
+                    None => url.host_str().unwrap_or_default().to_string(),
+                };
+                Err(Box::new(GathererErrors::InvalidCredentials {
+                    name,
+                    msg: format!(
+                        "{} answered {} with {}",
+                        url.path(),
+                        status as u16,
+                        summarize_body(&body)
+                    ),
+                }))
+            }
+            _ => Err(Box::new(HttpErrors::BadStatus {
+                status_code: status,
+                resp: Box::new(resp),
+            })),
+        }
+    }
+
+    /// Send the request through the rate limiter, retrying it as the [`HttpRetryPolicy`] allows
+    async fn send_with_retry(
+        &self,
+        req: &'_ surf::Request,
+        body: &'_ [u8],
+        mime: &'_ Mime,
+    ) -> Result<surf::Response> {
+        let max_attempts = self.retry.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            if let Some(limiter) = &self.limiter {
+                limiter.take(1).await;
+            }
+            let mut attempt_req = req.clone();
+            if !body.is_empty() {
+                let mut attempt_body = Body::from_bytes(body.to_vec());
+                attempt_body.set_mime(mime.clone());
+                attempt_req.set_body(attempt_body);
+            }
+            let delay = match self.client.send(attempt_req).await {
+                Ok(resp) => {
+                    let status = resp.status() as u16;
+                    if !self.retry.should_retry(req.method(), status) || attempt >= max_attempts {
+                        return Ok(resp);
+                    }
+                    let retry_after = match status {
+                        429 | 503 => resp.header(RETRY_AFTER).and_then(|value| {
+                            retry::parse_retry_after(value.as_str(), chrono::Utc::now())
+                        }),
+                        _ => None,
+                    };
+                    match retry_after {
+                        Some(wait) if wait.as_secs() > self.retry.max_retry_after_secs => {
+                            log::warn!(
+                                "{} asked to wait {:?} before retrying, giving up on it",
+                                req.url(),
+                                wait
+                            );
+                            return Ok(resp);
+                        }
+                        Some(wait) => wait,
+                        None => self.retry.delay_for(attempt),
+                    }
+                }
+                Err(err) if attempt < max_attempts => {
+                    let delay = self.retry.delay_for(attempt);
+                    log::debug!("Request to {} failed. {}", req.url(), err);
+                    delay
+                }
+                Err(err) => return Err(Box::new(HttpErrors::InternalHttpClientError(err))),
+            };
+            log::warn!(
+                "Attempt {} of {} for {} {} failed, retrying in {:?}",
+                attempt,
+                max_attempts,
+                req.method(),
+                req.url(),
+                delay
+            );
+            async_io::Timer::after(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Keep cookies in `jar` instead of the empty in-memory jar every client starts with
+    pub fn set_cookie_jar(&mut self, jar: Arc<CookieJar>) {
+        self.cookies = jar;
+    }
+
+    pub fn cookie_jar(&self) -> &'_ Arc<CookieJar> {
+        &self.cookies
+    }
+}
+
+/// Error bodies can be whole HTML pages, only the start of one is worth putting in an error
+fn summarize_body(body: &'_ str) -> String {
+    const MAX_CHARS: usize = 300;
+    let body = body.trim();
+    if body.is_empty() {
+        return "an empty body".to_string();
+    }
+    match body.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{summarize_body, Client, ClientConfig, HttpErrors, Response, Url},
+        crate::gatherers::GathererErrors,
+        futures::executor::block_on,
+        surf::StatusCode,
+    };
+
+    fn response(status: StatusCode, body: &'_ str) -> Response {
+        let mut resp = surf::http::Response::new(status);
+        resp.set_body(body);
+        Response::from_surf(resp.into())
+    }
+
+    fn check(client: &'_ Client, status: StatusCode, body: &'_ str) -> crate::Result<Response> {
+        let url = Url::parse("https://api.example.test/v2/posts?limit=10").unwrap();
+        block_on(client.check_status(&url, response(status, body)))
+    }
+
+    fn client(name: Option<&'_ str>) -> Client {
+        Client::new(ClientConfig {
+            name: name.map(String::from),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn success_and_accepted_statuses_are_responses() {
+        let client = client(None);
+        assert_eq!(
+            check(&client, StatusCode::Ok, "").unwrap().status(),
+            StatusCode::Ok
+        );
+        assert_eq!(
+            check(&client, StatusCode::NoContent, "").unwrap().status(),
+            StatusCode::NoContent
+        );
+        let accepting = client.accepting(&[404]);
+        assert_eq!(
+            check(&accepting, StatusCode::NotFound, "")
+                .unwrap()
+                .status(),
+            StatusCode::NotFound
+        );
+        // Only the copy accepts it
+        assert!(check(&client, StatusCode::NotFound, "").is_err());
+    }
+
+    #[test]
+    fn auth_failures_are_invalid_credentials() {
+        for status in [StatusCode::Unauthorized, StatusCode::Forbidden] {
+            let err =
+                check(&client(Some("onlyfans")), status, r#"{"error":"expired"}"#).unwrap_err();
+            match err.downcast_ref::<GathererErrors>() {
+                Some(GathererErrors::InvalidCredentials { name, msg }) => {
+                    assert_eq!(name, "onlyfans");
+                    assert_eq!(
+                        msg,
+                        &format!(
+                            "/v2/posts answered {} with {{\"error\":\"expired\"}}",
+                            status as u16
+                        )
+                    );
+                }
+                _ => panic!("{:?}", err),
+            }
+        }
+        // Without a name the host says who refused
+        let err = check(&client(None), StatusCode::Forbidden, "").unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<GathererErrors>(),
+                Some(GathererErrors::InvalidCredentials { name, msg })
+                    if name == "api.example.test" && msg.ends_with("an empty body")
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn other_statuses_are_bad_status() {
+        for status in [
+            StatusCode::NotFound,
+            StatusCode::TooManyRequests,
+            StatusCode::BadGateway,
+            StatusCode::MovedPermanently,
+        ] {
+            let err = check(&client(None), status, "").unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<HttpErrors>(),
+                    Some(HttpErrors::BadStatus { status_code, .. }) if *status_code == status
+                ),
+                "{:?}",
+                err
+            );
+        }
     }
 
-    pub fn set_cookies(&mut self, cookies: Headers) {
-        self.cookies = Some(cookies);
+    #[test]
+    fn long_bodies_are_cut_short() {
+        assert_eq!(summarize_body("  \n"), "an empty body");
+        assert_eq!(summarize_body(" short "), "short");
+        let long = "é".repeat(400);
+        assert_eq!(summarize_body(&long), format!("{}...", "é".repeat(300)));
     }
 }
@@ -0,0 +1,406 @@
+//! Proxies
+//!
+//! Routes requests through an HTTP proxy (with `CONNECT`) or a SOCKS5 proxy. Surf's own clients can't do either, so
+//! [`ProxyClient`] opens the tunnel itself and speaks HTTP/1.1 over it, with TLS on top for `https` URLs. A new
+//! connection is made for every request.
+
+use {
+    async_net::TcpStream,
+    async_trait::async_trait,
+    futures::{future::Either, AsyncReadExt, AsyncWriteExt},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        convert::TryInto,
+        io,
+        net::{IpAddr, SocketAddr},
+        time::Duration,
+    },
+    surf::{
+        http::{Request, Response, StatusCode, Url},
+        Config, HttpClient,
+    },
+};
+
+/// Longest `CONNECT` response header that is read before giving up on the proxy
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+/// How long opening a tunnel may take when the config doesn't say
+pub const DEFAULT_PROXY_CONNECT_TIMEOUT_SECS: u64 = 30;
+
+/// A proxy to send requests through, the scheme of `url` picks the protocol
+///
+/// `http://host:port` tunnels with `CONNECT`, `socks5://host:port` resolves names locally and `socks5h://host:port`
+/// leaves that to the proxy. Credentials can be given in the URL or with `username` and `password`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// How long connecting to the proxy and opening the tunnel may take, 30 seconds when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+}
+
+impl ProxyConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            username: None,
+            password: None,
+            connect_timeout_secs: None,
+        }
+    }
+
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.username = Some(username.into());
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn with_connect_timeout_secs(mut self, secs: u64) -> Self {
+        self.connect_timeout_secs = Some(secs);
+        self
+    }
+}
+
+/// Which proxy media downloads go through, picked by the gatherer the item came from
+#[derive(Debug, Clone, Default)]
+pub struct ProxyRoutes {
+    /// Used by gatherers without a proxy of their own
+    pub default: Option<ProxyConfig>,
+    /// Keyed on the gatherer name
+    pub gatherers: HashMap<String, ProxyConfig>,
+}
+
+impl ProxyRoutes {
+    pub fn for_gatherer(&self, gatherer: &'_ str) -> Option<&'_ ProxyConfig> {
+        self.gatherers.get(gatherer).or(self.default.as_ref())
+    }
+}
+
+/// A surf client sending everything through the proxy, or surf's own client when there isn't one
+pub fn surf_client(proxy: Option<&'_ ProxyConfig>) -> surf::Client {
+    match proxy {
+        Some(proxy) => Config::new()
+            .set_http_client(ProxyClient::new(proxy))
+            .try_into()
+            .expect("Failed to create a client for the proxy"),
+        None => surf::Client::default(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    HttpConnect,
+    Socks5 { remote_dns: bool },
+}
+
+#[derive(Debug, Clone)]
+struct ParsedProxy {
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    connect_timeout: Duration,
+}
+
+impl ParsedProxy {
+    fn parse(config: &'_ ProxyConfig) -> std::result::Result<Self, String> {
+        let url = Url::parse(&config.url)
+            .map_err(|err| format!("Invalid proxy URL {}. {}", config.url, err))?;
+        let (kind, default_port) = match url.scheme() {
+            "http" => (ProxyKind::HttpConnect, 8080),
+            "socks5" => (ProxyKind::Socks5 { remote_dns: false }, 1080),
+            "socks5h" => (ProxyKind::Socks5 { remote_dns: true }, 1080),
+            scheme => {
+                return Err(format!(
+                    "Proxy scheme {} is not supported, use http, socks5 or socks5h",
+                    scheme
+                ))
+            }
+        };
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("Proxy URL {} has no host", config.url))?
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_string();
+        let username = config
+            .username
+            .clone()
+            .or_else(|| (!url.username().is_empty()).then(|| url.username().to_string()));
+        let password = config
+            .password
+            .clone()
+            .or_else(|| url.password().map(String::from));
+        Ok(Self {
+            kind,
+            host,
+            port: url.port().unwrap_or(default_port),
+            credentials: username.map(|username| (username, password.unwrap_or_default())),
+            connect_timeout: Duration::from_secs(
+                config
+                    .connect_timeout_secs
+                    .unwrap_or(DEFAULT_PROXY_CONNECT_TIMEOUT_SECS),
+            ),
+        })
+    }
+
+    /// A connection to `host:port` through the proxy, ready for the request
+    ///
+    /// Gives up once `connect_timeout` has passed, a proxy that never answers doesn't hold the request forever.
+    async fn connect(&self, host: &'_ str, port: u16) -> io::Result<TcpStream> {
+        let tunnel = Box::pin(self.open_tunnel(host, port));
+        let timeout = Box::pin(async_io::Timer::after(self.connect_timeout));
+        match futures::future::select(tunnel, timeout).await {
+            Either::Left((stream, _)) => stream,
+            Either::Right(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "Proxy error, no tunnel to {}:{} after {:?}",
+                    host, port, self.connect_timeout
+                ),
+            )),
+        }
+    }
+
+    async fn open_tunnel(&self, host: &'_ str, port: u16) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        match self.kind {
+            ProxyKind::HttpConnect => self.http_connect(&mut stream, host, port).await?,
+            ProxyKind::Socks5 { remote_dns } => {
+                self.socks5_connect(&mut stream, host, port, remote_dns)
+                    .await?
+            }
+        }
+        Ok(stream)
+    }
+
+    async fn http_connect(
+        &self,
+        stream: &'_ mut TcpStream,
+        host: &'_ str,
+        port: u16,
+    ) -> io::Result<()> {
+        let authority = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+            _ => format!("{}:{}", host, port),
+        };
+        let mut connect = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some((username, password)) = &self.credentials {
+            let credentials = base64::encode(format!("{}:{}", username, password));
+            connect.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+        }
+        connect.push_str("\r\n");
+        stream.write_all(connect.as_bytes()).await?;
+
+        // Read a byte at a time, anything after the header already belongs to the tunnel
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_CONNECT_RESPONSE {
+                return Err(proxy_error("the proxy sent an oversized CONNECT response"));
+            }
+            stream.read_exact(&mut byte).await?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(proxy_error(&format!(
+                "the proxy refused to connect to {}: {}",
+                authority, status_line
+            ))),
+        }
+    }
+
+    async fn socks5_connect(
+        &self,
+        stream: &'_ mut TcpStream,
+        host: &'_ str,
+        port: u16,
+        remote_dns: bool,
+    ) -> io::Result<()> {
+        // Greeting, offering username/password only when there is one
+        let greeting: &[u8] = match self.credentials {
+            Some(_) => &[5, 2, 0, 2],
+            None => &[5, 1, 0],
+        };
+        stream.write_all(greeting).await?;
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await?;
+        match (choice, &self.credentials) {
+            ([5, 0], _) => {}
+            ([5, 2], Some((username, password))) => {
+                let mut auth = vec![1, socks5_len("username", username)?];
+                auth.extend_from_slice(username.as_bytes());
+                auth.push(socks5_len("password", password)?);
+                auth.extend_from_slice(password.as_bytes());
+                stream.write_all(&auth).await?;
+                let mut status = [0; 2];
+                stream.read_exact(&mut status).await?;
+                if status[1] != 0 {
+                    return Err(proxy_error(
+                        "the SOCKS5 proxy rejected the username or password",
+                    ));
+                }
+            }
+            _ => {
+                return Err(proxy_error(
+                    "the SOCKS5 proxy accepts none of the offered auth methods",
+                ))
+            }
+        }
+
+        let mut request = vec![5, 1, 0];
+        let address = match host.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) if remote_dns => None,
+            Err(_) => Some(
+                async_net::resolve((host, port))
+                    .await?
+                    .first()
+                    .map(SocketAddr::ip)
+                    .ok_or_else(|| proxy_error(&format!("{} did not resolve", host)))?,
+            ),
+        };
+        match address {
+            Some(IpAddr::V4(ip)) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+            None => {
+                request.push(3);
+                request.push(socks5_len("host name", host)?);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(proxy_error(&format!(
+                "the SOCKS5 proxy could not connect to {}:{} ({})",
+                host,
+                port,
+                socks5_reply_message(reply[1])
+            )));
+        }
+        // The address the proxy bound to isn't needed, it only has to be read past
+        let bound_len = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize
+            }
+            _ => return Err(proxy_error("the SOCKS5 proxy sent an unknown address type")),
+        };
+        let mut bound = vec![0; bound_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+}
+
+/// SOCKS5 sends strings with a one byte length
+fn socks5_len(field: &'_ str, value: &'_ str) -> io::Result<u8> {
+    u8::try_from(value.len()).map_err(|_| {
+        proxy_error(&format!(
+            "the {} is {} bytes, SOCKS5 allows at most 255",
+            field,
+            value.len()
+        ))
+    })
+}
+
+fn socks5_reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by the ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn proxy_error(msg: &'_ str) -> io::Error {
+    io::Error::other(format!("Proxy error, {}", msg))
+}
+
+/// An [`HttpClient`] for surf that sends each request through the proxy
+#[derive(Debug, Clone)]
+pub struct ProxyClient {
+    /// A bad proxy config is reported by every request rather than when the client is built
+    proxy: std::result::Result<ParsedProxy, String>,
+}
+
+impl ProxyClient {
+    pub fn new(config: &'_ ProxyConfig) -> Self {
+        Self {
+            proxy: ParsedProxy::parse(config),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for ProxyClient {
+    async fn send(&self, req: Request) -> std::result::Result<Response, surf::Error> {
+        let proxy = self
+            .proxy
+            .as_ref()
+            .map_err(|err| surf::Error::from_str(StatusCode::BadRequest, err.clone()))?;
+        let url = req.url().clone();
+        let host = url
+            .host_str()
+            .ok_or_else(|| {
+                surf::Error::from_str(StatusCode::BadRequest, format!("{} has no host", url))
+            })?
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_string();
+        let port = url.port_or_known_default().ok_or_else(|| {
+            surf::Error::from_str(StatusCode::BadRequest, format!("{} has no port", url))
+        })?;
+        log::trace!(
+            "Connecting to {}:{} through {}:{}",
+            host,
+            port,
+            proxy.host,
+            proxy.port
+        );
+        let stream = proxy
+            .connect(&host, port)
+            .await
+            .map_err(|err| surf::Error::new(StatusCode::BadGateway, err))?;
+        match url.scheme() {
+            "https" => {
+                let stream = async_tls::TlsConnector::default()
+                    .connect(&host, stream)
+                    .await?;
+                async_h1::connect(stream, req).await
+            }
+            "http" => async_h1::connect(stream, req).await,
+            scheme => Err(surf::Error::from_str(
+                StatusCode::BadRequest,
+                format!("Scheme {} can't be sent through a proxy", scheme),
+            )),
+        }
+    }
+}
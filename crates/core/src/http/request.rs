@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 pub use surf::http::{
-    headers::{HeaderName, HeaderValue, Headers},
+    headers::{HeaderName, HeaderValue},
     Method,
 };
 
@@ -27,11 +27,7 @@ impl Request {
         self.0.url().as_str()
     }
     pub fn header_names(&self) -> Vec<&'_ str> {
-        self.0
-            .header_names()
-            .into_iter()
-            .map(|name| name.as_str())
-            .collect()
+        self.0.header_names().map(|name| name.as_str()).collect()
     }
 }
 
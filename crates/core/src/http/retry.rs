@@ -0,0 +1,195 @@
+use {
+    crate::downloaders::backoff,
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::time::Duration,
+    surf::http::Method,
+};
+
+/// How fast a [`super::Client`] sends requests, every clone of the client shares the same bucket
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Requests per second, fractions go below one a second (`0.2` is one every 5 seconds), 0 sends them as fast as
+    /// they come
+    pub requests_per_sec: f64,
+    /// How many requests can go out back to back before the rate kicks in
+    pub burst: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            requests_per_sec: 2.,
+            burst: 4,
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn unlimited() -> Self {
+        Self {
+            requests_per_sec: 0.,
+            burst: 0,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.requests_per_sec <= 0. || self.requests_per_sec.is_nan()
+    }
+}
+
+/// How many times and for which responses an API request is sent again
+///
+/// Requests that fail to get any response at all are retried as well. A `Retry-After` on a 429 or 503 is waited
+/// out instead of the backoff, as long as it is no longer than `max_retry_after_secs`. Only `GET` and `HEAD` are
+/// retried on a status, other methods may have done something the first time so they need `retry_unsafe_methods`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpRetryPolicy {
+    /// Total attempts including the first one, 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each retry after that
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Longest `Retry-After` that is waited out, the response is returned as is when the server asks for more
+    pub max_retry_after_secs: u64,
+    /// Status codes that get another attempt
+    pub statuses: Vec<u16>,
+    /// Retry `POST`, `PUT` and the other methods that aren't `GET` or `HEAD` on `statuses` too
+    pub retry_unsafe_methods: bool,
+}
+
+impl Default for HttpRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_delay_ms: 1_000,
+            max_delay_ms: 30_000,
+            max_retry_after_secs: 120,
+            statuses: vec![429, 500, 502, 503, 504],
+            retry_unsafe_methods: false,
+        }
+    }
+}
+
+impl HttpRetryPolicy {
+    /// A policy that sends every request exactly once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn should_retry(&self, method: Method, status: u16) -> bool {
+        let safe = matches!(method, Method::Get | Method::Head);
+        (safe || self.retry_unsafe_methods) && self.statuses.contains(&status)
+    }
+
+    /// Exponential backoff with jitter, the same curve the downloaders use
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        backoff(self.initial_delay_ms, self.max_delay_ms, attempt)
+    }
+}
+
+/// Parse a `Retry-After` value, either a number of seconds or an HTTP date
+pub(crate) fn parse_retry_after(value: &'_ str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means right away
+    Some(
+        (at.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, chrono::TimeZone};
+
+    #[test]
+    fn status_retries_are_for_get_and_head_unless_opted_in() {
+        let policy = HttpRetryPolicy::default();
+        assert!(policy.should_retry(Method::Get, 503));
+        assert!(policy.should_retry(Method::Head, 429));
+        assert!(!policy.should_retry(Method::Get, 404));
+        assert!(!policy.should_retry(Method::Post, 503));
+        assert!(!policy.should_retry(Method::Put, 500));
+
+        let policy = HttpRetryPolicy {
+            retry_unsafe_methods: true,
+            ..Default::default()
+        };
+        assert!(policy.should_retry(Method::Post, 503));
+        assert!(!policy.should_retry(Method::Post, 400));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let policy = HttpRetryPolicy {
+            initial_delay_ms: 100,
+            max_delay_ms: 1_000,
+            ..Default::default()
+        };
+        for (attempt, max) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1_000),
+            (40, 1_000),
+        ] {
+            let delay = policy.delay_for(attempt);
+            assert!(
+                delay <= Duration::from_millis(max),
+                "{} {:?}",
+                attempt,
+                delay
+            );
+            assert!(
+                delay >= Duration::from_millis(max / 2),
+                "{} {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn retry_after_takes_seconds_or_a_date() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+        assert_eq!(
+            parse_retry_after(" 120 ", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        // Already passed
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("-1", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn fractional_and_zero_rates() {
+        assert!(RateLimit::unlimited().is_unlimited());
+        assert!(!RateLimit {
+            requests_per_sec: 0.2,
+            burst: 0
+        }
+        .is_unlimited());
+        let limit: RateLimit = serde_json::from_str(r#"{"requests_per_sec": 3}"#).unwrap();
+        assert_eq!(limit.requests_per_sec, 3.);
+        assert_eq!(limit.burst, 4);
+    }
+}
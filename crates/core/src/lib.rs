@@ -2,8 +2,14 @@ pub mod directories;
 pub mod downloaders;
 pub mod gatherers;
 pub mod http;
+pub mod rate_limit;
+pub mod state;
 pub mod tasks;
 
+// Used by `register_gatherer!` so gatherer crates don't need their own copies
+#[doc(hidden)]
+pub use {inventory, serde_json};
+
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 pub mod prelude {
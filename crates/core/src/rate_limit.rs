@@ -0,0 +1,117 @@
+//! Rate Limiting
+//!
+//! A token bucket that can be shared between tasks to cap how fast something happens.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Refills at `rate` tokens per second up to `capacity`, callers wait until the tokens they took have been refilled.
+///
+/// Taking more than is available puts the bucket into debt rather than failing, every caller after that waits
+/// for the debt to be paid off so the average rate holds no matter how large each take is.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket that holds one second worth of tokens
+    pub fn new(rate: u64) -> Self {
+        Self::with_capacity(rate as f64, rate as f64)
+    }
+
+    /// `rate` can be below one, e.g. `0.1` refills a token every 10 seconds. The bucket always holds at least one
+    /// token so a take of one never waits on an empty bucket for longer than it needs to.
+    pub fn with_capacity(rate: f64, capacity: f64) -> Self {
+        let rate = if rate > 0. { rate } else { f64::MIN_POSITIVE };
+        let capacity = capacity.max(1.);
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `amount` tokens, waiting if the bucket doesn't have them yet
+    pub async fn take(&self, amount: u64) {
+        let wait = self.reserve(amount);
+        if !wait.is_zero() {
+            async_io::Timer::after(wait).await;
+        }
+    }
+
+    /// Take the tokens now and return how long the caller should wait before using them
+    fn reserve(&self, amount: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.capacity);
+        state.last_refill = now;
+        state.tokens -= amount as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            // A zero rate never refills, waiting forever beats panicking on a wait too long for a `Duration`
+            Duration::try_from_secs_f64(-state.tokens / self.rate).unwrap_or(Duration::MAX)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::TokenBucket, std::time::Duration};
+
+    /// The time spent between two calls refills a little, compare waits with some room for it
+    fn assert_about(wait: Duration, expected_secs: f64) {
+        let secs = wait.as_secs_f64();
+        assert!(
+            secs <= expected_secs && secs > expected_secs - 0.05,
+            "waited {:?}, expected about {}s",
+            wait,
+            expected_secs
+        );
+    }
+
+    #[test]
+    fn a_full_bucket_does_not_wait() {
+        let bucket = TokenBucket::new(100);
+        assert_eq!(bucket.reserve(100), Duration::ZERO);
+        assert_about(bucket.reserve(50), 0.5);
+    }
+
+    #[test]
+    fn large_takes_put_the_bucket_into_debt() {
+        let bucket = TokenBucket::new(100);
+        assert_about(bucket.reserve(300), 2.0);
+        // Everyone after waits for the debt as well
+        assert_about(bucket.reserve(100), 3.0);
+    }
+
+    #[test]
+    fn fractional_rates_refill_slower_than_once_a_second() {
+        let bucket = TokenBucket::with_capacity(0.5, 0.5);
+        // Always holds at least one token
+        assert_eq!(bucket.reserve(1), Duration::ZERO);
+        assert_about(bucket.reserve(1), 2.0);
+    }
+    #[test]
+    fn a_zero_rate_still_allows_the_first_take() {
+        let bucket = TokenBucket::with_capacity(0., 0.);
+        assert_eq!(bucket.reserve(1), Duration::ZERO);
+        assert!(bucket.reserve(1) > Duration::from_secs(60 * 60 * 24 * 365));
+        assert_eq!(TokenBucket::new(0).reserve(1), Duration::ZERO);
+    }
+}
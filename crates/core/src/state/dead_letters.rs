@@ -0,0 +1,143 @@
+use {
+    super::DownloadKey,
+    crate::{
+        directories::Directories,
+        downloaders::{Downloadable, ErrorClass, RetryFailure},
+        Result,
+    },
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::{collections::HashSet, path::PathBuf, sync::Mutex},
+};
+
+const DEAD_LETTER_FILE: &str = "dead_letters.json";
+
+/// An item that was still failing after its last retry
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeadLetter {
+    pub item: Downloadable,
+    pub class: ErrorClass,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    pub fn new(item: Downloadable, failure: &'_ RetryFailure) -> Self {
+        Self {
+            item,
+            class: failure.class,
+            error: failure.err.to_string(),
+            attempts: failure.attempts,
+            failed_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct DeadLetterFile {
+    items: Vec<DeadLetter>,
+}
+
+/// Items that ran out of retries, kept so the next run can try them again
+///
+/// Letters are taken out when they are re-queued, but stay in the file until [`DeadLetters::settle`] says the run
+/// dealt with them, so a run that is cut short still has them next time. New letters are saved in batches as they
+/// come in, only items that failed again are left once the run is settled.
+#[derive(Debug, Default)]
+pub struct DeadLetters {
+    path: Option<PathBuf>,
+    items: Mutex<Vec<DeadLetter>>,
+    /// Taken out to be queued again, not yet settled
+    requeued: Mutex<Vec<DeadLetter>>,
+    batch: super::SaveBatch,
+}
+
+impl DeadLetters {
+    /// Load the dead letters from the default state directory
+    pub fn load_default() -> Result<Self> {
+        Self::load(
+            Directories::new()
+                .get_default_state_dir()
+                .join(DEAD_LETTER_FILE),
+        )
+    }
+
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path: PathBuf = path.into();
+        let letter_file: DeadLetterFile = super::load_json(&path)?;
+        log::debug!(
+            "Loaded {} dead letters from {:?}",
+            letter_file.items.len(),
+            path
+        );
+        Ok(Self {
+            items: Mutex::new(letter_file.items),
+            path: Some(path),
+            requeued: Default::default(),
+            batch: Default::default(),
+        })
+    }
+
+    pub fn push(&self, letter: DeadLetter) {
+        log::debug!(
+            "Adding {} to the dead letters after {} attempts",
+            letter.item,
+            letter.attempts
+        );
+        self.items.lock().unwrap().push(letter);
+        if self.path.is_some() && self.batch.changed() {
+            if let Err(save_err) = self.save() {
+                log::error!("Failed to save the dead letters. {:?}", save_err);
+            }
+        }
+    }
+
+    /// Take every letter out so the items can be queued again
+    pub fn take_all(&self) -> Vec<DeadLetter> {
+        let letters = std::mem::take(&mut *self.items.lock().unwrap());
+        self.requeued
+            .lock()
+            .unwrap()
+            .extend(letters.iter().cloned());
+        letters
+    }
+
+    /// Every re-queued item has either been saved or put back with [`DeadLetters::push`], forget the rest
+    pub fn settle(&self) {
+        self.requeued.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist the letters to where they were loaded from, in-memory letters are a no-op
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.batch.save(|| {
+            let mut items = self.items.lock().unwrap().clone();
+            // A re-queued item that failed again is in `items` already
+            let failed_again: HashSet<DownloadKey> = items
+                .iter()
+                .map(|letter| DownloadKey::from(&letter.item))
+                .collect();
+            items.extend(
+                self.requeued
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|letter| !failed_again.contains(&DownloadKey::from(&letter.item)))
+                    .cloned(),
+            );
+            super::save_json(path, &DeadLetterFile { items })
+        })
+    }
+}
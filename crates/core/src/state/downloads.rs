@@ -0,0 +1,184 @@
+use {
+    crate::{
+        directories::Directories,
+        downloaders::{copy_and_hash, Downloadable},
+        Result,
+    },
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::HashMap,
+        fmt::{Display, Formatter},
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+const DOWNLOAD_STATE_FILE: &str = "downloads.json";
+
+/// Identifies a single piece of media regardless of where it ends up on disk
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct DownloadKey {
+    pub gatherer: String,
+    pub user_name: String,
+    pub media_id: String,
+}
+
+impl Display for DownloadKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.gatherer, self.user_name, self.media_id)
+    }
+}
+
+impl From<&'_ Downloadable> for DownloadKey {
+    fn from(item: &'_ Downloadable) -> Self {
+        Self {
+            gatherer: item.gatherer.clone(),
+            user_name: item.user_name.clone(),
+            media_id: item.media_id.clone(),
+        }
+    }
+}
+
+/// What was written to disk for a [`DownloadKey`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SavedItem {
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: String,
+    pub saved_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct StateFile {
+    items: Vec<StateFileEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StateFileEntry {
+    #[serde(flatten)]
+    key: DownloadKey,
+    #[serde(flatten)]
+    item: SavedItem,
+}
+
+/// Local record of every item that has been saved, shared by the gatherers and downloaders
+///
+/// Gatherers check it before queueing media and downloaders check it again before making a request,
+/// anything already archived by a previous run is skipped without touching the network. Records are saved in
+/// batches while the run goes on, [`DownloadState::save`] writes whatever is left at the end.
+#[derive(Debug, Default)]
+pub struct DownloadState {
+    path: Option<PathBuf>,
+    items: Mutex<HashMap<DownloadKey, SavedItem>>,
+    batch: super::SaveBatch,
+}
+
+impl DownloadState {
+    /// Load the state from the default state directory
+    pub fn load_default() -> Result<Self> {
+        Self::load(
+            Directories::new()
+                .get_default_state_dir()
+                .join(DOWNLOAD_STATE_FILE),
+        )
+    }
+
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path: PathBuf = path.into();
+        let state_file: StateFile = super::load_json(&path)?;
+        log::debug!(
+            "Loaded {} saved items from {:?}",
+            state_file.items.len(),
+            path
+        );
+        Ok(Self {
+            items: Mutex::new(
+                state_file
+                    .items
+                    .into_iter()
+                    .map(|entry| (entry.key, entry.item))
+                    .collect(),
+            ),
+            path: Some(path),
+            batch: Default::default(),
+        })
+    }
+
+    /// Whether the item has been saved before and the file it was saved to is still there
+    pub fn is_saved(&self, key: &'_ DownloadKey) -> bool {
+        match self.items.lock().unwrap().get(key) {
+            Some(saved) => saved.path.exists(),
+            None => false,
+        }
+    }
+
+    pub fn get(&self, key: &'_ DownloadKey) -> Option<SavedItem> {
+        self.items.lock().unwrap().get(key).cloned()
+    }
+
+    /// Record a file that a downloader has just written and verified
+    pub fn record(&self, key: DownloadKey, path: &'_ Path, size: u64, hash: String) -> SavedItem {
+        let saved = SavedItem {
+            path: path.to_path_buf(),
+            size,
+            hash,
+            saved_at: Utc::now(),
+        };
+        log::trace!("Recording {} as {:?}", key, saved);
+        self.items.lock().unwrap().insert(key, saved.clone());
+        if self.path.is_some() && self.batch.changed() {
+            if let Err(save_err) = self.save() {
+                log::error!("Failed to save the download state. {:?}", save_err);
+            }
+        }
+        saved
+    }
+
+    /// Record a file that is already on disk, hashes the file to keep with the record
+    pub async fn record_file(&self, key: DownloadKey, path: &'_ Path) -> Result<SavedItem> {
+        let size = async_fs::metadata(path).await?.len();
+        let hash = hash_file(path).await?;
+        Ok(self.record(key, path, size, hash))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist the state to where it was loaded from, in-memory state is a no-op
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.batch.save(|| {
+            let state_file = StateFile {
+                items: self
+                    .items
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(key, item)| StateFileEntry {
+                        key: key.clone(),
+                        item: item.clone(),
+                    })
+                    .collect(),
+            };
+            super::save_json(path, &state_file)
+        })
+    }
+}
+
+/// Hex encoded SHA-256 of a file
+pub(crate) async fn hash_file(path: &'_ Path) -> Result<String> {
+    let file = async_fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    copy_and_hash(file, &mut futures::io::sink(), &mut hasher, None, None).await?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,96 @@
+//! State
+//!
+//! Records kept on the local machine so a run can skip work that an earlier run already finished.
+
+mod dead_letters;
+mod downloads;
+mod watermarks;
+
+pub use self::{
+    dead_letters::{DeadLetter, DeadLetters},
+    downloads::{DownloadKey, DownloadState, SavedItem},
+    watermarks::{Watermark, WatermarkCursor, WatermarkKey, Watermarks},
+};
+use {
+    crate::Result,
+    serde::{de::DeserializeOwned, Serialize},
+    std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Changes that are held in memory before the state is written out during a run
+const SAVE_AFTER_CHANGES: usize = 25;
+/// Longest a change is held in memory during a run
+const SAVE_AFTER: Duration = Duration::from_secs(15);
+
+/// Read a JSON state file, a file that doesn't exist yet is treated as empty state
+pub(crate) fn load_json<T>(path: &'_ Path) -> Result<T>
+where
+    T: DeserializeOwned + Default,
+{
+    if !path.exists() {
+        log::debug!("No state file at {:?}, starting fresh", path);
+        return Ok(T::default());
+    }
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Write the state to a temp file first and rename it over the original,
+/// a crash mid-write never leaves a half written state file behind
+pub(crate) fn save_json<T>(path: &'_ Path, value: &'_ T) -> Result<()>
+where
+    T: Serialize,
+{
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(value)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    log::debug!("Saved state to {:?}", path);
+    Ok(())
+}
+
+/// Counts the changes made since the state was last saved, so a run writes it out in batches as it goes
+///
+/// A crash or a killed run only loses the last batch instead of everything since the run started.
+#[derive(Debug)]
+pub(crate) struct SaveBatch {
+    changes: AtomicUsize,
+    last_save: Mutex<Instant>,
+    /// One save at a time, each takes its snapshot inside so a newer one is never overwritten by an older one
+    writing: Mutex<()>,
+}
+
+impl Default for SaveBatch {
+    fn default() -> Self {
+        Self {
+            changes: AtomicUsize::new(0),
+            last_save: Mutex::new(Instant::now()),
+            writing: Mutex::new(()),
+        }
+    }
+}
+
+impl SaveBatch {
+    /// Note a change, returns whether enough have built up or enough time has passed that it is time to save
+    pub(crate) fn changed(&self) -> bool {
+        let changes = self.changes.fetch_add(1, Ordering::SeqCst) + 1;
+        changes >= SAVE_AFTER_CHANGES || self.last_save.lock().unwrap().elapsed() >= SAVE_AFTER
+    }
+
+    /// Run `save`, which snapshots the state and writes it, without any other save running at the same time
+    pub(crate) fn save(&self, save: impl FnOnce() -> Result<()>) -> Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        self.changes.store(0, Ordering::SeqCst);
+        *self.last_save.lock().unwrap() = Instant::now();
+        save()
+    }
+}
@@ -0,0 +1,244 @@
+use {
+    crate::{directories::Directories, Result},
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt::{Display, Formatter},
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    },
+};
+
+const WATERMARK_FILE: &str = "watermarks.json";
+
+/// Identifies what a watermark covers, one gather type of one subscription
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct WatermarkKey {
+    pub gatherer: String,
+    pub user_name: String,
+    pub gather_type: String,
+}
+
+impl Display for WatermarkKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.gatherer, self.user_name, self.gather_type
+        )
+    }
+}
+
+/// The newest post or message a run has seen
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Watermark {
+    pub id: Option<String>,
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl Watermark {
+    pub fn new(id: &'_ str, time: Option<DateTime<Utc>>) -> Self {
+        Self {
+            id: Some(id.to_string()),
+            time,
+        }
+    }
+
+    /// Whether an item is at or behind the watermark, meaning an earlier run has already seen it
+    ///
+    /// Times are compared when both sides have one, otherwise ids are compared as numbers since
+    /// both sites hand out ids that only go up.
+    pub fn is_reached_by(&self, id: &'_ str, time: Option<DateTime<Utc>>) -> bool {
+        if let (Some(mark), Some(time)) = (self.time, time) {
+            return time <= mark;
+        }
+        let mark_id = match &self.id {
+            Some(mark_id) => mark_id,
+            None => return false,
+        };
+        match (mark_id.parse::<i64>(), id.parse::<i64>()) {
+            (Ok(mark_id), Ok(id)) => id <= mark_id,
+            _ => mark_id == id,
+        }
+    }
+
+    fn is_newer_than(&self, other: &'_ Self) -> bool {
+        match &self.id {
+            Some(id) => !other.is_reached_by(id, self.time),
+            None => matches!((self.time, other.time), (Some(time), Some(mark)) if time > mark),
+        }
+    }
+}
+
+/// Handed to a gatherer through the [`crate::gatherers::Subscription`] for a single gather type
+///
+/// Gatherers stop paginating once an item [`WatermarkCursor::is_seen`] and [`WatermarkCursor::observe`]
+/// everything they walk past, the newest observed item becomes the watermark for the next run.
+#[derive(Debug, Clone, Default)]
+pub struct WatermarkCursor {
+    previous: Option<Watermark>,
+    newest: Arc<Mutex<Option<Watermark>>>,
+}
+
+impl WatermarkCursor {
+    pub fn new(previous: Option<Watermark>) -> Self {
+        Self {
+            previous,
+            newest: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Whether an earlier run already got as far as this item
+    pub fn is_seen(&self, id: &'_ str, time: Option<DateTime<Utc>>) -> bool {
+        match &self.previous {
+            Some(previous) => previous.is_reached_by(id, time),
+            None => false,
+        }
+    }
+
+    /// Keep the item if it is the newest one so far
+    pub fn observe(&self, id: &'_ str, time: Option<DateTime<Utc>>) {
+        let seen = Watermark::new(id, time);
+        let mut newest = self.newest.lock().unwrap();
+        match &*newest {
+            Some(current) if !seen.is_newer_than(current) => {}
+            _ => *newest = Some(seen),
+        }
+    }
+
+    pub fn newest(&self) -> Option<Watermark> {
+        self.newest.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct WatermarkFile {
+    items: Vec<WatermarkFileEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct WatermarkFileEntry {
+    #[serde(flatten)]
+    key: WatermarkKey,
+    #[serde(flatten)]
+    watermark: Watermark,
+}
+
+/// How far each subscription has been gathered, so a run only walks what is new since the last one
+#[derive(Debug, Default)]
+pub struct Watermarks {
+    path: Option<PathBuf>,
+    items: Mutex<HashMap<WatermarkKey, Watermark>>,
+    /// Watermarks of gathers whose downloads are still running, kept out of the file until the run ends
+    pending: Mutex<HashMap<WatermarkKey, Watermark>>,
+    batch: super::SaveBatch,
+}
+
+impl Watermarks {
+    /// Load the watermarks from the default state directory
+    pub fn load_default() -> Result<Self> {
+        Self::load(
+            Directories::new()
+                .get_default_state_dir()
+                .join(WATERMARK_FILE),
+        )
+    }
+
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path: PathBuf = path.into();
+        let watermark_file: WatermarkFile = super::load_json(&path)?;
+        log::debug!(
+            "Loaded {} watermarks from {:?}",
+            watermark_file.items.len(),
+            path
+        );
+        Ok(Self {
+            items: Mutex::new(
+                watermark_file
+                    .items
+                    .into_iter()
+                    .map(|entry| (entry.key, entry.watermark))
+                    .collect(),
+            ),
+            path: Some(path),
+            ..Default::default()
+        })
+    }
+
+    pub fn get(&self, key: &'_ WatermarkKey) -> Option<Watermark> {
+        self.items.lock().unwrap().get(key).cloned()
+    }
+
+    /// Move the watermark forward and save it, an older watermark than the one already kept is ignored
+    pub fn advance(&self, key: WatermarkKey, watermark: Watermark) {
+        if Self::insert_newer(&mut self.items.lock().unwrap(), key, watermark)
+            && self.path.is_some()
+        {
+            if let Err(save_err) = self.save() {
+                log::error!("Failed to save the watermarks. {:?}", save_err);
+            }
+        }
+    }
+
+    /// Move the watermark forward once the run ends, for gathers that queued downloads which may not finish
+    /// before a crash. Saving it right away would have the next run skip what was never downloaded
+    pub fn advance_after_run(&self, key: WatermarkKey, watermark: Watermark) {
+        Self::insert_newer(&mut self.pending.lock().unwrap(), key, watermark);
+    }
+
+    /// Move forward every watermark held back by [`Self::advance_after_run`], [`Self::save`] persists them
+    pub fn commit_pending(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut items = self.items.lock().unwrap();
+        for (key, watermark) in pending {
+            Self::insert_newer(&mut items, key, watermark);
+        }
+    }
+
+    fn insert_newer(
+        items: &'_ mut HashMap<WatermarkKey, Watermark>,
+        key: WatermarkKey,
+        watermark: Watermark,
+    ) -> bool {
+        match items.get(&key) {
+            Some(current) if !watermark.is_newer_than(current) => false,
+            _ => {
+                log::trace!("Advancing watermark for {} to {:?}", key, watermark);
+                items.insert(key, watermark);
+                true
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist the watermarks to where they were loaded from, in-memory watermarks are a no-op
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.batch.save(|| {
+            let watermark_file = WatermarkFile {
+                items: self
+                    .items
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(key, watermark)| WatermarkFileEntry {
+                        key: key.clone(),
+                        watermark: watermark.clone(),
+                    })
+                    .collect(),
+            };
+            super::save_json(path, &watermark_file)
+        })
+    }
+}
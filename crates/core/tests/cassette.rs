@@ -0,0 +1,144 @@
+use {
+    futures::executor::block_on,
+    gatherer_core::http::{
+        cassette::read_cassette, CassetteConfig, Client, ClientConfig, HttpRetryPolicy, RateLimit,
+    },
+    std::{
+        collections::HashMap,
+        io::{Read, Write},
+        net::TcpListener,
+        path::PathBuf,
+        thread,
+    },
+};
+
+const BODY: &str = r#"{"id":1,"name":"creator"}"#;
+
+/// Answers a single request with a JSON body and a session cookie
+fn serve_once() -> String {
+    serve_once_with(BODY)
+}
+
+fn serve_once_with(body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\nset-cookie: sess=secret-session\r\n\
+             content-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+fn cassette_path() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "gatherers-cassette-{}-{}.json",
+        std::process::id(),
+        fastrand::u64(..)
+    ))
+}
+
+fn client(base_url: String, cassette: CassetteConfig) -> Client {
+    Client::new(ClientConfig {
+        name: Some("test".to_string()),
+        base_url: Some(base_url),
+        rate_limit: RateLimit::unlimited(),
+        retry: HttpRetryPolicy::none(),
+        cassette: Some(cassette),
+        proxy: None,
+    })
+}
+
+#[test]
+fn records_scrubbed_exchanges_and_replays_them() {
+    let path = cassette_path();
+    let base_url = serve_once();
+    block_on(async {
+        let headers = HashMap::from([
+            ("authorization".to_string(), "secret-token".to_string()),
+            ("sign".to_string(), "secret-sign".to_string()),
+            ("x-page".to_string(), "1".to_string()),
+        ]);
+        let recorder = client(base_url.clone(), CassetteConfig::record(&path));
+        let resp = recorder.get("/api/me", Some(headers)).await.unwrap();
+        assert_eq!(resp.as_string().await.unwrap(), BODY);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        for secret in ["secret-token", "secret-sign", "secret-session"] {
+            assert!(!contents.contains(secret), "{} was recorded", secret);
+        }
+        let cassette = read_cassette(&path).unwrap();
+        let interaction = &cassette.interactions[0];
+        assert_eq!(interaction.request.url, format!("{}/api/me", base_url));
+        assert_eq!(interaction.request.headers["authorization"], "[scrubbed]");
+        assert_eq!(interaction.request.headers["x-page"], "1");
+        assert_eq!(interaction.response.headers["set-cookie"], "[scrubbed]");
+        assert_eq!(interaction.response.body, BODY);
+
+        // The server only answers once, so this can only come from the cassette
+        let replayer = client(base_url.clone(), CassetteConfig::replay(&path));
+        let resp = replayer.get("/api/me", None).await.unwrap();
+        assert_eq!(resp.as_string().await.unwrap(), BODY);
+        // Each exchange answers a single request
+        assert!(replayer.get("/api/me", None).await.is_err());
+    });
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn scrubs_json_keys_and_query_parameters() {
+    const SECRET_BODY: &str = r#"{"id":1,"email":"fan@example.com","nested":[{"Token":"secret-token","card":"secret-card"}]}"#;
+    let path = cassette_path();
+    let base_url = serve_once_with(SECRET_BODY);
+    block_on(async {
+        let cassette = CassetteConfig::record(&path).with_scrub_keys(["card"]);
+        let recorder = client(base_url.clone(), cassette);
+        let resp = recorder
+            .get("/api/me?access_token=secret-query&page=2", None)
+            .await
+            .unwrap();
+        // The caller still gets the real response
+        assert_eq!(resp.as_string().await.unwrap(), SECRET_BODY);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        for secret in [
+            "fan@example.com",
+            "secret-token",
+            "secret-card",
+            "secret-query",
+        ] {
+            assert!(!contents.contains(secret), "{} was recorded", secret);
+        }
+        let interaction = &read_cassette(&path).unwrap().interactions[0];
+        assert_eq!(
+            interaction.request.url,
+            format!("{}/api/me?access_token=%5Bscrubbed%5D&page=2", base_url)
+        );
+        let body: serde_json::Value = serde_json::from_str(&interaction.response.body).unwrap();
+        assert_eq!(body["id"], 1);
+        assert_eq!(body["nested"][0]["Token"], "[scrubbed]");
+
+        // Replay scrubs the request the same way to find it
+        let replayer = client(base_url.clone(), CassetteConfig::replay(&path));
+        let resp = replayer
+            .get("/api/me?access_token=another-token&page=2", None)
+            .await
+            .unwrap();
+        assert_eq!(resp.as_string().await.unwrap(), interaction.response.body);
+    });
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,151 @@
+use {
+    chrono::{TimeZone, Utc},
+    gatherer_core::http::{Cookie, CookieJar, StoredCookie, Url},
+    std::path::PathBuf,
+};
+
+fn url(url: &'_ str) -> Url {
+    Url::parse(url).unwrap()
+}
+
+fn jar_path() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "gatherers-cookies-{}-{}.json",
+        std::process::id(),
+        fastrand::u64(..)
+    ))
+}
+
+#[test]
+fn cookie_values_keep_their_equals_signs() {
+    let cookie = Cookie::parse("sess=abc==; csrf=x=y; flag").unwrap();
+    assert_eq!(cookie.get("sess"), "abc==");
+    assert_eq!(cookie.get("csrf"), "x=y");
+    assert_eq!(cookie.get("flag"), "true");
+}
+
+#[test]
+fn set_cookie_attributes_are_parsed() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let cookie = StoredCookie::parse(
+        &url("https://api.example.com/v2/users/me"),
+        "sess=abc==; Domain=.Example.com; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Secure; HttpOnly",
+        now,
+    )
+    .unwrap();
+    assert_eq!(cookie.value, "abc==");
+    assert_eq!(cookie.domain, "example.com");
+    assert!(!cookie.host_only);
+    assert_eq!(cookie.path, "/v2/users");
+    assert_eq!(
+        cookie.expires,
+        Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap())
+    );
+    assert!(cookie.secure && cookie.http_only);
+
+    // Max-Age wins over Expires
+    let cookie = StoredCookie::parse(
+        &url("https://example.com/"),
+        "a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=60",
+        now,
+    )
+    .unwrap();
+    assert_eq!(cookie.expires, Some(now + chrono::Duration::seconds(60)));
+
+    // A Max-Age past what a date can hold never expires
+    let cookie = StoredCookie::parse(
+        &url("https://example.com/"),
+        &format!("a=1; Max-Age={}", i64::MAX),
+        now,
+    )
+    .unwrap();
+    assert_eq!(cookie.expires, Some(chrono::DateTime::<Utc>::MAX_UTC));
+    assert!(!cookie.is_expired(now));
+
+    // Another site's domain and a missing `=` are both ignored
+    assert!(
+        StoredCookie::parse(&url("https://example.com/"), "a=1; Domain=other.com", now).is_none()
+    );
+    assert!(StoredCookie::parse(&url("https://example.com/"), "[scrubbed]", now).is_none());
+}
+
+#[test]
+fn cookies_for_a_public_suffix_are_ignored() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let site = url("https://shop.example.co.uk/");
+    assert!(StoredCookie::parse(&site, "a=1; Domain=uk", now).is_none());
+    assert!(StoredCookie::parse(&site, "a=1; Domain=co.uk", now).is_none());
+    assert!(StoredCookie::parse(&url("https://example.com/"), "a=1; Domain=com", now).is_none());
+    let cookie = StoredCookie::parse(&site, "a=1; Domain=example.co.uk", now).unwrap();
+    assert_eq!(cookie.domain, "example.co.uk");
+
+    // A host that is itself a suffix keeps the cookie to itself
+    let cookie =
+        StoredCookie::parse(&url("http://localhost/"), "a=1; Domain=localhost", now).unwrap();
+    assert!(cookie.host_only);
+}
+
+#[test]
+fn jar_sends_cookies_matching_the_request() {
+    let jar = CookieJar::default();
+    let site = url("https://example.com/api/init");
+    assert!(jar.set_cookie(&site, "host=1; Path=/"));
+    assert!(jar.set_cookie(&site, "wide=2; Domain=example.com; Path=/"));
+    assert!(jar.set_cookie(&site, "api=3; Path=/api"));
+    assert!(jar.set_cookie(&site, "secure=4; Path=/; Secure"));
+
+    assert_eq!(
+        jar.header_for(&url("https://example.com/api/me"))
+            .as_deref(),
+        Some("api=3; host=1; wide=2; secure=4")
+    );
+    assert_eq!(
+        jar.header_for(&url("https://cdn.example.com/apis"))
+            .as_deref(),
+        Some("wide=2")
+    );
+    assert_eq!(
+        jar.header_for(&url("http://example.com/")).as_deref(),
+        Some("host=1; wide=2")
+    );
+    assert_eq!(jar.header_for(&url("https://notexample.com/")), None);
+}
+
+#[test]
+fn jar_updates_and_expires_cookies() {
+    let jar = CookieJar::default();
+    let site = url("https://example.com/");
+    assert!(jar.set_cookie(&site, "sess=old"));
+    assert!(!jar.set_cookie(&site, "sess=old"));
+    assert!(jar.set_cookie(&site, "sess=new"));
+    assert_eq!(jar.len(), 1);
+    assert_eq!(jar.get("sess").as_deref(), Some("new"));
+
+    assert!(jar.set_cookie(&site, "sess=gone; Max-Age=0"));
+    assert!(jar.is_empty());
+    assert!(!jar.set_cookie(&site, "other=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT"));
+    assert_eq!(jar.header_for(&site), None);
+}
+
+#[test]
+fn jar_is_saved_and_only_reseeded_by_a_new_cookie_string() {
+    let path = jar_path();
+    let site = url("https://example.com");
+    let jar = CookieJar::load(&path).unwrap();
+    jar.seed(&site, "sess=first; auth_id=1").unwrap();
+    jar.store_response(
+        &url("https://example.com/api/init"),
+        ["sess=refreshed; Path=/; Max-Age=3600"].into_iter(),
+    );
+
+    let jar = CookieJar::load(&path).unwrap();
+    assert_eq!(jar.get("sess").as_deref(), Some("refreshed"));
+    // Same string as before, the refreshed session stays
+    jar.seed(&site, "sess=first; auth_id=1").unwrap();
+    assert_eq!(jar.get("sess").as_deref(), Some("refreshed"));
+    // A new login replaces the whole jar
+    jar.seed(&site, "sess=second; auth_id=1").unwrap();
+    assert_eq!(jar.get("sess").as_deref(), Some("second"));
+    assert_eq!(CookieJar::load(&path).unwrap().len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
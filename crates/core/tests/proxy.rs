@@ -0,0 +1,218 @@
+use {
+    futures::executor::block_on,
+    gatherer_core::http::{proxy, ProxyConfig},
+    std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+const BODY: &str = "through the proxy";
+
+/// Reads up to the end of a header, the proxies below have no use for anything after it
+fn read_head(stream: &'_ mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).unwrap() == 0 {
+            break;
+        }
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// Plays the site at the other end of the tunnel
+fn answer_request(stream: &'_ mut TcpStream) {
+    read_head(stream);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        BODY.len(),
+        BODY
+    );
+    stream.write_all(response.as_bytes()).unwrap();
+}
+
+/// An HTTP proxy answering one `CONNECT` with `status`, the header it was sent comes back on the channel
+fn http_proxy(status: &'static str) -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        tx.send(read_head(&mut stream)).unwrap();
+        stream
+            .write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes())
+            .unwrap();
+        if status.starts_with('2') {
+            answer_request(&mut stream);
+        }
+    });
+    (format!("http://{}", addr), rx)
+}
+
+/// What a SOCKS5 client sent before the tunnel was open
+#[derive(Debug)]
+struct Socks5Handshake {
+    methods: Vec<u8>,
+    credentials: Option<(String, String)>,
+    address_type: u8,
+    address: Vec<u8>,
+    port: u16,
+}
+
+fn read_bytes(stream: &'_ mut TcpStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+}
+
+/// A SOCKS5 proxy for one connection, replying with a bound address of `bound_type`
+fn socks5_proxy(needs_auth: bool, bound_type: u8) -> (String, mpsc::Receiver<Socks5Handshake>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let greeting = read_bytes(&mut stream, 2);
+        let methods = read_bytes(&mut stream, greeting[1] as usize);
+        let method = if needs_auth { 2 } else { 0 };
+        stream.write_all(&[5, method]).unwrap();
+        let credentials = needs_auth.then(|| {
+            let version_and_len = read_bytes(&mut stream, 2);
+            let username = read_bytes(&mut stream, version_and_len[1] as usize);
+            let password_len = read_bytes(&mut stream, 1)[0] as usize;
+            let password = read_bytes(&mut stream, password_len);
+            stream.write_all(&[1, 0]).unwrap();
+            (
+                String::from_utf8(username).unwrap(),
+                String::from_utf8(password).unwrap(),
+            )
+        });
+        let request = read_bytes(&mut stream, 4);
+        let address = match request[3] {
+            1 => read_bytes(&mut stream, 4),
+            4 => read_bytes(&mut stream, 16),
+            _ => {
+                let len = read_bytes(&mut stream, 1)[0] as usize;
+                read_bytes(&mut stream, len)
+            }
+        };
+        let port = read_bytes(&mut stream, 2);
+        let mut reply = vec![5, 0, 0, bound_type];
+        match bound_type {
+            1 => reply.extend_from_slice(&[10, 0, 0, 1]),
+            4 => reply.extend_from_slice(&[0; 16]),
+            _ => {
+                reply.push(9);
+                reply.extend_from_slice(b"proxy.lan");
+            }
+        }
+        reply.extend_from_slice(&1080u16.to_be_bytes());
+        stream.write_all(&reply).unwrap();
+        tx.send(Socks5Handshake {
+            methods,
+            credentials,
+            address_type: request[3],
+            address,
+            port: u16::from_be_bytes([port[0], port[1]]),
+        })
+        .unwrap();
+        answer_request(&mut stream);
+    });
+    (format!("socks5://{}", addr), rx)
+}
+
+fn get(config: &'_ ProxyConfig, url: &'_ str) -> surf::Result<String> {
+    block_on(proxy::surf_client(Some(config)).get(url).recv_string())
+}
+
+#[test]
+fn http_connect_opens_a_tunnel() {
+    let (proxy_url, headers) = http_proxy("200 Connection established");
+    let config = ProxyConfig::new(proxy_url).with_credentials("user", "pass");
+    assert_eq!(get(&config, "http://example.test/api").unwrap(), BODY);
+
+    let head = headers.recv().unwrap();
+    assert!(head.starts_with("CONNECT example.test:80 HTTP/1.1\r\n"));
+    // base64 of user:pass
+    assert!(head.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+}
+
+#[test]
+fn http_connect_refused_by_the_proxy_is_an_error() {
+    let (proxy_url, _headers) = http_proxy("407 Proxy Authentication Required");
+    let err = get(&ProxyConfig::new(proxy_url), "http://example.test/api").unwrap_err();
+    assert!(err.to_string().contains("407"), "{}", err);
+}
+
+#[test]
+fn socks5_without_auth_sends_ipv4_addresses() {
+    let (proxy_url, handshakes) = socks5_proxy(false, 1);
+    assert_eq!(
+        get(&ProxyConfig::new(proxy_url), "http://127.0.0.1:8000/").unwrap(),
+        BODY
+    );
+    let handshake = handshakes.recv().unwrap();
+    assert_eq!(handshake.methods, [0]);
+    assert_eq!(handshake.credentials, None);
+    assert_eq!(handshake.address_type, 1);
+    assert_eq!(handshake.address, [127, 0, 0, 1]);
+    assert_eq!(handshake.port, 8000);
+}
+
+#[test]
+fn socks5_with_auth_sends_domains_for_the_proxy_to_resolve() {
+    let (proxy_url, handshakes) = socks5_proxy(true, 3);
+    let config = ProxyConfig::new(proxy_url.replace("socks5://", "socks5h://"))
+        .with_credentials("user", "pass");
+    assert_eq!(get(&config, "http://example.test/").unwrap(), BODY);
+    let handshake = handshakes.recv().unwrap();
+    assert_eq!(handshake.methods, [0, 2]);
+    assert_eq!(
+        handshake.credentials,
+        Some(("user".to_string(), "pass".to_string()))
+    );
+    assert_eq!(handshake.address_type, 3);
+    assert_eq!(handshake.address, b"example.test");
+    assert_eq!(handshake.port, 80);
+}
+
+#[test]
+fn socks5_sends_ipv6_addresses() {
+    let (proxy_url, handshakes) = socks5_proxy(false, 4);
+    assert_eq!(
+        get(&ProxyConfig::new(proxy_url), "http://[::1]:8000/").unwrap(),
+        BODY
+    );
+    let handshake = handshakes.recv().unwrap();
+    assert_eq!(handshake.address_type, 4);
+    assert_eq!(handshake.address, std::net::Ipv6Addr::LOCALHOST.octets());
+}
+
+#[test]
+fn socks5_credentials_longer_than_255_bytes_are_an_error() {
+    let (proxy_url, _handshakes) = socks5_proxy(true, 1);
+    let config = ProxyConfig::new(proxy_url).with_credentials("u".repeat(256), "pass");
+    let err = get(&config, "http://127.0.0.1:8000/").unwrap_err();
+    assert!(err.to_string().contains("255"), "{}", err);
+}
+
+#[test]
+fn a_proxy_that_never_answers_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        // Keep the connection open without ever replying
+        let (_stream, _) = listener.accept().unwrap();
+        thread::sleep(Duration::from_secs(10));
+    });
+    let config = ProxyConfig::new(format!("socks5://{}", addr)).with_connect_timeout_secs(1);
+    let started = Instant::now();
+    let err = get(&config, "http://127.0.0.1:8000/").unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(err.to_string().contains("no tunnel"), "{}", err);
+}
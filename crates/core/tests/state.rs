@@ -0,0 +1,108 @@
+use {
+    chrono::Utc,
+    gatherer_core::{
+        downloaders::{Downloadable, ErrorClass},
+        state::{
+            DeadLetter, DeadLetters, DownloadKey, DownloadState, Watermark, WatermarkKey,
+            Watermarks,
+        },
+    },
+    std::path::{Path, PathBuf},
+};
+
+fn state_path(name: &'_ str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "gatherers-{}-{}-{}.json",
+        name,
+        std::process::id(),
+        fastrand::u64(..)
+    ))
+}
+
+fn item(media_id: &'_ str) -> Downloadable {
+    Downloadable {
+        public_url: format!("https://cdn.example.test/{}.jpg", media_id),
+        file_name: format!("{}.jpg", media_id),
+        base_path: PathBuf::from("downloads"),
+        gatherer: "onlyfans".to_string(),
+        user_name: "creator".to_string(),
+        media_id: media_id.to_string(),
+        priority: 0,
+    }
+}
+
+fn letter(media_id: &'_ str) -> DeadLetter {
+    DeadLetter {
+        item: item(media_id),
+        class: ErrorClass::ServerError,
+        error: "503".to_string(),
+        attempts: 3,
+        failed_at: Utc::now(),
+    }
+}
+
+fn media_ids(letters: &'_ DeadLetters) -> Vec<String> {
+    let mut ids: Vec<String> = letters
+        .take_all()
+        .into_iter()
+        .map(|letter| letter.item.media_id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn download_state_is_saved_in_batches_during_the_run() {
+    let path = state_path("downloads");
+    let state = DownloadState::load(&path).unwrap();
+    for media_id in 0..25 {
+        let key = DownloadKey::from(&item(&media_id.to_string()));
+        state.record(key, Path::new("downloads/file.jpg"), 10, String::new());
+    }
+    // Nothing called save, the 25th change wrote the batch out
+    assert_eq!(DownloadState::load(&path).unwrap().len(), 25);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn requeued_dead_letters_stay_saved_until_the_run_is_settled() {
+    let path = state_path("dead-letters");
+    let letters = DeadLetters::load(&path).unwrap();
+    letters.push(letter("1"));
+    letters.push(letter("2"));
+    letters.save().unwrap();
+
+    let letters = DeadLetters::load(&path).unwrap();
+    assert_eq!(letters.take_all().len(), 2);
+    letters.push(letter("2"));
+    // Cut short here, the next run still has both and "2" only once
+    letters.save().unwrap();
+    assert_eq!(media_ids(&DeadLetters::load(&path).unwrap()), ["1", "2"]);
+
+    letters.settle();
+    letters.save().unwrap();
+    assert_eq!(media_ids(&DeadLetters::load(&path).unwrap()), ["2"]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn watermarks_with_queued_downloads_are_saved_once_the_run_ends() {
+    let path = state_path("watermarks");
+    let key = |gather_type: &'_ str| WatermarkKey {
+        gatherer: "onlyfans".to_string(),
+        user_name: "creator".to_string(),
+        gather_type: gather_type.to_string(),
+    };
+    let watermarks = Watermarks::load(&path).unwrap();
+    watermarks.advance(key("posts"), Watermark::new("10", None));
+    watermarks.advance_after_run(key("messages"), Watermark::new("20", None));
+
+    let saved = Watermarks::load(&path).unwrap();
+    assert!(saved.get(&key("posts")).is_some());
+    assert!(saved.get(&key("messages")).is_none());
+
+    watermarks.commit_pending();
+    watermarks.save().unwrap();
+    assert_eq!(Watermarks::load(&path).unwrap().len(), 2);
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,4 +1,3 @@
-pub(crate) const POSTS_LIMIT_COUNT: u8 = 10;
 pub(crate) const GROUP_MESSAGES_LIMIT: u8 = 50;
 pub(crate) const BASE_URL: &str = "https://apiv2.fansly.com";
 pub(crate) const STATUS_URL: &str = "/api/v1/status";
@@ -1,14 +1,18 @@
 use {
-    crate::{
-        structs::{self, MessageGroup},
-        Fansly,
-    },
+    crate::{responses, structs, Fansly},
     async_trait::async_trait,
+    futures::{stream, StreamExt, TryStreamExt},
     gatherer_core::{
-        gatherers::{Gatherer, Media, Subscription, Transaction},
+        gatherers::{
+            Gatherer, Media, MediaOrigin, MediaOriginKind, MediaStream, Message, Post,
+            Subscription, SubscriptionName, Transaction,
+        },
         Result,
     },
-    std::path::Path,
+    std::{
+        collections::{HashMap, HashSet},
+        path::Path,
+    },
 };
 
 #[async_trait]
@@ -20,21 +24,27 @@ impl Gatherer for Fansly {
     async fn gather_media_from_bundles(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
         let mut bundle_media = Vec::new();
 
-        let account = self.get_user_accounts_by_ids(&[sub.id.clone()]).await?;
-        let account = account.response.get(0).unwrap();
+        let account = self
+            .get_user_accounts_by_ids(std::slice::from_ref(&sub.id))
+            .await?;
+        let account = account.response.first().unwrap();
 
         if let Some(avatar) = account.avatar.clone() {
             log::debug!("Adding avatar for {}", sub.name);
-            if let Some(media) = crate::fansly_media_to_gatherers_media(avatar, &sub.name.username)
+            if let Some(mut media) =
+                crate::fansly_media_to_gatherers_media(avatar, &sub.name.username)
             {
+                media.origin = Some(MediaOrigin::new(MediaOriginKind::Profile, &sub.id, None));
                 bundle_media.push(media)
             }
         };
 
         if let Some(banner) = account.banner.clone() {
             log::debug!("Adding banner for {}", sub.name);
-            if let Some(media) = crate::fansly_media_to_gatherers_media(banner, &sub.name.username)
+            if let Some(mut media) =
+                crate::fansly_media_to_gatherers_media(banner, &sub.name.username)
             {
+                media.origin = Some(MediaOrigin::new(MediaOriginKind::Profile, &sub.id, None));
                 bundle_media.push(media)
             }
         };
@@ -43,11 +53,237 @@ impl Gatherer for Fansly {
     }
 
     async fn gather_media_from_posts(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
+        self.stream_media_from_posts(sub).try_collect().await
+    }
+
+    async fn gather_media_from_messages(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
+        self.stream_media_from_messages(sub).try_collect().await
+    }
+
+    fn stream_media_from_posts<'a>(&'a self, sub: &'a Subscription) -> MediaStream<'a> {
         log::debug!("Getting posts for user: {}", sub.id);
+        self.stream_posts_by_user_id(&sub.id, &sub.watermark, &sub.date_range)
+            .and_then(move |page| async move {
+                self.get_media_from_post_pages(sub, std::slice::from_ref(&page))
+                    .await
+            })
+            .map_ok(|media| stream::iter(media.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    fn stream_media_from_messages<'a>(&'a self, sub: &'a Subscription) -> MediaStream<'a> {
+        stream::once(self.get_subscription_threads(sub))
+            .map_ok(move |threads| {
+                stream::iter(threads).flat_map(move |thread| {
+                    self.stream_messages_from_group(thread.id, &sub.watermark, &sub.date_range)
+                })
+            })
+            .try_flatten()
+            .and_then(
+                move |messages| async move { self.get_media_from_messages(sub, &messages).await },
+            )
+            .map_ok(|media| stream::iter(media.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn gather_posts(&self, sub: &'_ Subscription) -> Result<Vec<Post>> {
+        let pages = self
+            .get_posts_by_user_id(&sub.id, &sub.watermark, &sub.date_range)
+            .await?;
+        let mut media_by_post = group_by_origin(self.get_media_from_post_pages(sub, &pages).await?);
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.posts.unwrap_or_default())
+            .map(|post| {
+                let media = media_by_post.remove(&post.id).unwrap_or_default();
+                Post {
+                    paid: media.iter().any(|media| media.paid),
+                    posted_at: crate::media_timestamp(post.created_at),
+                    id: post.id,
+                    title: String::new(),
+                    content: post.content,
+                    price: None,
+                    opened: None,
+                    media,
+                }
+            })
+            .collect())
+    }
+
+    async fn gather_messages(&self, sub: &'_ Subscription) -> Result<Vec<Message>> {
+        let messages = self.get_subscription_messages(sub).await?;
+        let mut media_by_message =
+            group_by_origin(self.get_media_from_messages(sub, &messages).await?);
+        // Look up who is on the other end of the conversation, that is the authed user
+        let other_ids: Vec<String> = messages
+            .iter()
+            .filter(|message| message.sender_id != sub.id)
+            .map(|message| message.sender_id.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let others: HashMap<String, SubscriptionName> = if other_ids.is_empty() {
+            HashMap::new()
+        } else {
+            self.get_user_accounts_by_ids(&other_ids)
+                .await?
+                .response
+                .into_iter()
+                .map(|account| {
+                    (
+                        account.id,
+                        SubscriptionName {
+                            username: account.username,
+                            display_name: account.display_name,
+                        },
+                    )
+                })
+                .collect()
+        };
+        Ok(messages
+            .into_iter()
+            .map(|message| {
+                let (from, to) = if message.sender_id == sub.id {
+                    let to = others.values().next().cloned().unwrap_or_default();
+                    (sub.name.clone(), to)
+                } else {
+                    let from = others.get(&message.sender_id).cloned().unwrap_or_else(|| {
+                        SubscriptionName {
+                            username: message.sender_id.clone(),
+                            display_name: None,
+                        }
+                    });
+                    (from, sub.name.clone())
+                };
+                Message {
+                    attached_media: media_by_message.remove(&message.id).unwrap_or_default(),
+                    sent_at: crate::media_timestamp(message.created_at),
+                    id: message.id,
+                    from,
+                    to,
+                    message: message.content,
+                    price: None,
+                    opened: None,
+                }
+            })
+            .collect())
+    }
+
+    async fn gather_media_from_stories(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
+        match self.get_account_stories(&sub.id).await {
+            Ok(user_stories) => {
+                let origins: HashMap<String, MediaOrigin> = user_stories
+                    .iter()
+                    .filter_map(|story| {
+                        let origin = MediaOrigin::new(
+                            MediaOriginKind::Story,
+                            &story.id,
+                            crate::media_timestamp(story.created_at),
+                        );
+                        Some((story.content_id.clone()?, origin))
+                    })
+                    .collect();
+                let story_content_ids: Vec<String> = user_stories
+                    .into_iter()
+                    .map(|s| s.content_id.unwrap_or_default())
+                    .collect();
+                match self.get_media_by_ids(&story_content_ids).await {
+                    Ok(media) => Ok(media
+                        .into_iter()
+                        .filter_map(|fansly_media| {
+                            to_gatherer_media(fansly_media, &sub.name.username).ok()
+                        })
+                        .map(|mut media| {
+                            media.origin = origins.get(&media.id).cloned();
+                            media
+                        })
+                        .collect()),
+                    Err(media_err) => Err(media_err),
+                }
+            }
+            Err(stories_err) => Err(stories_err),
+        }
+    }
+
+    async fn gather_paid_content(&self) -> Result<Vec<Media>> {
+        let purchased = self.get_purchased_content().await?;
+        let media_ids: Vec<String> = purchased
+            .iter()
+            .map(|media| media.account_media_id.to_string())
+            .collect();
+        let purchased_at: HashMap<&str, i64> = purchased
+            .iter()
+            .map(|media| (media.account_media_id.as_str(), media.created_at as i64))
+            .collect();
+        let media = self.get_media_by_ids(&media_ids).await?;
+        Ok(media
+            .into_iter()
+            .filter_map(|media| {
+                let bought_at = purchased_at.get(media.id.as_str()).copied();
+                let mut media = super::fansly_media_to_gatherers_media(media, "")?;
+                media.purchased_at = bought_at.and_then(crate::media_timestamp);
+                Some(media)
+            })
+            .collect())
+    }
+
+    async fn gather_transaction_details(&self, user_names: &[String]) -> Result<Vec<Transaction>> {
+        match self.get_transaction_details(user_names).await {
+            Ok(transactions) => {
+                let user_ids = transactions
+                    .iter()
+                    .filter_map(|t| t.receiver_id.clone())
+                    .collect::<Vec<_>>();
+                let user_accounts: crate::responses::AccountsResponse =
+                    self.get_user_accounts_by_ids(&user_ids).await?;
+                let mut all_transaction_details = Vec::new();
+                for transaction in transactions
+                    .into_iter()
+                    .filter(|transaction| transaction.status == 2)
+                {
+                    if let Some(receiver_id) = transaction.receiver_id {
+                        if let Some(account) = user_accounts
+                            .response
+                            .iter()
+                            .find(|account| account.id == receiver_id)
+                        {
+                            let amount = transaction.amount as f64 / 1000.;
+                            all_transaction_details.push(Transaction {
+                                total_amount: amount,
+                                user_name: account.username.clone(),
+                                date: Default::default(),
+                                description: None,
+                            })
+                        }
+                    }
+                }
+                Ok(all_transaction_details)
+            }
+            Err(transaction_err) => Err(transaction_err),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.conf.enabled
+    }
+
+    fn name(&self) -> &'static str {
+        "fansly"
+    }
+}
 
-        let posts = self.get_posts_by_user_id(&sub.id).await?;
-        log::debug!("Found posts from user: {}. {:?}", &sub.name.username, posts);
-        let mut media_ids: Vec<String> = posts
+/// Helpers shared by the media and the post/message gatherers
+impl Fansly {
+    /// Every media in the timeline pages with the post or bundle it came from set as its origin
+    async fn get_media_from_post_pages(
+        &self,
+        sub: &'_ Subscription,
+        pages: &'_ [responses::inner::Posts],
+    ) -> Result<Vec<Media>> {
+        log::debug!("Found posts from user: {}. {:?}", &sub.name.username, pages);
+        let mut media_ids: Vec<String> = pages
             .iter()
             .flat_map(|p| {
                 let media_ids = p
@@ -66,8 +302,26 @@ impl Gatherer for Fansly {
             })
             .collect();
 
+        // The post each attachment came from, bundles pass theirs on to the media inside them
+        let mut origins: HashMap<String, MediaOrigin> = HashMap::new();
+        for post in pages.iter().flat_map(|page| page.posts.iter().flatten()) {
+            let origin = MediaOrigin::new(
+                MediaOriginKind::Post,
+                &post.id,
+                crate::media_timestamp(post.created_at),
+            )
+            .with_text(Some(post.content.clone()));
+            for content_id in post
+                .attachments
+                .iter()
+                .filter_map(|attachment| attachment.content_id.clone())
+            {
+                origins.entry(content_id).or_insert_with(|| origin.clone());
+            }
+        }
+
         log::debug!("Collecting media bundles");
-        let bundle_ids: Vec<String> = posts
+        let bundle_ids: Vec<String> = pages
             .iter()
             .flat_map(|post| {
                 if let Some(bundles) = &post.account_media_bundles {
@@ -81,13 +335,24 @@ impl Gatherer for Fansly {
             })
             .collect();
         let bundles = self.get_media_bundles_by_ids(&bundle_ids).await?;
+        for bundle in &bundles {
+            let origin = origins
+                .get(&bundle.id)
+                .cloned()
+                .unwrap_or_else(|| MediaOrigin::new(MediaOriginKind::Bundle, &bundle.id, None));
+            for media_id in &bundle.account_media_ids {
+                origins
+                    .entry(media_id.clone())
+                    .or_insert_with(|| origin.clone());
+            }
+        }
         let mut bundle_media_ids: Vec<String> = bundles
             .into_iter()
             .flat_map(|bundle| bundle.account_media_ids.to_vec())
             .collect();
         media_ids.append(&mut bundle_media_ids);
 
-        let mut account_media_ids: Vec<String> = posts
+        let mut account_media_ids: Vec<String> = pages
             .iter()
             .flat_map(|post| {
                 if let Some(account_medias) = &post.account_media {
@@ -118,179 +383,153 @@ impl Gatherer for Fansly {
         Ok(all_media
             .into_iter()
             .filter_map(|media| super::fansly_media_to_gatherers_media(media, &sub.name.username))
+            .map(|mut media| {
+                media.origin = origins.get(&media.id).cloned();
+                media
+            })
             .collect())
     }
 
-    async fn gather_media_from_messages(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
-        let groups = self.get_messages_groups().await;
-        match groups {
-            Ok(groups) => {
-                // TODO: might be a better way to do this without grabbing all of them first
-                log::debug!(
-                    "{}: Found {} total message threads",
-                    self.name(),
-                    groups.len()
-                );
-                let subscription_threads: Vec<MessageGroup> = groups
-                    .into_iter()
-                    .filter(|group| {
-                        group
-                            .users
-                            .iter()
-                            .any(|g_user| sub.id[..] == g_user.user_id)
-                    })
-                    .collect();
-                let mut media_ids_from_messages = Vec::new();
-                for thread in subscription_threads {
-                    let messages = self.get_all_messages_from_group(&thread.id).await;
-                    match messages {
-                        Ok(thread_messages) => {
-                            log::debug!(
-                                "Found {} messages in thread {}",
-                                thread_messages.len(),
-                                thread.id
-                            );
-                            let mut thread_media_ids: Vec<String> = thread_messages
-                                .iter()
-                                .flat_map(|m| {
-                                    m.attachments
-                                        .iter()
-                                        .map(|a| a.content_id.clone().unwrap_or_default())
-                                        .collect::<Vec<_>>()
-                                })
-                                .collect();
-                            log::debug!(
-                                "Found {} media items from messages for {}",
-                                thread_media_ids.len(),
-                                sub.name
-                            );
-                            media_ids_from_messages.append(&mut thread_media_ids);
-                        }
-                        Err(group_message_err) => {
-                            return Err(format!(
-                                "Failed to get messages for {}({}). {:#}",
-                                sub.name, thread.id, group_message_err
-                            )
-                            .into())
-                        }
-                    }
-                }
-
-                let media_items = self.get_media_by_ids(&media_ids_from_messages).await;
-                match media_items {
-                    Ok(media) => {
-                        log::debug!(
-                            "Get data on {} media items from messages for user {}",
-                            media.len(),
-                            sub.name
-                        );
-                        // let media: Vec<Media> = ;
-                        Ok(media
-                            .into_iter()
-                            .filter_map(|media| {
-                                super::fansly_media_to_gatherers_media(media, &sub.name.username)
-                            })
-                            .collect())
-                    }
-                    Err(media_err) => Err(format!(
-                        "Failed to get media details for {} items for user {}. {:?}",
-                        media_ids_from_messages.len(),
-                        sub.name,
-                        media_err,
-                    )
-                    .into()),
-                }
-            }
-            Err(group_err) => Err(format!(
+    /// The message threads the sub is part of
+    async fn get_subscription_threads(
+        &self,
+        sub: &'_ Subscription,
+    ) -> Result<Vec<structs::MessageGroup>> {
+        let groups = self.get_messages_groups().await.inspect_err(|group_err| {
+            log::error!(
                 "{}: Failed to get message groups for user {}. {}",
                 self.name(),
                 sub.name.username,
                 group_err
             )
-            .into()),
-        }
+        })?;
+        // TODO: might be a better way to do this without grabbing all of them first
+        log::debug!(
+            "{}: Found {} total message threads",
+            self.name(),
+            groups.len()
+        );
+        Ok(groups
+            .into_iter()
+            .filter(|group| {
+                group
+                    .users
+                    .iter()
+                    .any(|g_user| sub.id[..] == g_user.user_id)
+            })
+            .collect())
     }
 
-    async fn gather_media_from_stories(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
-        match self.get_account_stories(&sub.id).await {
-            Ok(user_stories) => {
-                let story_content_ids: Vec<String> = user_stories
-                    .into_iter()
-                    .map(|s| s.content_id.unwrap_or_default())
-                    .collect();
-                match self.get_media_by_ids(&story_content_ids).await {
-                    Ok(media) => Ok(media
-                        .into_iter()
-                        .filter_map(|fansly_media| {
-                            match to_gatherer_media(fansly_media, &sub.name.username) {
-                                Ok(link) => Some(link),
-                                Err(_) => None,
-                            }
-                        })
-                        .collect()),
-                    Err(media_err) => Err(media_err),
+    /// Every message in the threads the sub is part of, sent by either side
+    async fn get_subscription_messages(
+        &self,
+        sub: &'_ Subscription,
+    ) -> Result<Vec<structs::Message>> {
+        let mut messages = Vec::new();
+        for thread in self.get_subscription_threads(sub).await? {
+            match self
+                .get_all_messages_from_group(&thread.id, &sub.watermark, &sub.date_range)
+                .await
+            {
+                Ok(mut thread_messages) => {
+                    log::debug!(
+                        "Found {} messages in thread {}",
+                        thread_messages.len(),
+                        thread.id
+                    );
+                    messages.append(&mut thread_messages);
+                }
+                Err(group_message_err) => {
+                    log::error!(
+                        "Failed to get messages for {}({}). {:#}",
+                        sub.name,
+                        thread.id,
+                        group_message_err
+                    );
+                    return Err(group_message_err);
                 }
             }
-            Err(stories_err) => Err(stories_err),
         }
+        Ok(messages)
     }
 
-    async fn gather_paid_content(&self) -> Result<Vec<Media>> {
-        let purchased = self.get_purchased_content().await?;
-        let media_ids: Vec<String> = purchased
+    /// Every media attached to the messages with the message set as its origin
+    async fn get_media_from_messages(
+        &self,
+        sub: &'_ Subscription,
+        messages: &'_ [structs::Message],
+    ) -> Result<Vec<Media>> {
+        let mut origins: HashMap<String, MediaOrigin> = HashMap::new();
+        for message in messages {
+            let origin = MediaOrigin::new(
+                MediaOriginKind::Message,
+                &message.id,
+                crate::media_timestamp(message.created_at),
+            )
+            .with_text(Some(message.content.clone()));
+            for content_id in message
+                .attachments
+                .iter()
+                .filter_map(|attachment| attachment.content_id.clone())
+            {
+                origins.entry(content_id).or_insert_with(|| origin.clone());
+            }
+        }
+        let media_ids_from_messages: Vec<String> = messages
             .iter()
-            .map(|media| media.account_media_id.to_string())
+            .flat_map(|m| {
+                m.attachments
+                    .iter()
+                    .map(|a| a.content_id.clone().unwrap_or_default())
+                    .collect::<Vec<_>>()
+            })
             .collect();
-        let media = self.get_media_by_ids(&media_ids).await?;
-        Ok(media
-            .into_iter()
-            .filter_map(|media| super::fansly_media_to_gatherers_media(media, ""))
-            .collect())
-    }
+        log::debug!(
+            "Found {} media items from messages for {}",
+            media_ids_from_messages.len(),
+            sub.name
+        );
 
-    async fn gather_transaction_details(&self, user_names: &[String]) -> Result<Vec<Transaction>> {
-        match self.get_transaction_details(user_names).await {
-            Ok(transactions) => {
-                let user_ids = transactions
-                    .iter()
-                    .filter_map(|t| t.receiver_id.clone())
-                    .collect::<Vec<_>>();
-                let user_accounts: crate::responses::AccountsResponse =
-                    self.get_user_accounts_by_ids(&user_ids).await?;
-                let mut all_transaction_details = Vec::new();
-                for transaction in transactions
+        match self.get_media_by_ids(&media_ids_from_messages).await {
+            Ok(media) => {
+                log::debug!(
+                    "Get data on {} media items from messages for user {}",
+                    media.len(),
+                    sub.name
+                );
+                Ok(media
                     .into_iter()
-                    .filter(|transaction| transaction.status == 2)
-                {
-                    if let Some(receiver_id) = transaction.receiver_id {
-                        if let Some(account) = user_accounts
-                            .response
-                            .iter()
-                            .find(|account| account.id == receiver_id)
-                        {
-                            let amount = transaction.amount as f64 / 1000.;
-                            all_transaction_details.push(Transaction {
-                                total_amount: amount,
-                                user_name: account.username.clone(),
-                                date: Default::default(),
-                                description: None,
-                            })
-                        }
-                    }
-                }
-                Ok(all_transaction_details)
+                    .filter_map(|media| {
+                        super::fansly_media_to_gatherers_media(media, &sub.name.username)
+                    })
+                    .map(|mut media| {
+                        media.origin = origins.get(&media.id).cloned();
+                        media
+                    })
+                    .collect())
+            }
+            Err(media_err) => {
+                log::error!(
+                    "Failed to get media details for {} items for user {}. {:?}",
+                    media_ids_from_messages.len(),
+                    sub.name,
+                    media_err,
+                );
+                Err(media_err)
             }
-            Err(transaction_err) => Err(transaction_err),
         }
     }
+}
 
-    fn is_enabled(&self) -> bool {
-        self.conf.enabled
-    }
-
-    fn name(&self) -> &'static str {
-        "fansly"
+/// Media keyed by the id of the post or message it was attached to
+fn group_by_origin(media: Vec<Media>) -> HashMap<String, Vec<Media>> {
+    let mut grouped: HashMap<String, Vec<Media>> = HashMap::new();
+    for media in media {
+        if let Some(origin) = &media.origin {
+            grouped.entry(origin.id.clone()).or_default().push(media);
+        }
     }
+    grouped
 }
 
 pub fn to_gatherer_media(
@@ -301,6 +540,7 @@ pub fn to_gatherer_media(
         if details.locations.is_empty() {
             return Err(format!("Content not available: {:?}", details).into());
         }
+        let (width, height, duration) = (details.width(), details.height(), details.duration());
         let filename = details.file_name.unwrap_or_default();
         let original_file_path = Path::new(&filename);
         // debug!("The original upload file name was {:?}", original_file_path);
@@ -310,11 +550,21 @@ pub fn to_gatherer_media(
             .map(|ext| format!(".{}", &ext.to_str().unwrap_or_default()))
             .unwrap_or_default();
         Ok(gatherer_core::gatherers::Media {
+            id: fansly_media.id.clone(),
+            created_at: crate::media_timestamp(fansly_media.created_at),
+            expires_at: None,
             file_name,
             url: details.locations[0].location.to_string(),
+            kind: gatherer_core::gatherers::MediaKind::from_mime_type(&details.mimetype),
             mime_type: details.mimetype,
             paid: fansly_media.purchased,
             user_name: sub_name.to_string(),
+            width,
+            height,
+            duration,
+            size: None,
+            origin: None,
+            purchased_at: None,
         })
     } else {
         Err(format!("Content not available: {:?}", fansly_media).into())
@@ -1,14 +1,23 @@
 mod constants;
 mod gatherer;
 mod responses;
-mod structs;
+pub mod structs;
 
 pub use self::gatherer::*;
 use {
     chrono::prelude::*,
+    futures::{
+        stream::{self, BoxStream},
+        StreamExt, TryStreamExt,
+    },
     gatherer_core::{
+        gatherers::DateRange,
         gatherers::{self, Gatherer, GathererErrors, Subscription, SubscriptionName},
-        http::{self, Client, ClientConfig, Headers},
+        http::{
+            self, CassetteConfig, Client, ClientConfig, Headers, HttpRetryPolicy, ProxyConfig,
+            RateLimit,
+        },
+        state::WatermarkCursor,
         Result,
     },
     regex::Regex,
@@ -21,6 +30,26 @@ pub struct FanslyConfig {
     pub enabled: bool,
     pub auth_token: String,
     pub ignore_lists: Vec<String>,
+    /// How fast API requests are sent, downloads aren't counted
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub retry: HttpRetryPolicy,
+    /// Record the API traffic to a cassette or replay it from one, for bug reports and tests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cassette: Option<CassetteConfig>,
+    /// Send the API requests and media downloads through this proxy instead of the global one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+}
+
+gatherer_core::register_gatherer! {
+    name: "fansly",
+    config_section: "fansly",
+    aliases: [],
+    capabilities: [Posts, Messages, Bundles, Stories, Purchased, Transactions],
+    gatherer: Fansly,
+    config: FanslyConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -39,7 +68,12 @@ impl Fansly {
         };
 
         let api_config = ClientConfig {
+            name: Some("Fansly".to_string()),
             base_url: Some(constants::BASE_URL.to_string()),
+            rate_limit: fansly_conf.rate_limit,
+            retry: fansly_conf.retry.clone(),
+            cassette: fansly_conf.cassette.clone(),
+            proxy: fansly_conf.proxy.clone(),
         };
         let s = Self {
             http_client: Client::new(api_config),
@@ -118,7 +152,7 @@ impl Fansly {
             account_ids.join(",")
         );
         let resp = self.http_client.get(&endpoint, None).await?;
-        Ok(resp.as_json().await?)
+        resp.as_json().await
     }
 
     pub async fn get_media_by_ids(&self, media_ids: &[String]) -> Result<Vec<structs::Media>> {
@@ -172,15 +206,30 @@ impl Fansly {
         }
     }
 
+    /// Walk the timeline newest first, stops after the page holding the first post covered by `watermark`
     pub async fn get_posts_by_user_id(
         &self,
         account_id: &'_ str,
+        watermark: &'_ WatermarkCursor,
+        range: &'_ DateRange,
     ) -> Result<Vec<responses::inner::Posts>> {
-        let mut posts: Vec<responses::inner::Posts> = Vec::new();
-        let mut more_pages = true;
-        let mut before_post_id = String::from("0");
+        self.stream_posts_by_user_id(account_id, watermark, range)
+            .try_collect()
+            .await
+    }
 
-        while more_pages {
+    /// [`Fansly::get_posts_by_user_id`] a page at a time
+    pub fn stream_posts_by_user_id<'a>(
+        &'a self,
+        account_id: &'a str,
+        watermark: &'a WatermarkCursor,
+        range: &'a DateRange,
+    ) -> BoxStream<'a, Result<responses::inner::Posts>> {
+        stream::try_unfold(Some(String::from("0")), move |before_post_id| async move {
+            let before_post_id = match before_post_id {
+                Some(before_post_id) => before_post_id,
+                None => return Ok(None),
+            };
             let endpoint = format!(
                 "{}/{}?before={}&after=0",
                 constants::TIMELINE_URL,
@@ -188,38 +237,48 @@ impl Fansly {
                 before_post_id
             );
             log::debug!("Endpoint: [{}]", endpoint);
-            let response = self
+            let post_response = self
                 .http_client
                 .get(&endpoint, self.get_default_headers())
-                .await;
-            match response {
-                Ok(post_response) => {
-                    let post_response: responses::PostsResponse = post_response.as_json().await?;
-                    if post_response.response.account_media.is_none()
-                        && post_response.response.account_media_bundles.is_none()
-                        && post_response.response.aggregated_posts.is_none()
-                        && post_response.response.posts.is_none()
-                        && post_response.response.stories.is_none()
-                    {
-                        break;
-                    }
-                    if let Some(user_posts) = &post_response.response.posts {
-                        if user_posts.len() < constants::POSTS_LIMIT_COUNT as usize
-                            && user_posts.is_empty()
-                        {
-                            more_pages = false
-                        }
-                        if let Some(last_post) = user_posts.iter().last() {
-                            before_post_id = last_post.id.to_string();
-                        };
-                        posts.push(post_response.response);
-                    }
+                .await?;
+            let post_response: responses::PostsResponse = post_response.as_json().await?;
+            if post_response.response.account_media.is_none()
+                && post_response.response.account_media_bundles.is_none()
+                && post_response.response.aggregated_posts.is_none()
+                && post_response.response.posts.is_none()
+                && post_response.response.stories.is_none()
+            {
+                return Ok(None);
+            }
+            let user_posts = match &post_response.response.posts {
+                Some(user_posts) if !user_posts.is_empty() => user_posts,
+                // Without posts there is nothing to page on from
+                _ => return Ok(Some((post_response.response, None))),
+            };
+            let mut next_before = user_posts
+                .iter()
+                .last()
+                .map(|last_post| last_post.id.to_string());
+            for post in user_posts {
+                let created_at = media_timestamp(post.created_at);
+                if range.is_past(created_at) {
+                    log::debug!(
+                        "Reached the start of the date range for posts from {}",
+                        account_id
+                    );
+                    next_before = None;
+                    break;
+                }
+                if watermark.is_seen(&post.id, created_at) {
+                    log::debug!("Reached the watermark for posts from {}", account_id);
+                    next_before = None;
+                    break;
                 }
-                Err(err) => return Err(err),
+                watermark.observe(&post.id, created_at);
             }
-        }
-
-        Ok(posts)
+            Ok(Some((post_response.response, next_before)))
+        })
+        .boxed()
     }
 
     pub async fn get_account_subscriptions(&self) -> Result<Vec<Subscription>> {
@@ -346,60 +405,100 @@ impl Fansly {
         }
     }
 
+    /// Walk a group's messages newest first, stops at the first message covered by `watermark`
     pub async fn get_all_messages_from_group(
         &self,
         group_id: &'_ str,
+        watermark: &'_ WatermarkCursor,
+        range: &'_ DateRange,
     ) -> Result<Vec<structs::Message>> {
-        let mut messages = Vec::new();
-        let mut has_more = true;
-        let mut before: Option<i64> = None;
-
-        while has_more {
-            let endpoint = if let Some(before) = before {
-                format!(
-                    "{}?groupId={}&limit={}&before={}",
-                    constants::GROUP_MESSAGES_URL,
-                    &group_id,
-                    constants::GROUP_MESSAGES_LIMIT,
-                    before
-                )
-            } else {
-                format!(
-                    "{}?groupId={}&limit={}",
-                    constants::GROUP_MESSAGES_URL,
-                    &group_id,
-                    constants::GROUP_MESSAGES_LIMIT
-                )
-            };
-            let resp_res = self
-                .http_client
-                .get(&endpoint, self.get_default_headers())
-                .await;
+        self.stream_messages_from_group(group_id.to_string(), watermark, range)
+            .try_concat()
+            .await
+    }
 
-            match resp_res {
-                Ok(resp) => {
-                    let mut group_messages: responses::GroupMessagesResponse =
-                        resp.as_json().await?;
-                    log::debug!(
-                        "Response for thread {}. {:?}",
-                        group_id,
-                        group_messages.response
-                    );
-                    if let Some(last_message) = group_messages.response.messages.iter().last() {
-                        before = Some(last_message.created_at);
+    /// [`Fansly::get_all_messages_from_group`] a page at a time
+    pub fn stream_messages_from_group<'a>(
+        &'a self,
+        group_id: String,
+        watermark: &'a WatermarkCursor,
+        range: &'a DateRange,
+    ) -> BoxStream<'a, Result<Vec<structs::Message>>> {
+        stream::try_unfold(Some(None), move |before: Option<Option<i64>>| {
+            let group_id = group_id.clone();
+            async move {
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(None),
+                };
+                let endpoint = if let Some(before) = before {
+                    format!(
+                        "{}?groupId={}&limit={}&before={}",
+                        constants::GROUP_MESSAGES_URL,
+                        &group_id,
+                        constants::GROUP_MESSAGES_LIMIT,
+                        before
+                    )
+                } else {
+                    format!(
+                        "{}?groupId={}&limit={}",
+                        constants::GROUP_MESSAGES_URL,
+                        &group_id,
+                        constants::GROUP_MESSAGES_LIMIT
+                    )
+                };
+                let resp = self
+                    .http_client
+                    .get(&endpoint, self.get_default_headers())
+                    .await
+                    .map_err(|message_err| {
+                        log::error!(
+                            "Failed to get messages from group {}. {:?}",
+                            group_id,
+                            message_err
+                        );
+                        message_err
+                    })?;
+                let group_messages: responses::GroupMessagesResponse = resp.as_json().await?;
+                log::debug!(
+                    "Response for thread {}. {:?}",
+                    group_id,
+                    group_messages.response
+                );
+                let mut next_before = match group_messages.response.messages.iter().last() {
+                    Some(last_message)
+                        if group_messages.response.messages.len()
+                            == constants::GROUP_MESSAGES_LIMIT as usize =>
+                    {
+                        Some(Some(last_message.created_at))
+                    }
+                    _ => None,
+                };
+                let mut messages = Vec::new();
+                for message in group_messages.response.messages {
+                    let created_at = media_timestamp(message.created_at);
+                    if range.is_past(created_at) {
+                        log::debug!(
+                            "Reached the start of the date range for messages in {}",
+                            group_id
+                        );
+                        next_before = None;
+                        break;
+                    }
+                    if watermark.is_seen(&message.id, created_at) {
+                        log::debug!("Reached the watermark for messages in {}", group_id);
+                        next_before = None;
+                        break;
+                    }
+                    watermark.observe(&message.id, created_at);
+                    if range.contains(created_at) {
+                        messages.push(message);
                     }
-                    has_more = group_messages.response.messages.len()
-                        == constants::GROUP_MESSAGES_LIMIT as usize;
-                    messages.append(&mut group_messages.response.messages);
                 }
-                Err(message_err) => log::error!(
-                    "Failed to get messages from group {}. {:?}",
-                    group_id,
-                    message_err
-                ),
+                Ok(Some((messages, next_before)))
             }
-        }
-        Ok(messages)
+        })
+        .boxed()
     }
 
     pub async fn get_purchased_content(&self) -> Result<Vec<structs::PurchasedMedia>> {
@@ -417,35 +516,19 @@ impl Fansly {
             let endpoint = format!(
                 "/api/v1/account/wallets/transactions?before=&after=&limit=10&offset={offset}"
             );
-            let result = self
+            // A failed page would be asked for again forever, give up on the first one
+            let resp = self
                 .http_client
                 .get(&endpoint, self.get_default_headers())
-                .await;
-
-            match result {
-                Ok(resp) => {
-                    let transactions: Result<responses::WalletTransactionsResponse> =
-                        resp.as_json().await;
-                    match transactions {
-                        Ok(mut transactions) => {
-                            offset += transactions.response.data.len();
-                            all_transactions.append(&mut transactions.response.data);
-                            if all_transactions.len() >= transactions.response.total as usize {
-                                break;
-                            }
-                        }
-                        Err(json_err) => {
-                            log::error!("Transaction data not in expected format. {:?}", json_err)
-                        }
-                    }
-                }
-                Err(err) => {
-                    log::error!(
-                        "Unable to get transaction data from {}. {:?}",
-                        endpoint,
-                        err
-                    )
-                }
+                .await?;
+            let mut transactions: responses::WalletTransactionsResponse = resp.as_json().await?;
+            if transactions.response.data.is_empty() {
+                break;
+            }
+            offset += transactions.response.data.len();
+            all_transactions.append(&mut transactions.response.data);
+            if all_transactions.len() >= transactions.response.total as usize {
+                break;
             }
         }
 
@@ -484,13 +567,24 @@ fn combine_subs_and_account_info(
                             display_name: info.display_name.to_owned(),
                         },
                         plan: String::from(sub_tier),
-                        started: Some(Utc.timestamp_millis(sub.created_at).into()),
-                        renewal_date: Some(Utc.timestamp_millis(sub.renew_date).into()),
+                        started: Utc
+                            .timestamp_millis_opt(sub.created_at)
+                            .single()
+                            .map(Into::into),
+                        renewal_date: Utc
+                            .timestamp_millis_opt(sub.renew_date)
+                            .single()
+                            .map(Into::into),
                         rewewal_price: (sub.price as f64).into(),
-                        ends_at: Some(Utc.timestamp_millis(sub.ends_at).into()),
+                        ends_at: Utc
+                            .timestamp_millis_opt(sub.ends_at)
+                            .single()
+                            .map(Into::into),
                         video_count,
                         image_count,
                         bundle_count,
+                        watermark: Default::default(),
+                        date_range: Default::default(),
                     })
                 }
                 None => None,
@@ -499,27 +593,46 @@ fn combine_subs_and_account_info(
         .collect()
 }
 
+/// Media timestamps are in seconds where most other Fansly timestamps are in milliseconds, accept either
+pub(crate) fn media_timestamp(timestamp: i64) -> Option<DateTime<Utc>> {
+    if timestamp > 100_000_000_000 {
+        Utc.timestamp_millis_opt(timestamp).single()
+    } else {
+        Utc.timestamp_opt(timestamp, 0).single()
+    }
+}
+
 fn fansly_media_to_gatherers_media(
     media: structs::Media,
     user_name: &'_ str,
 ) -> Option<gatherers::Media> {
     log::trace!("Converting to gatherer_core::Media. {:?}", media);
     if let Some(details) = &media.details {
-        if let Some(location) = &details.locations.get(0) {
+        if let Some(location) = &details.locations.first() {
             Some(gatherers::Media {
+                id: media.id.clone(),
+                created_at: media_timestamp(media.created_at),
+                expires_at: None,
                 file_name: if let Some(filename) = &details.file_name {
                     filename.clone()
                 } else {
                     format!(
                         "{}.{}",
                         details.id,
-                        details.mimetype.split('/').last().unwrap()
+                        details.mimetype.split('/').next_back().unwrap()
                     )
                 },
                 paid: media.purchased,
                 mime_type: details.mimetype.to_string(),
+                kind: gatherers::MediaKind::from_mime_type(&details.mimetype),
                 url: location.location.clone(),
                 user_name: user_name.to_string(),
+                width: details.width(),
+                height: details.height(),
+                duration: details.duration(),
+                size: None,
+                origin: None,
+                purchased_at: None,
             })
         } else {
             log::debug!("Unable to determine a location for {:?}", details.file_name);
@@ -49,6 +49,8 @@ pub(crate) mod inner {
         pub stats: crate::structs::SubscriptionStats,
     }
 
+    // Not requested yet, kept to mirror the API
+    #[allow(dead_code)]
     #[derive(Debug, Serialize, Deserialize)]
     pub struct PurchasedContent {
         #[serde(rename = "accountMediaOrders")]
@@ -61,6 +63,8 @@ pub(crate) mod inner {
         pub data: Vec<T>,
     }
 
+    // Not requested yet, kept to mirror the API
+    #[allow(dead_code)]
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Status {
         #[serde(rename = "accountId")]
@@ -62,6 +62,30 @@ pub struct MediaDetails {
     pub locations: Vec<Location>,
 }
 
+impl MediaDetails {
+    pub fn width(&self) -> Option<u32> {
+        self.width.and_then(|width| u32::try_from(width).ok())
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        self.height.and_then(|height| u32::try_from(height).ok())
+    }
+
+    /// Videos and audio carry their length in the JSON encoded `metadata`
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        let metadata: MediaMetadata = serde_json::from_str(self.metadata.as_deref()?).ok()?;
+        metadata
+            .duration
+            .filter(|duration| duration.is_finite() && *duration > 0.)
+            .map(std::time::Duration::from_secs_f64)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MediaMetadata {
+    duration: Option<f64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Location {
     #[serde(rename = "locationId")]
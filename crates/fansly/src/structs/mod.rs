@@ -1,11 +1,11 @@
-mod account;
-mod media;
-mod media_bundle;
-mod message;
-mod post;
-mod story;
-mod subscription;
-mod transaction;
+pub mod account;
+pub mod media;
+pub mod media_bundle;
+pub mod message;
+pub mod post;
+pub mod story;
+pub mod subscription;
+pub mod transaction;
 
 pub use account::{Account, FollowedAccount};
 pub use media::{Media, MediaDetails, PurchasedMedia};
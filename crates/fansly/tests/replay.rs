@@ -0,0 +1,80 @@
+use {
+    futures::executor::block_on,
+    gatherer_core::{
+        gatherers::{
+            Gatherer, GathererErrors, MediaKind, MediaOriginKind, Subscription, SubscriptionName,
+        },
+        http::CassetteConfig,
+    },
+    gatherer_fansly::{Fansly, FanslyConfig},
+    std::path::PathBuf,
+};
+
+fn cassette(name: &'_ str) -> CassetteConfig {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "cassettes", name]
+        .iter()
+        .collect();
+    CassetteConfig::replay(path)
+}
+
+fn config(cassette_name: &'_ str) -> FanslyConfig {
+    FanslyConfig {
+        enabled: true,
+        auth_token: "not-a-real-token".to_string(),
+        cassette: Some(cassette(cassette_name)),
+        ..Default::default()
+    }
+}
+
+fn subscription() -> Subscription {
+    Subscription {
+        id: "100".to_string(),
+        name: SubscriptionName {
+            username: "creator".to_string(),
+            display_name: None,
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn gathers_media_from_posts() {
+    block_on(async {
+        let fansly = Fansly::new(config("posts.json")).await.unwrap();
+        let media = fansly
+            .gather_media_from_posts(&subscription())
+            .await
+            .unwrap();
+
+        assert_eq!(media.len(), 2);
+        let image = media.iter().find(|media| media.id == "300").unwrap();
+        assert_eq!(image.kind, MediaKind::Image);
+        assert_eq!(image.url, "https://cdn3.fansly.com/100/400.jpeg");
+        assert_eq!(image.file_name, "400.jpeg");
+        assert_eq!(image.user_name, "creator");
+        assert!(!image.paid);
+        let origin = image.origin.as_ref().unwrap();
+        assert_eq!(origin.kind, MediaOriginKind::Post);
+        assert_eq!(origin.id, "200");
+
+        let video = media.iter().find(|media| media.id == "301").unwrap();
+        assert_eq!(video.kind, MediaKind::Video);
+        assert!(video.paid);
+        assert_eq!(
+            video.duration.map(|duration| duration.as_millis()),
+            Some(12_500)
+        );
+        assert_eq!(video.origin.as_ref().unwrap().id, "201");
+    })
+}
+
+#[test]
+fn rejected_token_is_reported_as_invalid_credentials() {
+    block_on(async {
+        let err = Fansly::new(config("invalid_token.json")).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<GathererErrors>(),
+            Some(GathererErrors::InvalidCredentials { name, .. }) if name == "Fansly"
+        ));
+    })
+}
@@ -1,13 +1,18 @@
 use {
     crate::{config::Config, get_available_gatherers},
     bpaf::*,
-    gatherer_core::Result,
+    chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc},
+    gatherer_core::{
+        gatherers::{ContentFilter, DateRange},
+        Result,
+    },
     std::{path::PathBuf, str::FromStr, sync::Arc},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum TransactionFormat {
     Json,
+    #[default]
     PlainText,
     Table,
 }
@@ -25,12 +30,6 @@ impl FromStr for TransactionFormat {
     }
 }
 
-impl Default for TransactionFormat {
-    fn default() -> Self {
-        Self::PlainText
-    }
-}
-
 #[derive(Debug, Clone, Bpaf)]
 #[bpaf(options)]
 pub struct Cli {
@@ -38,8 +37,12 @@ pub struct Cli {
     pub config_file_path: Option<PathBuf>,
     #[bpaf(external(verbose))]
     pub verbose: Option<usize>,
+    /// Only queue these kinds of media, sources and paid or free content, overrides the config
+    /// e.g. videos,messages,paid
     #[bpaf(long)]
-    pub content_types: Option<ContentTypes>,
+    pub content_types: Option<ContentFilter>,
+    // TODO: not wired up yet
+    #[allow(dead_code)]
     #[bpaf(short, long)]
     pub target_folder: Option<PathBuf>,
     #[bpaf(short, long)]
@@ -79,14 +82,50 @@ pub enum CliAction {
         worker_count: u8,
         #[bpaf(short, long)]
         limit_subs: Option<usize>,
+        /// Queue at most this many media items for each subscription, overrides the config
         #[bpaf(short, long)]
         limit_media: Option<usize>,
+        /// Queue at most this many media items for the whole run, overrides the config
+        #[bpaf(long)]
+        limit_media_total: Option<usize>,
         #[bpaf(short, long, fallback(Vec::new()))]
         ignored_user_names: Vec<String>,
+        /// Cap total download speed, overrides the config. e.g. 500K, 2M, 0 for no cap
+        #[bpaf(long)]
+        max_bandwidth: Option<ByteRate>,
+        /// Cap download speed per host, overrides the config. e.g. 500K, 2M, 0 for no cap
+        #[bpaf(long)]
+        max_host_bandwidth: Option<ByteRate>,
+        /// Only log, don't draw progress bars for the downloads
+        #[bpaf(long)]
+        no_progress: bool,
+        /// Walk every post and message again instead of stopping where the last run did
+        #[bpaf(long)]
+        full: bool,
+        /// Save a summary of what was gathered and downloaded as JSON
+        #[bpaf(long)]
+        report: Option<PathBuf>,
+        /// Only gather content from this date on. e.g. 2024-01-31, 7d
+        #[bpaf(long)]
+        since: Option<DateArg>,
+        /// Only gather content up to the end of this date. e.g. 2024-03-01, 1d
+        #[bpaf(long)]
+        until: Option<DateArg>,
     },
     #[bpaf(command("purchased"))]
     /// Gather only purchased content
-    Purchased,
+    Purchased {
+        /// Only gather purchases dated from this date on, by the post or message they came from unless the
+        /// site says when they were bought. e.g. 2024-01-31, 7d
+        #[bpaf(long)]
+        since: Option<DateArg>,
+        /// Only gather purchases dated up to the end of this date. e.g. 2024-03-01, 1d
+        #[bpaf(long)]
+        until: Option<DateArg>,
+        /// Only log, don't draw progress bars for the downloads
+        #[bpaf(long)]
+        no_progress: bool,
+    },
     /// Like posts from users you are subscribed to
     #[bpaf(command("like"))]
     Like {
@@ -118,24 +157,60 @@ pub enum CliAction {
 
 impl CliAction {
     // take ownership the action of self
-    pub async fn exec(self, conf: Arc<Config>, gatherers: &[String]) -> Result<()> {
+    pub async fn exec(
+        self,
+        conf: Arc<Config>,
+        gatherers: &[String],
+        content_types: Option<ContentFilter>,
+    ) -> Result<()> {
+        let content_filter = Arc::new(content_types.unwrap_or_else(|| conf.content_types.clone()));
         match self {
             CliAction::Start {
                 user_names,
                 worker_count,
                 limit_subs,
                 limit_media,
+                limit_media_total,
                 ignored_user_names,
+                max_bandwidth,
+                max_host_bandwidth,
+                no_progress,
+                full,
+                report,
+                since,
+                until,
             } => match get_available_gatherers(&conf, gatherers).await {
                 Ok(gatherers) => {
+                    let mut bandwidth = conf.bandwidth;
+                    if let Some(ByteRate(rate)) = max_bandwidth {
+                        bandwidth.max_bytes_per_sec = Some(rate);
+                    }
+                    if let Some(ByteRate(rate)) = max_host_bandwidth {
+                        bandwidth.max_host_bytes_per_sec = Some(rate);
+                    }
+                    let mut media_limits = conf.limits;
+                    if limit_media.is_some() {
+                        media_limits.per_subscription = limit_media;
+                    }
+                    if limit_media_total.is_some() {
+                        media_limits.per_run = limit_media_total;
+                    }
                     crate::cli_tasks::start(
                         gatherers,
                         &conf,
-                        worker_count,
-                        user_names,
-                        limit_media,
-                        limit_subs,
-                        ignored_user_names,
+                        crate::cli_tasks::StartOptions {
+                            worker_count,
+                            user_names,
+                            media_limits,
+                            limit_subs,
+                            ignored_user_names,
+                            bandwidth,
+                            show_progress: !no_progress,
+                            full_scan: full,
+                            report_path: report,
+                            content_filter,
+                            date_range: date_range(since, until)?,
+                        },
                     )
                     .await?;
                     Ok(())
@@ -148,6 +223,17 @@ impl CliAction {
             } => {
                 println!("Trying to like posts...");
                 log::debug!("Opts: {:?}, {:?}", like_all, like_user);
+                match get_available_gatherers(&conf, gatherers).await {
+                    Ok(gatherers) => match crate::cli_tasks::like(gatherers).await {
+                        Ok(_) => (),
+                        Err(err) => {
+                            log::error!("Error liking posts: {:?}", err);
+                        }
+                    },
+                    Err(gatherers_err) => {
+                        log::error!("Error getting available gatherers: {:?}", gatherers_err);
+                    }
+                }
                 Ok(())
             }
             CliAction::Unlike {
@@ -171,8 +257,19 @@ impl CliAction {
                 }
                 Ok(())
             }
-            CliAction::Purchased => match get_available_gatherers(&conf, gatherers).await {
-                Ok(gatherers) => Ok(crate::cli_tasks::purchased(gatherers, &conf).await?),
+            CliAction::Purchased {
+                since,
+                until,
+                no_progress,
+            } => match get_available_gatherers(&conf, gatherers).await {
+                Ok(gatherers) => Ok(crate::cli_tasks::purchased(
+                    gatherers,
+                    &conf,
+                    content_filter,
+                    date_range(since, until)?,
+                    !no_progress,
+                )
+                .await?),
                 Err(err) => Err(format!("Failed to get configured gatherers. {:?}", err).into()),
             },
             CliAction::List => match get_available_gatherers(&conf, gatherers).await {
@@ -199,43 +296,203 @@ impl Default for CliAction {
             worker_count: Default::default(),
             limit_subs: Default::default(),
             limit_media: Default::default(),
+            limit_media_total: Default::default(),
             user_names: Default::default(),
             ignored_user_names: Default::default(),
+            max_bandwidth: Default::default(),
+            max_host_bandwidth: Default::default(),
+            no_progress: Default::default(),
+            full: Default::default(),
+            report: Default::default(),
+            since: Default::default(),
+            until: Default::default(),
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum ContentTypes {
-    All,
-    Images,
-    Videos,
-    Messages,
+fn date_range(since: Option<DateArg>, until: Option<DateArg>) -> Result<DateRange> {
+    let since = since.map(DateArg::start);
+    let until = until.map(DateArg::end);
+    if let (Some(since), Some(until)) = (since, until) {
+        if since > until {
+            return Err(format!("--since {} is after --until {}", since, until).into());
+        }
+    }
+    Ok(DateRange::new(since, until))
 }
 
-impl FromStr for ContentTypes {
+/// Bytes per second, accepts a plain number or one with a `K`, `M` or `G` suffix. 0 is unlimited
+#[derive(Debug, Clone, Copy)]
+pub struct ByteRate(pub u64);
+
+impl FromStr for ByteRate {
     type Err = String;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let options: Vec<String> = ["All", "Images", "Videos", "Messages"]
-            .iter()
-            .map(|o| String::from(*o))
-            .collect();
-        match s.to_lowercase().as_str() {
-            "all" => Ok(Self::All),
-            "images" => Ok(Self::Images),
-            "videos" => Ok(Self::Videos),
-            "messages" => Ok(Self::Messages),
-            _ => Err(format!(
-                "'{}' is not a valid option. Valid: {:?}",
-                s, options
-            )),
+        let s = s.trim();
+        let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+            Some('K') => (&s[..s.len() - 1], 1024),
+            Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+            Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+            _ => (s, 1),
+        };
+        match number.trim().parse::<u64>() {
+            Ok(number) => number
+                .checked_mul(multiplier)
+                .map(Self)
+                .ok_or_else(|| format!("'{}' is too large a rate", s)),
+            _ => Err(format!("'{}' is not a valid rate. e.g. 500K, 2M", s)),
         }
     }
 }
 
-impl Default for ContentTypes {
-    fn default() -> Self {
-        Self::All
+/// A point in time, either a date like `2024-01-31`, a full RFC 3339 time or a span back from now like `7d`
+///
+/// Spans take an `h`, `d` or `w` suffix for hours, days and weeks. A date covers the whole day, it starts at
+/// midnight UTC when used for `--since` and runs until the end of the day for `--until`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateArg {
+    pub time: DateTime<Utc>,
+    /// Given as a date without a time
+    pub whole_day: bool,
+}
+
+impl DateArg {
+    /// The first moment covered, used for `--since`
+    pub fn start(self) -> DateTime<Utc> {
+        self.time
+    }
+
+    /// The last moment covered, used for `--until`
+    pub fn end(self) -> DateTime<Utc> {
+        if self.whole_day {
+            let end_of_day =
+                NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999).expect("a valid time of day");
+            self.time.date_naive().and_time(end_of_day).and_utc()
+        } else {
+            self.time
+        }
+    }
+}
+
+impl FromStr for DateArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self {
+                time: time.with_timezone(&Utc),
+                whole_day: false,
+            });
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Self {
+                time: date.and_time(NaiveTime::MIN).and_utc(),
+                whole_day: true,
+            });
+        }
+        let invalid = || {
+            format!(
+                "'{}' is not a valid date. e.g. 2024-01-31, 2024-01-31T12:00:00Z, 7d",
+                s
+            )
+        };
+        let to_span: fn(i64) -> Option<Duration> =
+            match s.chars().last().map(|c| c.to_ascii_lowercase()) {
+                Some('h') => Duration::try_hours,
+                Some('d') => Duration::try_days,
+                Some('w') => Duration::try_weeks,
+                _ => return Err(invalid()),
+            };
+        // Spans always reach back from now, a sign would only make `-7d` look like it goes the other way
+        let count = s[..s.len() - 1].trim();
+        if !count.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let count: u32 = count.parse().map_err(|_| invalid())?;
+        to_span(count.into())
+            .and_then(|span| Utc::now().checked_sub_signed(span))
+            .map(|time| Self {
+                time,
+                whole_day: false,
+            })
+            .ok_or_else(|| format!("'{}' is too far back", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{date_range, ByteRate, DateArg},
+        chrono::{Duration, TimeZone, Utc},
+        std::str::FromStr,
+    };
+
+    #[test]
+    fn byte_rates_take_a_suffix() {
+        assert_eq!(ByteRate::from_str("500").unwrap().0, 500);
+        assert_eq!(ByteRate::from_str("500k").unwrap().0, 500 * 1024);
+        assert_eq!(ByteRate::from_str(" 2M ").unwrap().0, 2 * 1024 * 1024);
+        assert_eq!(ByteRate::from_str("1G").unwrap().0, 1024 * 1024 * 1024);
+        assert_eq!(ByteRate::from_str("0").unwrap().0, 0);
+        assert!(ByteRate::from_str("K").is_err());
+        assert!(ByteRate::from_str("-1M").is_err());
+        // Overflows instead of wrapping around
+        assert!(ByteRate::from_str(&format!("{}G", u64::MAX / 1024)).is_err());
+    }
+
+    #[test]
+    fn a_date_covers_the_whole_day() {
+        let date = DateArg::from_str("2024-01-31").unwrap();
+        assert_eq!(
+            date.start(),
+            Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            date.end(),
+            Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap() - Duration::nanoseconds(1)
+        );
+
+        let time = DateArg::from_str("2024-01-31T12:00:00+02:00").unwrap();
+        assert_eq!(
+            time.start(),
+            Utc.with_ymd_and_hms(2024, 1, 31, 10, 0, 0).unwrap()
+        );
+        assert_eq!(time.start(), time.end());
+    }
+
+    #[test]
+    fn spans_count_back_from_now() {
+        let before = Utc::now();
+        let week = DateArg::from_str("2w").unwrap();
+        assert!(!week.whole_day);
+        assert!(week.start() >= before - Duration::weeks(2));
+        assert!(week.start() <= Utc::now() - Duration::weeks(2));
+        assert!(DateArg::from_str("12H").is_ok());
+
+        assert!(DateArg::from_str("7").is_err());
+        assert!(DateArg::from_str("d").is_err());
+        assert!(DateArg::from_str("7y").is_err());
+        assert!(DateArg::from_str("2024-02-30").is_err());
+        // Spans only go back, a sign isn't a direction
+        assert!(DateArg::from_str("-7d").is_err());
+        assert!(DateArg::from_str("+7d").is_err());
+        assert!(DateArg::from_str("- 7d").is_err());
+        // Too large for a duration, or for a date once taken from now
+        assert!(DateArg::from_str(&format!("{}w", i64::MAX)).is_err());
+        assert!(DateArg::from_str("100000000d").is_err());
+    }
+
+    #[test]
+    fn since_after_until_is_rejected() {
+        let day = DateArg::from_str("2024-01-31").unwrap();
+        // The same day on both ends is the whole day
+        let range = date_range(Some(day), Some(day)).unwrap();
+        assert!(range.contains(Some(Utc.with_ymd_and_hms(2024, 1, 31, 23, 0, 0).unwrap())));
+
+        let next_day = DateArg::from_str("2024-02-01").unwrap();
+        assert!(date_range(Some(next_day), Some(day)).is_err());
+        assert!(date_range(Some(next_day), None).is_ok());
     }
 }
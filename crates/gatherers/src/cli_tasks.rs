@@ -1,97 +1,148 @@
 use {
-    crate::{cli::TransactionFormat, config::Config},
+    crate::{cli::TransactionFormat, config::Config, progress::show_download_progress},
+    async_channel::Receiver,
+    async_task::Task,
     gatherer_core::{
-        downloaders::{BatchDownloader, MultiThreadedDownloader},
-        gatherers::{self, Gatherer},
+        downloaders::{
+            BandwidthLimiter, BandwidthLimits, BatchDownloader, BatchDownloaderBuilder,
+            BatchStrategy, Downloadable, MultiThreadedDownloader, ProgressEvents,
+            SequentialDownloader,
+        },
+        gatherers::{
+            self, find_gatherer, Capability, ContentFilter, DateRange, Gatherer, MediaBudget,
+            MediaLimits, RunSummary,
+        },
+        state::{DeadLetters, DownloadState, Watermarks},
         tasks::spawn_on_thread,
         Result,
     },
-    std::{collections::HashMap, path::Path, sync::Arc, time::Instant},
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::Instant,
+    },
 };
 
+/// Options for the `start` command, config values have already been merged with anything given on the command line
+#[derive(Debug, Clone)]
+pub struct StartOptions {
+    pub worker_count: u8,
+    pub user_names: Vec<String>,
+    pub media_limits: MediaLimits,
+    pub limit_subs: Option<usize>,
+    pub ignored_user_names: Vec<String>,
+    pub bandwidth: BandwidthLimits,
+    /// Draw progress bars for the downloads
+    pub show_progress: bool,
+    /// Ignore the watermarks and gather everything, the watermarks are still moved forward
+    pub full_scan: bool,
+    /// Where to save the run summary as JSON
+    pub report_path: Option<PathBuf>,
+    pub content_filter: Arc<ContentFilter>,
+    pub date_range: DateRange,
+}
+
 pub async fn start(
     cur_gatherers: Vec<Arc<dyn Gatherer + 'static>>,
     app_config: &'_ Config,
-    worker_count: u8,
-    user_names: Vec<String>,
-    limit_media: Option<usize>,
-    limit_subs: Option<usize>,
-    ignored_user_names: Vec<String>,
+    opts: StartOptions,
 ) -> Result<()> {
+    let StartOptions {
+        worker_count,
+        user_names,
+        media_limits,
+        limit_subs,
+        ignored_user_names,
+        bandwidth,
+        show_progress,
+        full_scan,
+        report_path,
+        content_filter,
+        date_range,
+    } = opts;
     if !cur_gatherers.is_empty() {
+        // Fail on a bad template before anything is requested
+        let templates = Arc::new(app_config.paths.build()?);
+        let priorities = Arc::new(app_config.priority.clone());
         let (tx, rx) = async_channel::unbounded();
-        // Start our downloader with our channel receiver
-        // TODO: downloader should be configurable, options are there just need codify
-        let downloader = MultiThreadedDownloader::new(worker_count, rx);
-        // This will be the base path to our downloader, it will be exactly what the user has provided in their config
-        let downloads_directory = Path::new(&app_config.download_dir).to_path_buf();
+        // Items saved by earlier runs are skipped by both the gatherers and the downloader
+        let state = Arc::new(load_download_state());
+        let dead_letters = Arc::new(load_dead_letters());
+        requeue_dead_letters(&dead_letters, &tx);
+        let watermarks = Arc::new(load_watermarks());
+        let summary = Arc::new(Mutex::new(RunSummary::default()));
+        let media_budget = Arc::new(MediaBudget::new(media_limits));
         // holds our configured tasks, they will start at the same time during
         // the join all which will also wait for them to complete
         let mut primary_threads = Vec::new();
+        // Start our downloader with our channel receiver
+        let downloader = build_downloader(
+            app_config,
+            rx,
+            DownloaderSetup {
+                worker_count,
+                bandwidth,
+                state: state.clone(),
+                dead_letters,
+                events: show_progress.then(|| watch_download_progress(&mut primary_threads)),
+            },
+        );
+        // This will be the base path to our downloader, it will be exactly what the user has provided in their config
+        let downloads_directory = Path::new(&app_config.download_dir).to_path_buf();
 
         // For each initialized gatherer start a new thread that will run the gatherer logic from start to finish
         for gatherer in cur_gatherers.into_iter() {
             primary_threads.push(spawn_on_thread({
-                let base_path = downloads_directory.clone();
                 let download_tx = tx.clone();
-                let limits = gatherers::RunLimits {
-                    media: limit_media,
-                    subscriptions: limit_subs,
-                };
-                let user_names = user_names.clone();
-                let mut fansly_ignored_users = app_config.fansly.ignore_lists.clone();
-                let mut onlyfans_ignored_users = app_config.onlyfans.ignore_lists.clone();
                 let mut ignored_user_names = ignored_user_names.clone();
-                ignored_user_names.append(&mut fansly_ignored_users);
-                ignored_user_names.append(&mut onlyfans_ignored_users);
+                ignored_user_names.append(&mut app_config.ignored_user_names());
+                let run_opts = gatherers::RunOptions {
+                    base_path: downloads_directory.clone(),
+                    limits: gatherers::RunLimits {
+                        media: media_budget.clone(),
+                        subscriptions: limit_subs,
+                    },
+                    user_names: user_names.clone(),
+                    ignored_user_names,
+                    state: Some(state.clone()),
+                    templates: templates.clone(),
+                    priorities: priorities.clone(),
+                    watermarks: Some(watermarks.clone()),
+                    full_scan,
+                    sidecars: app_config.sidecars,
+                    content_filter: content_filter.clone(),
+                    date_range,
+                };
+                let summary = summary.clone();
                 async move {
                     let gatherer_name = gatherer.name();
                     let start_time = Instant::now();
                     // Now that we have everything setup we can hand off the majority of the logic to the main func
-                    match gatherers::run_gatherer_for_all(
-                        gatherer,
-                        base_path,
-                        download_tx,
-                        limits,
-                        &user_names,
-                        &ignored_user_names,
-                    )
-                    .await
-                    {
-                        Ok(_) => println!(
-                            "{gatherer_name}: Finished after {:.2} seconds",
-                            Instant::now().duration_since(start_time).as_secs_f64()
-                        ),
-                        Err(gatherer_err) => {
-                            log::error!("{gatherer_name}: Failed to complete. {:?}", gatherer_err)
-                        }
-                    }
+                    let gatherer_summary =
+                        gatherers::run_gatherer_for_all(gatherer, download_tx, run_opts).await;
+                    println!(
+                        "{gatherer_name}: Finished after {:.2} seconds",
+                        Instant::now().duration_since(start_time).as_secs_f64()
+                    );
+                    summary.lock().unwrap().merge(gatherer_summary);
                 }
             }));
         }
 
         // Spawn a new thread to handle downloading our content as it comes in
-        primary_threads.push(spawn_on_thread(async move {
-            println!("Starting {} downloader..", downloader);
-            let start_time = Instant::now();
-            // Start the main process function
-            match downloader.process_all_items().await {
-                Ok(stats) => {
-                    let time_taken = Instant::now().duration_since(start_time);
-                    log::info!(
-                        "Successfully completed downloads: {:?}. Took {:?}",
-                        stats,
-                        time_taken
-                    );
-                }
-                Err(down_err) => log::error!("Failed to process downloads: {:?}", down_err),
-            }
-        }));
+        primary_threads.push(spawn_downloader(downloader, summary.clone()));
 
         // drop our initial send chan so the receiver can properly detect the end
         drop(tx);
         // block our program exit until all of our work is complete
         futures::future::join_all(primary_threads).await;
+        finish_run(&summary.lock().unwrap(), report_path.as_deref());
+        // Only gathers that finished moved their watermark, so saving after a partial run is safe
+        watermarks.commit_pending();
+        if let Err(save_err) = watermarks.save() {
+            log::error!("Failed to save the watermarks. {:?}", save_err);
+        }
 
         Ok(())
     } else {
@@ -99,54 +150,157 @@ pub async fn start(
     }
 }
 
+/// What `start` and `purchased` set their downloader up with, on top of the config
+struct DownloaderSetup {
+    worker_count: u8,
+    bandwidth: BandwidthLimits,
+    state: Arc<DownloadState>,
+    dead_letters: Arc<DeadLetters>,
+    events: Option<Arc<ProgressEvents>>,
+}
+
+/// Build the batch downloader picked in the `[downloader]` config section
+fn build_downloader(
+    app_config: &'_ Config,
+    rx: Receiver<Downloadable>,
+    setup: DownloaderSetup,
+) -> Box<dyn BatchDownloader> {
+    match app_config.downloader.strategy {
+        BatchStrategy::Sequential => {
+            configure_downloader(SequentialDownloader::new(rx), app_config, setup)
+        }
+        BatchStrategy::MultiThreaded => configure_downloader(
+            MultiThreadedDownloader::new(setup.worker_count, rx),
+            app_config,
+            setup,
+        ),
+    }
+}
+
+/// Give either batch downloader the options from the config and the setup
+fn configure_downloader<D: BatchDownloaderBuilder + 'static>(
+    downloader: D,
+    app_config: &'_ Config,
+    setup: DownloaderSetup,
+) -> Box<dyn BatchDownloader> {
+    let mut downloader = downloader
+        .with_state(setup.state)
+        .with_retry_policy(app_config.retry.clone())
+        .with_dead_letters(setup.dead_letters)
+        .with_file_downloader(app_config.downloader.file.clone())
+        .with_proxies(app_config.proxy_routes());
+    if !setup.bandwidth.is_unlimited() {
+        downloader =
+            downloader.with_bandwidth_limiter(Arc::new(BandwidthLimiter::new(setup.bandwidth)));
+    }
+    if let Some(content_store) = app_config
+        .content_store
+        .build(Path::new(&app_config.download_dir))
+    {
+        downloader = downloader.with_content_store(Arc::new(content_store));
+    }
+    if let Some(events) = setup.events {
+        downloader = downloader.with_progress_events(events);
+    }
+    Box::new(downloader)
+}
+
+/// Start drawing progress bars, the returned events are handed to the downloader
+fn watch_download_progress(primary_threads: &'_ mut Vec<Task<()>>) -> Arc<ProgressEvents> {
+    let events = Arc::new(ProgressEvents::new());
+    primary_threads.push(show_download_progress(events.subscribe()));
+    events
+}
+
 pub async fn purchased(
     cur_gatherers: Vec<Arc<dyn Gatherer + 'static>>,
     app_config: &'_ Config,
+    content_filter: Arc<ContentFilter>,
+    date_range: DateRange,
+    show_progress: bool,
 ) -> Result<()> {
+    // Fail on a bad template before anything is requested
+    let templates = Arc::new(app_config.paths.build()?);
+    let priorities = Arc::new(app_config.priority.clone());
     let (tx, rx) = async_channel::unbounded();
-    // Start our downloader with our channel receiver
-    // TODO: downloader should be configurable, options are there just need codify
-    let downloader = MultiThreadedDownloader::new(app_config.workers, rx);
-    // This will be the base path to our downloader, it will be exactly what the user has provided in their config
-    let downloads_directory = Path::new(&app_config.download_dir).to_path_buf();
+    let state = Arc::new(load_download_state());
+    let dead_letters = Arc::new(load_dead_letters());
+    requeue_dead_letters(&dead_letters, &tx);
+    let summary = Arc::new(Mutex::new(RunSummary::default()));
+    let media_budget = Arc::new(MediaBudget::new(app_config.limits));
     // holds our configured tasks, they will start at the same time during
     // the join all which will also wait for them to complete
     let mut primary_threads = Vec::new();
+    // Start our downloader with our channel receiver
+    let downloader = build_downloader(
+        app_config,
+        rx,
+        DownloaderSetup {
+            worker_count: app_config.workers,
+            bandwidth: app_config.bandwidth,
+            state: state.clone(),
+            dead_letters,
+            events: show_progress.then(|| watch_download_progress(&mut primary_threads)),
+        },
+    );
+    // This will be the base path to our downloader, it will be exactly what the user has provided in their config
+    let downloads_directory = Path::new(&app_config.download_dir).to_path_buf();
 
     for gatherer in cur_gatherers.into_iter() {
         primary_threads.push(spawn_on_thread({
-            let base_path = downloads_directory.clone();
             let download_tx = tx.clone();
+            let options = gatherers::RunOptions {
+                base_path: downloads_directory.clone(),
+                limits: gatherers::RunLimits {
+                    media: media_budget.clone(),
+                    subscriptions: None,
+                },
+                state: Some(state.clone()),
+                templates: templates.clone(),
+                priorities: priorities.clone(),
+                sidecars: app_config.sidecars,
+                content_filter: content_filter.clone(),
+                date_range,
+                ..Default::default()
+            };
+            let summary = summary.clone();
             async move {
                 let gatherer_name = gatherer.name();
-                let start_time = Instant::now();
-                // Start building output directory for our gatherer
-                let base_path = base_path.clone().join(gatherer.name().to_ascii_lowercase());
                 // Now that we have everything setup we can hand off the majority of the logic to the main func
-                match gatherers::run_gatherer(gatherers::GathererInfo {
-                    base_path,
+                let report = gatherers::run_gatherer(gatherers::GathererInfo {
                     gather_type: gatherers::GatherType::Purchased,
                     gatherer,
                     subscription: Default::default(),
                     downloader: download_tx,
                     name: gatherer_name.to_string(),
+                    options,
                 })
-                .await
-                {
-                    Ok(_) => println!(
-                        "{gatherer_name}: Finished after {:.2} seconds",
-                        Instant::now().duration_since(start_time).as_secs_f64()
-                    ),
-                    Err(gatherer_err) => {
-                        log::error!("{gatherer_name}: Failed to complete. {:?}", gatherer_err)
-                    }
-                }
+                .await;
+                println!(
+                    "{gatherer_name}: Finished after {:.2} seconds",
+                    report.duration.as_secs_f64()
+                );
+                summary.lock().unwrap().add(report);
             }
         }));
     }
 
     // Spawn a new thread to handle downloading our content as it comes in
-    primary_threads.push(spawn_on_thread(async move {
+    primary_threads.push(spawn_downloader(downloader, summary.clone()));
+
+    // drop our initial send chan so the receiver can properly detect the end
+    drop(tx);
+    futures::future::join_all(primary_threads).await;
+    finish_run(&summary.lock().unwrap(), None);
+    Ok(())
+}
+
+/// Run the downloader on its own thread, its stats are added to the summary once the queue is empty
+fn spawn_downloader(
+    downloader: Box<dyn BatchDownloader>,
+    summary: Arc<Mutex<RunSummary>>,
+) -> Task<()> {
+    spawn_on_thread(async move {
         println!("Starting {} downloader..", downloader);
         let start_time = Instant::now();
         // Start the main process function
@@ -158,15 +312,90 @@ pub async fn purchased(
                     stats,
                     time_taken
                 );
+                summary.lock().unwrap().downloads = Some(stats);
             }
             Err(down_err) => log::error!("Failed to process downloads: {:?}", down_err),
         }
-    }));
+    })
+}
 
-    // drop our initial send chan so the receiver can properly detect the end
-    drop(tx);
-    futures::future::join_all(primary_threads).await;
-    Ok(())
+/// Print what the run did and save it as JSON when a report path was given
+fn finish_run(summary: &'_ RunSummary, report_path: Option<&'_ Path>) {
+    print!("{}", summary);
+    if let Some(report_path) = report_path {
+        match summary.save(report_path) {
+            Ok(_) => println!("Saved the run report to {:?}", report_path),
+            Err(save_err) => log::error!("Failed to save the run report. {:?}", save_err),
+        }
+    }
+}
+
+/// Load the record of previously saved items, a broken state file shouldn't stop a run from happening
+fn load_download_state() -> DownloadState {
+    match DownloadState::load_default() {
+        Ok(state) => {
+            log::debug!("Loaded {} previously saved items", state.len());
+            state
+        }
+        Err(state_err) => {
+            log::error!(
+                "Failed to load the download state, starting fresh. {:?}",
+                state_err
+            );
+            DownloadState::default()
+        }
+    }
+}
+
+/// Load how far each subscription was gathered, without them the run walks everything
+fn load_watermarks() -> Watermarks {
+    match Watermarks::load_default() {
+        Ok(watermarks) => {
+            log::debug!("Loaded {} watermarks", watermarks.len());
+            watermarks
+        }
+        Err(watermarks_err) => {
+            log::error!(
+                "Failed to load the watermarks, gathering everything. {:?}",
+                watermarks_err
+            );
+            Watermarks::default()
+        }
+    }
+}
+
+/// Load the items that ran out of retries in an earlier run, same as the state a broken file is not fatal
+fn load_dead_letters() -> DeadLetters {
+    match DeadLetters::load_default() {
+        Ok(dead_letters) => dead_letters,
+        Err(letters_err) => {
+            log::error!(
+                "Failed to load the dead letters, starting fresh. {:?}",
+                letters_err
+            );
+            DeadLetters::default()
+        }
+    }
+}
+
+/// Put every dead letter back on the download queue ahead of anything the gatherers find
+fn requeue_dead_letters(
+    dead_letters: &'_ DeadLetters,
+    tx: &'_ async_channel::Sender<Downloadable>,
+) {
+    let letters = dead_letters.take_all();
+    if letters.is_empty() {
+        return;
+    }
+    println!(
+        "Retrying {} items that failed in a previous run",
+        letters.len()
+    );
+    for letter in letters {
+        if let Err(send_err) = tx.try_send(letter.item) {
+            log::error!("Failed to re-queue a dead letter. {:?}", send_err);
+        }
+    }
 }
 
 pub async fn list(cur_gatherers: Vec<Arc<dyn Gatherer + 'static>>) -> Result<()> {
@@ -198,7 +427,6 @@ pub async fn list(cur_gatherers: Vec<Arc<dyn Gatherer + 'static>>) -> Result<()>
     }
 }
 
-#[allow(dead_code)]
 pub async fn like(_cur_gatherers: Vec<Arc<dyn Gatherer + 'static>>) -> Result<()> {
     Ok(())
 }
@@ -216,6 +444,11 @@ pub async fn transactions(
         let mut gatherer_totals: HashMap<&str, HashMap<String, f64>> = HashMap::new();
 
         for gatherer in cur_gatherers.into_iter() {
+            if matches!(find_gatherer(gatherer.name()), Some(registration) if !registration.supports(Capability::Transactions))
+            {
+                log::debug!("{:>12}: Transactions are not supported", gatherer.name());
+                continue;
+            }
             let mut user_total: HashMap<String, f64> = HashMap::new();
             let transactions = gatherer.gather_transaction_details(&user_names).await;
             match transactions {
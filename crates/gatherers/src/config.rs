@@ -1,11 +1,19 @@
-#[cfg(feature = "fansly")]
-use gatherer_fansly::FanslyConfig;
-#[cfg(feature = "onlyfans")]
 use {
-    gatherer_core::{directories::Directories, Result},
-    gatherer_onlyfans::OnlyFansConfig,
+    gatherer_core::{
+        directories::Directories,
+        downloaders::{
+            BandwidthLimits, ContentStoreConfig, DownloaderConfig, PriorityRules, RetryPolicy,
+        },
+        gatherers::{
+            registered_gatherers, ContentFilter, GathererRegistration, MediaLimits,
+            PathTemplateConfig,
+        },
+        http::{ProxyConfig, ProxyRoutes},
+        Result,
+    },
     serde::{Deserialize, Serialize},
     std::{
+        collections::BTreeMap,
         path::{Path, PathBuf},
         sync::Arc,
     },
@@ -16,10 +24,37 @@ pub struct Config {
     pub config_dir: String,
     pub download_dir: String,
     pub workers: u8,
-    #[cfg(feature = "onlyfans")]
-    pub fansly: FanslyConfig,
-    #[cfg(feature = "onlyfans")]
-    pub onlyfans: OnlyFansConfig,
+    // Plain values have to come before any of the tables for the config to save as TOML
+    /// Write a `<kind>-<id>.json` file with the text, dates and price of each post and message next to its media
+    #[serde(default)]
+    pub sidecars: bool,
+    /// Which batch and file downloaders are used
+    #[serde(default)]
+    pub downloader: DownloaderConfig,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub bandwidth: BandwidthLimits,
+    #[serde(default)]
+    pub content_store: ContentStoreConfig,
+    /// Where each item is saved inside `download_dir`
+    #[serde(default)]
+    pub paths: PathTemplateConfig,
+    /// How the download queue is ordered
+    #[serde(default)]
+    pub priority: PriorityRules,
+    /// How much media a run queues, `--limit-media` and `--limit-media-total` override these
+    #[serde(default)]
+    pub limits: MediaLimits,
+    /// Which media is queued when `--content-types` isn't given
+    #[serde(default)]
+    pub content_types: ContentFilter,
+    /// Proxy for every gatherer without a `proxy` in its own section, used for API requests and downloads alike
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    /// One table per gatherer keyed on its config section, e.g. `[fansly]`, handed to the gatherer as is
+    #[serde(flatten)]
+    pub gatherers: BTreeMap<String, toml::Value>,
 }
 
 impl Config {
@@ -40,6 +75,54 @@ impl Config {
         Ok(Arc::new(toml::from_str(&file_contents[..])?))
     }
 
+    /// The gatherer's config section, or its defaults when the user doesn't have one
+    ///
+    /// The global proxy is filled in when the section doesn't set one of its own.
+    pub fn gatherer_section(&self, registration: &'_ GathererRegistration) -> serde_json::Value {
+        let mut section = match self.gatherers.get(registration.config_section) {
+            Some(section) => serde_json::to_value(section).unwrap_or_default(),
+            None => (registration.default_config)(),
+        };
+        if let (Some(proxy), Some(fields)) = (&self.proxy, section.as_object_mut()) {
+            if fields.get("proxy").is_none_or(serde_json::Value::is_null) {
+                fields.insert(
+                    "proxy".to_string(),
+                    serde_json::to_value(proxy).unwrap_or_default(),
+                );
+            }
+        }
+        section
+    }
+
+    /// The proxy downloads go through for each gatherer, the same one its API requests use
+    pub fn proxy_routes(&self) -> ProxyRoutes {
+        ProxyRoutes {
+            default: self.proxy.clone(),
+            gatherers: registered_gatherers()
+                .filter_map(|registration| {
+                    let proxy = self
+                        .gatherers
+                        .get(registration.config_section)?
+                        .get("proxy")?
+                        .clone()
+                        .try_into()
+                        .ok()?;
+                    Some((registration.name.to_string(), proxy))
+                })
+                .collect(),
+        }
+    }
+
+    /// Users in the `ignore_lists` of every gatherer section
+    pub fn ignored_user_names(&self) -> Vec<String> {
+        self.gatherers
+            .values()
+            .filter_map(|section| section.get("ignore_lists")?.as_array())
+            .flatten()
+            .filter_map(|user_name| user_name.as_str().map(String::from))
+            .collect()
+    }
+
     /// Save the config file
     fn save(&self) -> Result<()> {
         let config_dir = Path::new(&self.config_dir);
@@ -56,8 +139,9 @@ impl Config {
 
 impl Drop for Config {
     fn drop(&mut self) {
+        // Panicking here would abort when the drop happens during an unwind, and lose the run state saves after it
         if let Err(err) = self.save() {
-            panic!("Failed to save the config file, {:?}", err);
+            log::error!("Failed to save the config file, {:?}", err);
         }
     }
 }
@@ -68,10 +152,46 @@ impl Default for Config {
         let config_directory = dirs.get_default_config_dir();
         Self {
             config_dir: String::from(config_directory.to_str().unwrap_or_default()),
-            fansly: FanslyConfig::default(),
-            onlyfans: OnlyFansConfig::default(),
             download_dir: String::from("/tmp"),
             workers: 8,
+            downloader: DownloaderConfig::default(),
+            retry: RetryPolicy::default(),
+            bandwidth: BandwidthLimits::default(),
+            content_store: ContentStoreConfig::default(),
+            paths: PathTemplateConfig::default(),
+            priority: PriorityRules::default(),
+            limits: MediaLimits::default(),
+            content_types: ContentFilter::default(),
+            sidecars: false,
+            proxy: None,
+            gatherers: registered_gatherers()
+                .filter_map(|registration| {
+                    let section = json_to_toml((registration.default_config)())?;
+                    Some((registration.config_section.to_string(), section))
+                })
+                .collect(),
         }
     }
 }
+
+/// TOML has no null, unset values are left out of the section instead
+fn json_to_toml(value: serde_json::Value) -> Option<toml::Value> {
+    Some(match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(value) => toml::Value::Boolean(value),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(number) => toml::Value::Integer(number),
+            None => toml::Value::Float(number.as_f64()?),
+        },
+        serde_json::Value::String(value) => toml::Value::String(value),
+        serde_json::Value::Array(values) => {
+            toml::Value::Array(values.into_iter().filter_map(json_to_toml).collect())
+        }
+        serde_json::Value::Object(fields) => toml::Value::Table(
+            fields
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, json_to_toml(value)?)))
+                .collect(),
+        ),
+    })
+}
@@ -2,11 +2,20 @@
 mod cli;
 mod cli_tasks;
 mod config;
-mod macros;
+mod progress;
 
+// Gatherer crates register themselves when they are linked in, nothing else of theirs is used here
+#[cfg(feature = "fansly")]
+use gatherer_fansly as _;
+#[cfg(feature = "onlyfans")]
+use gatherer_onlyfans as _;
 use {
     self::{cli::Cli, config::Config},
-    gatherer_core::{self, directories::Directories, gatherers::Gatherer},
+    gatherer_core::{
+        self,
+        directories::Directories,
+        gatherers::{find_gatherer, registered_gatherers, Gatherer, GathererRegistration},
+    },
     log::LevelFilter,
     std::sync::Arc,
 };
@@ -40,7 +49,11 @@ fn main() {
             }
         };
 
-        match cli.action.exec(config, &cli.gatherers).await {
+        match cli
+            .action
+            .exec(config, &cli.gatherers, cli.content_types)
+            .await
+        {
             Ok(()) => eprintln!("Completed"),
             Err(err) => log::error!("Command failed: {:?}", err),
         };
@@ -51,37 +64,32 @@ async fn get_available_gatherers(
     conf: &'_ Config,
     gatherer_names: &[String],
 ) -> gatherer_core::Result<Vec<Arc<dyn Gatherer>>> {
-    let mut gatherers: Vec<Arc<dyn Gatherer>> = Vec::new();
     log::debug!("Gatherer names from CLI args: {:?}", gatherer_names);
-    if !gatherer_names.is_empty() {
-        for name in gatherer_names.iter().map(|n| n.as_str()) {
-            log::debug!("Checking for gatherer named {}", name);
-            match name {
-                "fansly" => {
-                    #[cfg(feature = "fansly")]
-                    add_gatherer!(&mut gatherers, gatherer_fansly::Fansly, conf.fansly.clone());
-                }
-                "onlyfans" | "only_fans" => {
-                    #[cfg(feature = "onlyfans")]
-                    add_gatherer!(
-                        &mut gatherers,
-                        gatherer_onlyfans::OnlyFans,
-                        conf.onlyfans.clone()
-                    );
-                }
-                _ => log::info!("Gatherer {} is not known at this time.", name),
-            }
-        }
+    let registrations: Vec<&GathererRegistration> = if gatherer_names.is_empty() {
+        registered_gatherers().collect()
     } else {
-        #[cfg(feature = "fansly")]
-        add_gatherer!(&mut gatherers, gatherer_fansly::Fansly, conf.fansly.clone());
+        gatherer_names
+            .iter()
+            .filter_map(|name| {
+                log::debug!("Checking for gatherer named {}", name);
+                let registration = find_gatherer(name);
+                if registration.is_none() {
+                    log::info!("Gatherer {} is not known at this time.", name);
+                }
+                registration
+            })
+            .collect()
+    };
 
-        #[cfg(feature = "onlyfans")]
-        add_gatherer!(
-            &mut gatherers,
-            gatherer_onlyfans::OnlyFans,
-            conf.onlyfans.clone()
-        );
+    let mut gatherers: Vec<Arc<dyn Gatherer>> = Vec::new();
+    for registration in registrations {
+        match registration
+            .build(conf.gatherer_section(registration))
+            .await
+        {
+            Ok(gatherer) => gatherers.push(gatherer),
+            Err(err) => log::error!("Failed to initialize: {}. {:?}", registration.name, err),
+        }
     }
 
     Ok(gatherers)
@@ -97,7 +105,7 @@ fn init_logging(cli: &'_ Cli) -> gatherer_core::Result<()> {
                 message
             ))
         })
-        .level(match &cli.verbose.unwrap_or_else(|| 0) {
+        .level(match &cli.verbose.unwrap_or(0) {
             0 => LevelFilter::Error,
             1 => LevelFilter::Info,
             2 => LevelFilter::Debug,
@@ -0,0 +1,147 @@
+use {
+    async_channel::Receiver,
+    async_task::Task,
+    gatherer_core::{downloaders::DownloadEvent, tasks::spawn_on_thread},
+    indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle},
+    std::{collections::HashMap, time::Instant},
+};
+
+const OVERALL_TEMPLATE: &str =
+    "{spinner} [{elapsed_precise}] {wide_bar} {pos}/{len} items, ETA {eta} {msg}";
+const WORKER_TEMPLATE: &str = "W({prefix:>2}) {wide_msg} {bytes:>10}/{total_bytes:<10}";
+
+/// Render download events as one bar per worker plus an overall bar with throughput and ETA
+///
+/// The task finishes once the event stream ends, which happens when the downloader is dropped.
+pub fn show_download_progress(events: Receiver<DownloadEvent>) -> Task<()> {
+    spawn_on_thread(async move {
+        let mut display = ProgressDisplay::new();
+        while let Ok(event) = events.recv().await {
+            display.update(event);
+        }
+        display.finish();
+    })
+}
+
+struct ProgressDisplay {
+    bars: MultiProgress,
+    overall: ProgressBar,
+    workers: HashMap<usize, WorkerBar>,
+    started_at: Instant,
+    bytes_downloaded: u64,
+    failed: u64,
+}
+
+struct WorkerBar {
+    bar: ProgressBar,
+    /// Position last reported for the current item, progress events carry the running total
+    position: u64,
+}
+
+impl ProgressDisplay {
+    fn new() -> Self {
+        let bars = MultiProgress::new();
+        let overall = bars.add(ProgressBar::new(0));
+        overall.set_style(ProgressStyle::with_template(OVERALL_TEMPLATE).unwrap());
+        Self {
+            bars,
+            overall,
+            workers: HashMap::new(),
+            started_at: Instant::now(),
+            bytes_downloaded: 0,
+            failed: 0,
+        }
+    }
+
+    fn update(&mut self, event: DownloadEvent) {
+        match event {
+            DownloadEvent::Queued { .. } => self.overall.inc_length(1),
+            DownloadEvent::Started {
+                worker,
+                path,
+                attempt,
+                ..
+            } => {
+                let worker = self.worker(worker);
+                worker.position = 0;
+                worker.bar.set_position(0);
+                worker.bar.set_length(0);
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                if attempt > 1 {
+                    worker
+                        .bar
+                        .set_message(format!("{} (attempt {})", file_name, attempt));
+                } else {
+                    worker.bar.set_message(file_name);
+                }
+            }
+            DownloadEvent::Progress {
+                worker,
+                downloaded,
+                total,
+                ..
+            } => {
+                let worker = self.worker(worker);
+                let progressed = downloaded.saturating_sub(worker.position);
+                worker.position = downloaded;
+                if let Some(total) = total {
+                    worker.bar.set_length(total);
+                }
+                worker.bar.set_position(downloaded);
+                self.bytes_downloaded += progressed;
+                self.update_throughput();
+            }
+            DownloadEvent::Completed { worker, .. } => self.item_done(worker),
+            DownloadEvent::Skipped { worker, .. } => self.item_done(worker),
+            DownloadEvent::Failed { worker, reason, .. } => {
+                self.failed += 1;
+                self.bars
+                    .println(format!("W({:2}): Failed. {:?}", worker, reason))
+                    .ok();
+                self.item_done(worker);
+                self.update_throughput();
+            }
+        }
+    }
+
+    fn worker(&mut self, worker: usize) -> &'_ mut WorkerBar {
+        let bars = &self.bars;
+        self.workers.entry(worker).or_insert_with(|| {
+            let bar = bars.add(ProgressBar::new(0));
+            bar.set_style(ProgressStyle::with_template(WORKER_TEMPLATE).unwrap());
+            bar.set_prefix(worker.to_string());
+            WorkerBar { bar, position: 0 }
+        })
+    }
+
+    fn item_done(&mut self, worker: usize) {
+        self.overall.inc(1);
+        let worker = self.worker(worker);
+        worker.position = 0;
+        worker.bar.set_message("waiting");
+    }
+
+    fn update_throughput(&self) {
+        let elapsed = self.started_at.elapsed().as_secs_f64().max(1.0);
+        let per_sec = (self.bytes_downloaded as f64 / elapsed) as u64;
+        let mut msg = format!(
+            "{} at {}/s",
+            HumanBytes(self.bytes_downloaded),
+            HumanBytes(per_sec)
+        );
+        if self.failed > 0 {
+            msg.push_str(&format!(", {} failed", self.failed));
+        }
+        self.overall.set_message(msg);
+    }
+
+    fn finish(self) {
+        for worker in self.workers.values() {
+            worker.bar.finish_and_clear();
+        }
+        self.overall.finish();
+    }
+}
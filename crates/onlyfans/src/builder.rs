@@ -31,9 +31,12 @@ impl OnlyFansBuilder {
         let http_client = self.http_client.as_ref().unwrap();
         let cookie = Cookie::parse(&config.cookie).unwrap();
 
-        log::debug!("Config cookie         : {:?}", cookie);
-        log::debug!("Config cookie session : {:?}", cookie.get("sess"));
-        log::debug!("Config cookie csrf    : {:?}", cookie.get("csrf"));
+        // Cookie values are credentials, only say which ones are there
+        log::debug!(
+            "Config cookie has a session: {}, csrf: {}",
+            !cookie.get("sess").is_empty(),
+            !cookie.get("csrf").is_empty()
+        );
 
         // Handle init call
         let mut init_headers =
@@ -46,13 +49,12 @@ impl OnlyFansBuilder {
                 .get(constants::INIT_URL, Some(init_headers))
                 .await;
 
+            // Any cookies it sets are already in the client's jar
             match init_response {
-                Ok(success) => {
-                    let success_headers =
-                        Cookie::parse(success.get_header("set-cookie").unwrap_or("")).unwrap();
-
-                    log::debug!("'set-cookie' header: {:?}", success_headers)
-                }
+                Ok(_) => log::debug!(
+                    "Cookie jar has a session after init: {}",
+                    http_client.cookie_jar().get("sess").is_some()
+                ),
                 Err(init_failed) => log::debug!("OnlyFans failed to init: {:?}", init_failed),
             };
         }
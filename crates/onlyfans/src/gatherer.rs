@@ -1,7 +1,11 @@
 use {
     async_trait::async_trait,
+    futures::{stream, StreamExt, TryStreamExt},
     gatherer_core::{
-        gatherers::{structs::DateTime, Gatherer, Media, Subscription, Transaction},
+        gatherers::{
+            structs::DateTime, Gatherer, Media, MediaKind, MediaOrigin, MediaOriginKind,
+            MediaStream, Message, Post, Subscription, SubscriptionName, Transaction,
+        },
         Result,
     },
     std::collections::HashMap,
@@ -22,68 +26,97 @@ impl Gatherer for crate::OnlyFans {
     }
 
     async fn gather_media_from_posts(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
-        match self.get_user_posts(&sub.id).await {
-            Ok(user_posts) => {
-                let mut media = Vec::new();
+        self.stream_media_from_posts(sub).try_collect().await
+    }
 
-                for post in user_posts {
-                    for post_media in post.media.unwrap_or_default() {
-                        match to_gatherer_media(&post_media, &sub.name.username) {
-                            Some(valid_media) => {
-                                let mut valid_media: gatherer_core::gatherers::Media = valid_media;
-                                // If the post has a cost and it has been opened than we have paid for it
-                                valid_media.paid = if let Some(price) = post.price {
-                                    post.is_opened.unwrap_or(false) && (price > 0.)
-                                } else {
-                                    false
-                                };
-                                media.push(valid_media)
-                            }
-                            None => {
-                                log::debug!("Failed to get media from user post. {:?}", post_media.id)
-                            }
-                        }
-                    }
-                }
+    async fn gather_media_from_messages(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
+        self.stream_media_from_messages(sub).try_collect().await
+    }
 
-                Ok(media)
+    fn stream_media_from_posts<'a>(&'a self, sub: &'a Subscription) -> MediaStream<'a> {
+        self.stream_user_posts(&sub.id, &sub.watermark, &sub.date_range)
+            .map_ok(move |posts| {
+                let media = posts
+                    .into_iter()
+                    .flat_map(|post| to_gatherer_post(post, &sub.name.username).media);
+                stream::iter(media.map(Ok))
+            })
+            .try_flatten()
+            .boxed()
+    }
+
+    fn stream_media_from_messages<'a>(&'a self, sub: &'a Subscription) -> MediaStream<'a> {
+        let authed_user_id = self.authed_user.id;
+        self.stream_user_messages(&sub.id, &sub.watermark, &sub.date_range)
+            .map_ok(move |messages| {
+                let media = messages
+                    .into_iter()
+                    // Only media sent to the authed user, not what they sent themselves
+                    .filter(move |msg| {
+                        !matches!(&msg.from_user, Some(from_user) if from_user.id == authed_user_id)
+                    })
+                    .flat_map(|msg| {
+                        // Who sent it doesn't matter for the media
+                        let (from, to) = Default::default();
+                        to_gatherer_message(msg, from, to, &sub.name.username).attached_media
+                    });
+                stream::iter(media.map(Ok))
+            })
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn gather_posts(&self, sub: &'_ Subscription) -> Result<Vec<Post>> {
+        match self
+            .get_user_posts(&sub.id, &sub.watermark, &sub.date_range)
+            .await
+        {
+            Ok(user_posts) => Ok(user_posts
+                .into_iter()
+                .map(|post| to_gatherer_post(post, &sub.name.username))
+                .collect()),
+            Err(posts_err) => {
+                log::error!(
+                    "Failed to get posts for user {}. {:?}",
+                    sub.name.username,
+                    posts_err
+                );
+                Err(posts_err)
             }
-            Err(posts_err) => Err(format!(
-                "Failed to get posts for user {}. {:?}",
-                sub.name.username, posts_err
-            )
-            .into()),
         }
     }
 
-    async fn gather_media_from_messages(&self, sub: &'_ Subscription) -> Result<Vec<Media>> {
-        match self.get_user_messages(&sub.id).await {
+    async fn gather_messages(&self, sub: &'_ Subscription) -> Result<Vec<Message>> {
+        match self
+            .get_user_messages(&sub.id, &sub.watermark, &sub.date_range)
+            .await
+        {
             Ok(user_messages) => {
-                let mut media = Vec::new();
-                for msg in user_messages {
-                    for msg_media in msg.media.unwrap_or_default() {
-                        match to_gatherer_media(&msg_media, &sub.name.username) {
-                            Some(valid_media) => {
-                                let mut valid_media: gatherer_core::gatherers::Media = valid_media;
-                                // if the post is not free, and you cannot purchase it but it is opened than you have paid for this content
-                                valid_media.paid = !msg.is_free.unwrap_or(false)
-                                    && !msg.can_purchase.unwrap_or(false)
-                                    && msg.is_opened.unwrap_or(false);
-                                media.push(valid_media)
-                            }
-                            None => {
-                                log::debug!("Failed to get media from msg. {:?}", msg.id)
-                            }
-                        }
-                    }
-                }
-                Ok(media)
+                let authed_user_name = self.authed_user_name();
+                Ok(user_messages
+                    .into_iter()
+                    .map(|msg| {
+                        let sent_by_sub = match &msg.from_user {
+                            Some(from_user) => from_user.id != self.authed_user.id,
+                            None => true,
+                        };
+                        let (from, to) = if sent_by_sub {
+                            (sub.name.clone(), authed_user_name.clone())
+                        } else {
+                            (authed_user_name.clone(), sub.name.clone())
+                        };
+                        to_gatherer_message(msg, from, to, &sub.name.username)
+                    })
+                    .collect())
+            }
+            Err(messages_err) => {
+                log::error!(
+                    "Failed to get messages for user: {}. {:?}",
+                    sub.name.username,
+                    messages_err
+                );
+                Err(messages_err)
             }
-            Err(messages_err) => Err(format!(
-                "Failed to get messages for user: {}. {:?}",
-                sub.name.username, messages_err
-            )
-            .into()),
         }
     }
 
@@ -92,9 +125,17 @@ impl Gatherer for crate::OnlyFans {
             Ok(user_stories) => {
                 let mut media = vec![];
                 for story in user_stories {
+                    let origin = MediaOrigin::new(
+                        MediaOriginKind::Story,
+                        story.id.unwrap_or_default(),
+                        crate::parse_timestamp(story.created_at.as_deref()),
+                    );
                     for story_media in story.media.unwrap_or_default() {
                         match to_gatherer_media(&story_media, &sub.name.username) {
-                            Some(valid_media) => media.push(valid_media),
+                            Some(mut valid_media) => {
+                                valid_media.origin = Some(origin.clone());
+                                media.push(valid_media)
+                            }
                             None => {
                                 log::debug!("Failed to get media from user story. {:?}", story.id)
                             }
@@ -113,7 +154,7 @@ impl Gatherer for crate::OnlyFans {
             Ok(paid_content) => {
                 let mut results = Vec::new();
                 for item in paid_content.into_iter() {
-                    let user_id: i64 = match item.response_type.unwrap_or_default().as_str() {
+                    let user_id: i64 = match item.response_type.as_deref().unwrap_or_default() {
                         "message" => {
                             if let Some(from_user) = &item.from_user {
                                 from_user.id.unwrap_or_default()
@@ -152,10 +193,26 @@ impl Gatherer for crate::OnlyFans {
                             }
                         }
                     };
+                    let origin_kind = match item.response_type.as_deref() {
+                        Some("message") => Some(MediaOriginKind::Message),
+                        Some("post") => Some(MediaOriginKind::Post),
+                        _ => None,
+                    };
+                    let origin = origin_kind.map(|kind| {
+                        MediaOrigin::new(
+                            kind,
+                            item.id.unwrap_or_default(),
+                            crate::parse_timestamp(item.created_at.as_deref()),
+                        )
+                        .with_text(item.text.clone())
+                        .with_price(Some(item.price))
+                        .with_opened(item.is_opened)
+                    });
                     // set all of these results to ensure paid flag is set properly
                     for media in item.media.unwrap_or_default() {
                         if let Some(mut purchased_media) = to_gatherer_media(&media, &user_name) {
                             purchased_media.paid = true;
+                            purchased_media.origin = origin.clone();
                             results.push(purchased_media);
                         }
                     }
@@ -213,14 +270,119 @@ impl Gatherer for crate::OnlyFans {
     }
 }
 
+impl crate::OnlyFans {
+    fn authed_user_name(&self) -> SubscriptionName {
+        SubscriptionName {
+            username: self.authed_user.username.clone().unwrap_or_default(),
+            display_name: self.authed_user.name.clone(),
+        }
+    }
+}
+
+fn to_gatherer_post(post: crate::structs::Post, of_sub_name: &'_ str) -> Post {
+    let origin = MediaOrigin::new(
+        MediaOriginKind::Post,
+        post.id.unwrap_or_default(),
+        crate::post_posted_at(&post),
+    )
+    .with_text(post.text.clone())
+    .with_price(post.price)
+    .with_opened(post.is_opened);
+    // If the post has a cost and it has been opened than we have paid for it
+    let paid = if let Some(price) = post.price {
+        post.is_opened.unwrap_or(false) && (price > 0.)
+    } else {
+        false
+    };
+    let mut media = Vec::new();
+    for post_media in post.media.unwrap_or_default() {
+        match to_gatherer_media(&post_media, of_sub_name) {
+            Some(mut valid_media) => {
+                valid_media.paid = paid;
+                valid_media.origin = Some(origin.clone());
+                media.push(valid_media)
+            }
+            None => {
+                log::debug!("Failed to get media from user post. {:?}", post_media.id)
+            }
+        }
+    }
+    Post {
+        id: origin.id,
+        title: String::new(),
+        content: origin.text.unwrap_or_default(),
+        posted_at: origin.posted_at,
+        price: origin.price,
+        opened: origin.opened,
+        media,
+        paid,
+    }
+}
+
+fn to_gatherer_message(
+    msg: crate::structs::Message,
+    from: SubscriptionName,
+    to: SubscriptionName,
+    of_sub_name: &'_ str,
+) -> Message {
+    let expires_at = message_expires_at(&msg);
+    let origin = MediaOrigin::new(
+        MediaOriginKind::Message,
+        msg.id.unwrap_or_default(),
+        crate::parse_timestamp(msg.created_at.as_deref()),
+    )
+    .with_text(msg.text.clone())
+    .with_price(Some(msg.price))
+    .with_opened(msg.is_opened);
+    // if the post is not free, and you cannot purchase it but it is opened than you have paid for this content
+    let paid = !msg.is_free.unwrap_or(false)
+        && !msg.can_purchase.unwrap_or(false)
+        && msg.is_opened.unwrap_or(false);
+    let mut attached_media = Vec::new();
+    for msg_media in msg.media.unwrap_or_default() {
+        match to_gatherer_media(&msg_media, of_sub_name) {
+            Some(mut valid_media) => {
+                valid_media.paid = paid;
+                valid_media.expires_at = expires_at;
+                valid_media.origin = Some(origin.clone());
+                attached_media.push(valid_media)
+            }
+            None => {
+                log::debug!("Failed to get media from msg. {:?}", msg.id)
+            }
+        }
+    }
+    Message {
+        id: origin.id,
+        from,
+        to,
+        message: origin.text.unwrap_or_default(),
+        sent_at: origin.posted_at,
+        price: origin.price,
+        opened: origin.opened,
+        attached_media,
+    }
+}
+
+/// Messages with `cancelSeconds` are removed that long after being sent
+fn message_expires_at(msg: &'_ crate::structs::Message) -> Option<chrono::DateTime<chrono::Utc>> {
+    let cancel_seconds = msg.cancel_seconds.filter(|secs| *secs > 0)?;
+    let sent_at =
+        crate::parse_timestamp(msg.created_at.as_deref()).unwrap_or_else(chrono::Utc::now);
+    Some(sent_at + chrono::Duration::seconds(cancel_seconds))
+}
+
 pub(crate) fn to_gatherer_media(
     of_media: &'_ crate::structs::Media,
     of_sub_name: &'_ str,
 ) -> Option<Media> {
-    let mime_type = match of_media.media_type.clone().unwrap_or_default().as_str() {
-        "photo" => "image/jpeg",
-        "video" | "gif" => "video/mp4",
-        _ => "unknown",
+    // OnlyFans serves gifs as mp4 files, the kind keeps them apart from other videos
+    let (mime_type, kind) = match of_media.media_type.clone().unwrap_or_default().as_str() {
+        "photo" => ("image/jpeg", MediaKind::Image),
+        "video" => ("video/mp4", MediaKind::Video),
+        "gif" => ("video/mp4", MediaKind::Gif),
+        "audio" => ("audio/mpeg", MediaKind::Audio),
+        _ => ("unknown", MediaKind::Unknown),
     };
     let mut possible_media_link: &Option<String> = &of_media.full;
     if possible_media_link.is_none() {
@@ -255,7 +417,11 @@ pub(crate) fn to_gatherer_media(
     let file_name: String = {
         if let Ok(url) = Url::parse(&url) {
             if let Some(segments) = url.path_segments() {
-                segments.into_iter().last().unwrap_or_default().to_string()
+                segments
+                    .into_iter()
+                    .next_back()
+                    .unwrap_or_default()
+                    .to_string()
             } else {
                 log::debug!("No path segments available in URL {}", url);
                 return None;
@@ -266,11 +432,49 @@ pub(crate) fn to_gatherer_media(
         }
     };
 
+    // The url may come from `full` which has no details, so take them from whichever source block there is
+    let details = of_media
+        .files
+        .as_ref()
+        .and_then(|files| files.source.as_ref())
+        .map(|file| (file.width, file.height, file.duration, file.size))
+        .or_else(|| {
+            of_media
+                .source
+                .as_ref()
+                .map(|source| (source.width, source.height, source.duration, source.size))
+        })
+        .or_else(|| {
+            of_media
+                .info
+                .as_ref()
+                .and_then(|info| info.source.as_ref())
+                .map(|source| (source.width, source.height, source.duration, source.size))
+        });
+    let (width, height, duration, size) = details.unwrap_or_default();
+
     Some(Media {
+        id: of_media.id.map(|id| id.to_string()).unwrap_or_default(),
+        created_at: crate::parse_timestamp(of_media.created_at.as_deref()),
+        expires_at: None,
         file_name,
         paid: false,
         mime_type: mime_type.to_string(),
+        kind,
         url,
         user_name: of_sub_name.to_string(),
+        width: positive(width),
+        height: positive(height),
+        duration: positive(duration).map(std::time::Duration::from_secs),
+        size: positive(size),
+        origin: None,
+        purchased_at: None,
     })
 }
+
+/// OnlyFans sends `0` for anything it doesn't know
+fn positive<T: TryFrom<i64>>(value: Option<i64>) -> Option<T> {
+    value
+        .filter(|value| *value > 0)
+        .and_then(|value| T::try_from(value).ok())
+}
@@ -2,22 +2,33 @@ mod builder;
 mod constants;
 mod gatherer;
 mod responses;
-mod structs;
+pub mod structs;
 
 use {
     crate::{
         builder::OnlyFansBuilder,
         structs::{DynamicRule, ListUser},
     },
+    futures::{
+        stream::{self, BoxStream},
+        StreamExt, TryStreamExt,
+    },
     gatherer_core::{
+        gatherers::DateRange,
         gatherers::GathererErrors,
-        http::{Client, ClientConfig, Headers, Url},
+        http::{
+            CassetteConfig, Client, ClientConfig, CookieJar, Headers, HttpRetryPolicy, ProxyConfig,
+            RateLimit, Url,
+        },
+        state::WatermarkCursor,
         Result,
     },
     serde::{Deserialize, Serialize},
     sha1::{Digest, Sha1},
     std::{
         collections::HashMap,
+        path::PathBuf,
+        sync::Arc,
         time::{SystemTime, UNIX_EPOCH},
     },
 };
@@ -31,6 +42,29 @@ pub struct OnlyFansConfig {
     pub x_bc: String,
     pub user_agent: String,
     pub ignore_lists: Vec<String>,
+    /// How fast API requests are sent, downloads aren't counted
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub retry: HttpRetryPolicy,
+    /// Record the API traffic to a cassette or replay it from one, for bug reports and tests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cassette: Option<CassetteConfig>,
+    /// Send the API requests and media downloads through this proxy instead of the global one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    /// Where the session cookies are kept between runs, `cookies/onlyfans.json` in the state directory by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie_jar: Option<PathBuf>,
+}
+
+gatherer_core::register_gatherer! {
+    name: "onlyfans",
+    config_section: "onlyfans",
+    aliases: ["only_fans"],
+    capabilities: [Posts, Messages, Stories, Purchased, Transactions],
+    gatherer: OnlyFans,
+    config: OnlyFansConfig,
 }
 
 #[derive(Debug)]
@@ -50,25 +84,46 @@ impl OnlyFans {
             }));
         }
 
-        let http_client = Client::new(ClientConfig {
+        let mut http_client = Client::new(ClientConfig {
+            name: Some("OnlyFans".to_string()),
             base_url: Some(constants::BASE_URL.to_string()),
+            rate_limit: of_conf.rate_limit,
+            retry: of_conf.retry.clone(),
+            cassette: of_conf.cassette.clone(),
+            proxy: of_conf.proxy.clone(),
         });
+        http_client.set_cookie_jar(Arc::new(load_cookie_jar(&of_conf)?));
 
         let mut ofb = OnlyFansBuilder::new(of_conf);
         ofb.with_dynamic_rule(get_dc_dynamic_rule(&http_client).await?);
         ofb.add_http_client(http_client);
         ofb.parse_cookie_string();
-        Ok(ofb.build().await?)
+        ofb.build().await
     }
 }
 
+/// The saved jar, seeded with `cookie` from the config, a different `cookie` than last time starts a fresh jar
+fn load_cookie_jar(config: &'_ OnlyFansConfig) -> Result<CookieJar> {
+    let loaded = match &config.cookie_jar {
+        Some(path) => CookieJar::load(path),
+        None => CookieJar::load_default("onlyfans"),
+    };
+    let jar = loaded.unwrap_or_else(|jar_err| {
+        log::error!(
+            "Failed to load the OnlyFans cookie jar, using the config cookie only. {:?}",
+            jar_err
+        );
+        CookieJar::default()
+    });
+    jar.seed(&Url::parse(constants::BASE_URL)?, &config.cookie)?;
+    Ok(jar)
+}
+
 fn generate_request_headers(
     config: &'_ OnlyFansConfig,
     path: &'_ str,
     dynamic_rule: &'_ DynamicRule,
 ) -> Headers {
-    let cookie = &config.cookie;
-
     let mut h = HashMap::new();
     h.insert(
         "accept".to_string(),
@@ -84,7 +139,6 @@ fn generate_request_headers(
     h.insert("x-bc".to_string(), config.x_bc.to_string());
     h.insert("referer".to_string(), "https://onlyfans.com".to_string());
     h.insert("user-agent".to_string(), config.user_agent.to_string());
-    h.insert("cookie".to_string(), cookie.to_string());
     h.insert("user-id".to_string(), config.auth_id.to_string());
 
     // Add the necessary signed headers
@@ -111,7 +165,7 @@ impl OnlyFans {
             } else {
                 format!("/api2/v2/subscriptions/subscribes?offset={offset}&sort=desc&field=expire_date&limit=10")
             };
-            if let Ok(partial_subs) = self
+            let partial_subs = self
                 .http_client
                 .get(
                     &endpoint,
@@ -121,22 +175,20 @@ impl OnlyFans {
                         &self.dynamic_rule,
                     )),
                 )
-                .await
-            {
-                let subs: responses::SubscriptionResponse = partial_subs.as_json().await?;
-                if subs.len() < 10 {
-                    more_pages = false;
-                }
-
-                // return only the active subscribers
-                subscriptions.append(
-                    &mut subs
-                        .into_iter()
-                        .filter(|s| !s.subscribed_is_expired_now)
-                        .collect(),
-                );
-                offset += 10;
+                .await?;
+            let subs: responses::SubscriptionResponse = partial_subs.as_json().await?;
+            if subs.len() < 10 {
+                more_pages = false;
             }
+
+            // return only the active subscribers
+            subscriptions.append(
+                &mut subs
+                    .into_iter()
+                    .filter(|s| !s.subscribed_is_expired_now)
+                    .collect(),
+            );
+            offset += 10;
         }
         Ok(subscriptions)
     }
@@ -151,7 +203,8 @@ impl OnlyFans {
                 "/api2/v2/posts/paid?limit=10&skip_users=all&format=infinite&offset={}",
                 offset
             );
-            match self
+            // A failed page would be asked for again forever, give up on the first one
+            let response = self
                 .http_client
                 .get(
                     &endpoint,
@@ -161,30 +214,11 @@ impl OnlyFans {
                         &self.dynamic_rule,
                     )),
                 )
-                .await
-            {
-                Ok(response) => {
-                    let purchases_response: Result<responses::PurchasedItemsResponse> =
-                        response.as_json().await;
-                    match purchases_response {
-                        Ok(mut purchases) => {
-                            paid_content.append(&mut purchases.list);
-                            if !purchases.has_more {
-                                has_more = false;
-                            }
-                        }
-                        Err(json_err) => {
-                            log::error!("Failed to serialize JSON into struct: {:?}", json_err)
-                        }
-                    }
-                }
-                Err(response_err) => {
-                    log::debug!(
-                        "Failed to get data from endpoint {}. {:?}",
-                        endpoint,
-                        response_err
-                    );
-                }
+                .await?;
+            let mut purchases: responses::PurchasedItemsResponse = response.as_json().await?;
+            paid_content.append(&mut purchases.list);
+            if !purchases.has_more {
+                has_more = false;
             }
             offset += 10;
         }
@@ -192,10 +226,49 @@ impl OnlyFans {
         Ok(paid_content)
     }
 
-    async fn get_user_posts(&self, user_id: &str) -> Result<Vec<structs::Post>> {
-        let mut posts = Vec::new();
+    async fn get_user_posts(
+        &self,
+        user_id: &str,
+        watermark: &'_ WatermarkCursor,
+        range: &'_ DateRange,
+    ) -> Result<Vec<structs::Post>> {
+        self.stream_user_posts(user_id, watermark, range)
+            .try_concat()
+            .await
+    }
 
-        // Get pinned posts for user
+    /// Pinned posts first and then a page at a time newest first, ends at the first post covered by `watermark`
+    /// or older than the `range`
+    fn stream_user_posts<'a>(
+        &'a self,
+        user_id: &'a str,
+        watermark: &'a WatermarkCursor,
+        range: &'a DateRange,
+    ) -> BoxStream<'a, Result<Vec<structs::Post>>> {
+        stream::try_unfold(Page::First, move |page| async move {
+            match page {
+                Page::First => {
+                    let mut pinned = self.get_user_pinned_posts(user_id).await?;
+                    pinned.retain(|post| range.contains(post_posted_at(post)));
+                    // Publish times are unix seconds with a fraction, the same as `postedAtPrecise`
+                    let before_pub_time = range
+                        .until
+                        .map(|until| format!("{}.000000", until.timestamp()));
+                    Ok(Some((pinned, Page::Next(before_pub_time))))
+                }
+                Page::Next(last_pub_time) => {
+                    let page = self
+                        .get_user_posts_page(user_id, last_pub_time, watermark, range)
+                        .await?;
+                    Ok(Some(page))
+                }
+                Page::Done => Ok(None),
+            }
+        })
+        .boxed()
+    }
+
+    async fn get_user_pinned_posts(&self, user_id: &str) -> Result<Vec<structs::Post>> {
         let endpoint = format!(
             "/api2/v2/users/{user_id}/posts?skip_users=all&pinned=1&counters=0&format=infinite"
         );
@@ -214,126 +287,189 @@ impl OnlyFans {
             Ok(posts_response) => {
                 let response: Result<responses::PostsResponse> = posts_response.as_json().await;
                 match response {
-                    Ok(mut pinned_posts) => posts.append(&mut pinned_posts.list),
+                    Ok(pinned_posts) => Ok(pinned_posts.list),
                     Err(pinned_err) => {
-                        log::error!("Failed to get pinned posts for {user_id}: {pinned_err}")
+                        log::error!("Failed to get pinned posts for {user_id}: {pinned_err}");
+                        Ok(Vec::new())
                     }
                 }
             }
-            Err(response_err) => return Err(response_err),
+            Err(response_err) => Err(response_err),
         }
+    }
 
-        let mut last_pub_time: Option<String> = None;
-
-        loop {
-            let endpoint = if let Some(pub_time) = last_pub_time {
-                format!("/api2/v2/users/{user_id}/posts?limit=10&order=publish_date_desc&skip_users=all&pinned=0&format=infinite&beforePublishTime={pub_time}")
-            } else {
-                format!("/api2/v2/users/{user_id}/posts?limit=10&order=publish_date_desc&skip_users=all&pinned=0&format=infinite")
-            };
-            match self
-                .http_client
-                .get(
+    async fn get_user_posts_page(
+        &self,
+        user_id: &str,
+        last_pub_time: Option<String>,
+        watermark: &'_ WatermarkCursor,
+        range: &'_ DateRange,
+    ) -> Result<(Vec<structs::Post>, Page<Option<String>>)> {
+        let endpoint = if let Some(pub_time) = last_pub_time {
+            format!("/api2/v2/users/{user_id}/posts?limit=10&order=publish_date_desc&skip_users=all&pinned=0&format=infinite&beforePublishTime={pub_time}")
+        } else {
+            format!("/api2/v2/users/{user_id}/posts?limit=10&order=publish_date_desc&skip_users=all&pinned=0&format=infinite")
+        };
+        let posts_response = self
+            .http_client
+            .get(
+                &endpoint,
+                Some(crate::generate_request_headers(
+                    &self.config,
                     &endpoint,
-                    Some(crate::generate_request_headers(
-                        &self.config,
-                        &endpoint,
-                        &self.dynamic_rule,
-                    )),
-                )
-                .await
-            {
-                Ok(posts_response) => {
-                    let mut response: responses::PostsResponse = posts_response.as_json().await?;
-                    if !response.has_more {
-                        break;
-                    } else {
-                        last_pub_time = response
-                            .list
-                            .iter()
-                            .last()
-                            .map(|last_item| last_item.posted_at_precise.clone().unwrap_or_default());
-                    }
-                    posts.append(&mut response.list);
-                }
-                Err(response_err) => return Err(response_err),
+                    &self.dynamic_rule,
+                )),
+            )
+            .await?;
+        let response: responses::PostsResponse = posts_response.as_json().await?;
+        let last_pub_time = response
+            .list
+            .iter()
+            .last()
+            .map(|last_item| last_item.posted_at_precise.clone().unwrap_or_default());
+        // Posts are newest first, everything after the watermark was gathered by an earlier run
+        let mut posts = Vec::new();
+        let mut reached_watermark = false;
+        for post in response.list {
+            let post_id = post.id.unwrap_or_default().to_string();
+            let posted_at = post_posted_at(&post);
+            if range.is_past(posted_at) {
+                log::debug!("Reached the start of the date range for posts from {user_id}");
+                return Ok((posts, Page::Done));
+            }
+            if watermark.is_seen(&post_id, posted_at) {
+                reached_watermark = true;
+                break;
+            }
+            watermark.observe(&post_id, posted_at);
+            if range.contains(posted_at) {
+                posts.push(post);
             }
         }
-
-        Ok(posts)
+        if reached_watermark {
+            log::debug!("Reached the watermark for posts from {user_id}");
+            return Ok((posts, Page::Done));
+        }
+        if !response.has_more {
+            return Ok((posts, Page::Done));
+        }
+        Ok((posts, Page::Next(last_pub_time)))
     }
 
-    async fn get_user_messages(&self, user_id: &str) -> Result<Vec<structs::Message>> {
-        let mut messages = Vec::new();
-
-        let mut last_message_id: Option<i64> = None;
+    async fn get_user_messages(
+        &self,
+        user_id: &str,
+        watermark: &'_ WatermarkCursor,
+        range: &'_ DateRange,
+    ) -> Result<Vec<structs::Message>> {
+        self.stream_user_messages(user_id, watermark, range)
+            .try_concat()
+            .await
+    }
 
-        loop {
-            let endpoint = match last_message_id {
-                None => format!("/api2/v2/chats/{user_id}/messages?limit=10&offset=0&order=desc&skip_users=all"),
-                Some(message_id) => format!("/api2/v2/chats/{user_id}/messages?limit=10&offset=0&id={message_id}&order=desc&skip_users=all")
+    /// Both sides of the chat a page at a time newest first, ends at the first message covered by `watermark`
+    /// or older than the `range`
+    fn stream_user_messages<'a>(
+        &'a self,
+        user_id: &'a str,
+        watermark: &'a WatermarkCursor,
+        range: &'a DateRange,
+    ) -> BoxStream<'a, Result<Vec<structs::Message>>> {
+        stream::try_unfold(Page::First, move |page| async move {
+            let last_message_id = match page {
+                Page::First => None,
+                Page::Next(message_id) => Some(message_id),
+                Page::Done => return Ok(None),
             };
+            let page = self
+                .get_user_messages_page(user_id, last_message_id, watermark, range)
+                .await?;
+            Ok(Some(page))
+        })
+        .boxed()
+    }
 
-            match self
-                .http_client
-                .get(
+    async fn get_user_messages_page(
+        &self,
+        user_id: &str,
+        last_message_id: Option<i64>,
+        watermark: &'_ WatermarkCursor,
+        range: &'_ DateRange,
+    ) -> Result<(Vec<structs::Message>, Page<i64>)> {
+        let endpoint = match last_message_id {
+            None => format!("/api2/v2/chats/{user_id}/messages?limit=10&offset=0&order=desc&skip_users=all"),
+            Some(message_id) => format!("/api2/v2/chats/{user_id}/messages?limit=10&offset=0&id={message_id}&order=desc&skip_users=all")
+        };
+
+        let success_response = match self
+            .http_client
+            .get(
+                &endpoint,
+                Some(crate::generate_request_headers(
+                    &self.config,
                     &endpoint,
-                    Some(crate::generate_request_headers(
-                        &self.config,
-                        &endpoint,
-                        &self.dynamic_rule,
-                    )),
-                )
-                .await
-            {
-                Ok(success_response) => {
-                    let msg_success: Result<crate::responses::MessagesResponse> =
-                        success_response.as_json().await;
-                    match msg_success {
-                        Ok(curr_messages) => {
-                            let authed_user_id = self.authed_user.id;
-                            if curr_messages.has_more {
-                                last_message_id = curr_messages
-                                    .list
-                                    .iter()
-                                    .last()
-                                    .map(|last_item| last_item.id.unwrap_or_default());
-                            } else {
-                                break;
-                            }
-                            // filter out messages that have been sent by the authed user
-                            for curr_msg in curr_messages.list.into_iter() {
-                                match &curr_msg.from_user {
-                                    None => messages.push(curr_msg),
-                                    Some(from_user) => {
-                                        if from_user.id != authed_user_id {
-                                            messages.push(curr_msg)
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        Err(as_json_err) => {
-                            log::debug!(
-                                "Failed to convert message response into JSON: {:?}",
-                                as_json_err
-                            );
-                            return Err(as_json_err);
-                        }
-                    }
-                }
-                Err(error_response) => {
-                    log::debug!(
-                        "Received a bad response while getting messages for {}. {:?}",
-                        user_id,
-                        error_response
-                    );
-                    return Err(error_response);
-                }
+                    &self.dynamic_rule,
+                )),
+            )
+            .await
+        {
+            Ok(success_response) => success_response,
+            Err(error_response) => {
+                log::debug!(
+                    "Received a bad response while getting messages for {}. {:?}",
+                    user_id,
+                    error_response
+                );
+                return Err(error_response);
+            }
+        };
+        let msg_success: Result<crate::responses::MessagesResponse> =
+            success_response.as_json().await;
+        let curr_messages = match msg_success {
+            Ok(curr_messages) => curr_messages,
+            Err(as_json_err) => {
+                log::debug!(
+                    "Failed to convert message response into JSON: {:?}",
+                    as_json_err
+                );
+                return Err(as_json_err);
+            }
+        };
+        let last_message_id = curr_messages
+            .list
+            .iter()
+            .last()
+            .map(|last_item| last_item.id.unwrap_or_default());
+        // Messages are newest first, stop at the first one an earlier run has seen
+        let mut messages = Vec::new();
+        let mut reached_watermark = false;
+        for curr_msg in curr_messages.list.into_iter() {
+            let msg_id = curr_msg.id.unwrap_or_default().to_string();
+            let sent_at = parse_timestamp(curr_msg.created_at.as_deref());
+            if range.is_past(sent_at) {
+                log::debug!("Reached the start of the date range for messages with {user_id}");
+                return Ok((messages, Page::Done));
+            }
+            if watermark.is_seen(&msg_id, sent_at) {
+                reached_watermark = true;
+                break;
+            }
+            watermark.observe(&msg_id, sent_at);
+            // There is no way to ask for messages before a time, newer ones are walked past
+            if range.contains(sent_at) {
+                messages.push(curr_msg);
             }
         }
-
-        Ok(messages)
+        if reached_watermark {
+            log::debug!("Reached the watermark for messages with {user_id}");
+            return Ok((messages, Page::Done));
+        }
+        match last_message_id {
+            Some(last_message_id) if curr_messages.has_more => {
+                Ok((messages, Page::Next(last_message_id)))
+            }
+            _ => Ok((messages, Page::Done)),
+        }
     }
 
     async fn get_user_stories(&self, user_id: &str) -> Result<Vec<structs::Story>> {
@@ -443,7 +579,7 @@ impl OnlyFans {
             Ok(response) => {
                 let users: Result<responses::ListOfUsersResponse> = response.as_json().await;
                 match users {
-                    Ok(users_list) => Ok(users_list.into_iter().map(|(_, user)| user).collect()),
+                    Ok(users_list) => Ok(users_list.into_values().collect()),
                     Err(json_err) => Err(json_err),
                 }
             }
@@ -452,10 +588,30 @@ impl OnlyFans {
     }
 }
 
+/// Where the page streams pick up on their next request
+enum Page<C> {
+    First,
+    Next(C),
+    Done,
+}
+
+/// `createdAt` and the like are RFC 3339 timestamps
+pub(crate) fn parse_timestamp(timestamp: Option<&'_ str>) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(timestamp?)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&chrono::Utc))
+}
+
+/// `postedAtPrecise` is a unix timestamp with a fraction, e.g. `1640995200.000000`
+pub(crate) fn post_posted_at(post: &'_ structs::Post) -> Option<chrono::DateTime<chrono::Utc>> {
+    let secs: f64 = post.posted_at_precise.as_deref()?.parse().ok()?;
+    chrono::DateTime::from_timestamp(secs.trunc() as i64, (secs.fract() * 1e9) as u32)
+}
+
 async fn get_dc_dynamic_rule(api_client: &'_ Client) -> Result<DynamicRule> {
     let url = Url::parse(constants::DC_DYNAMIC_RULE).unwrap();
     let resp = api_client.get(&url, None).await?;
-    Ok(resp.as_json().await?)
+    resp.as_json().await
 }
 
 fn create_signed_headers(
@@ -472,7 +628,7 @@ fn create_signed_headers(
         .to_string();
 
     let static_param = &rule.static_param.unwrap_or_default();
-    let msg = vec![&static_param.as_str(), since_epoch.as_str(), path, user_id].join("\n");
+    let msg = [static_param.as_str(), since_epoch.as_str(), path, user_id].join("\n");
     let sha = calculate_sha1(msg);
     let sha_ascii = sha.to_ascii_lowercase();
 
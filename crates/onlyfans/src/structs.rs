@@ -118,6 +118,8 @@ impl From<Subscription> for gatherer_core::gatherers::Subscription {
             video_count: 0,
             image_count: 0,
             bundle_count: 0,
+            watermark: Default::default(),
+            date_range: Default::default(),
         }
     }
 }
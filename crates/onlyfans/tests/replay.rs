@@ -0,0 +1,174 @@
+use {
+    futures::executor::block_on,
+    gatherer_core::{
+        gatherers::{
+            Gatherer, GathererErrors, MediaKind, MediaOriginKind, Subscription, SubscriptionName,
+        },
+        http::{CassetteConfig, CookieJar},
+    },
+    gatherer_onlyfans::{OnlyFans, OnlyFansConfig},
+    std::path::{Path, PathBuf},
+};
+
+fn cassette(name: &'_ str) -> CassetteConfig {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "cassettes", name]
+        .iter()
+        .collect();
+    CassetteConfig::replay(path)
+}
+
+/// A cookie jar file of its own for each test, so nothing is read from or written to the real state directory
+fn cookie_jar_path(test: &'_ str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "gatherers-onlyfans-{}-{}.json",
+        test,
+        std::process::id()
+    ))
+}
+
+fn config(cassette_name: &'_ str, cookie_jar: &'_ Path) -> OnlyFansConfig {
+    OnlyFansConfig {
+        enabled: true,
+        cookie: "sess=not-a-real-session; auth_id=500".to_string(),
+        auth_id: "500".to_string(),
+        x_bc: "not-a-real-x-bc".to_string(),
+        user_agent: "Mozilla/5.0".to_string(),
+        cassette: Some(cassette(cassette_name)),
+        cookie_jar: Some(cookie_jar.to_path_buf()),
+        ..Default::default()
+    }
+}
+
+fn subscription() -> Subscription {
+    Subscription {
+        id: "100".to_string(),
+        name: SubscriptionName {
+            username: "creator".to_string(),
+            display_name: None,
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn gathers_media_from_posts() {
+    let jar_path = cookie_jar_path("posts");
+    block_on(async {
+        let onlyfans = OnlyFans::new(config("posts.json", &jar_path))
+            .await
+            .unwrap();
+        let media = onlyfans
+            .gather_media_from_posts(&subscription())
+            .await
+            .unwrap();
+
+        assert_eq!(media.len(), 2);
+        let video = &media[0];
+        assert_eq!(video.id, "301");
+        assert_eq!(video.kind, MediaKind::Video);
+        assert_eq!(video.file_name, "1920x1080_301.mp4");
+        assert_eq!(video.duration.map(|duration| duration.as_secs()), Some(12));
+        assert!(video.paid);
+        let origin = video.origin.as_ref().unwrap();
+        assert_eq!(origin.kind, MediaOriginKind::Post);
+        assert_eq!(origin.id, "201");
+
+        let image = &media[1];
+        assert_eq!(image.id, "300");
+        assert_eq!(image.kind, MediaKind::Image);
+        assert_eq!(
+            image.url,
+            "https://cdn2.onlyfans.com/files/1/11/111/1080x1350_300.jpg"
+        );
+        assert_eq!(image.user_name, "creator");
+        assert!(!image.paid);
+    });
+    std::fs::remove_file(&jar_path).unwrap();
+}
+
+#[test]
+fn session_cookie_set_by_init_is_saved_for_the_next_run() {
+    let jar_path = cookie_jar_path("session");
+    block_on(async {
+        OnlyFans::new(config("posts.json", &jar_path))
+            .await
+            .unwrap();
+    });
+
+    let jar = CookieJar::load(&jar_path).unwrap();
+    assert_eq!(jar.get("sess").as_deref(), Some("refreshed-session=="));
+    assert_eq!(jar.get("auth_id").as_deref(), Some("500"));
+    let url = "https://onlyfans.com/api2/v2/users/me".parse().unwrap();
+    let header = jar.header_for(&url).unwrap();
+    let mut sent: Vec<_> = header.split("; ").collect();
+    sent.sort_unstable();
+    assert_eq!(sent, ["auth_id=500", "sess=refreshed-session=="]);
+
+    // Seeding with the same config cookie keeps the refreshed session
+    block_on(async {
+        OnlyFans::new(config("posts.json", &jar_path))
+            .await
+            .unwrap();
+    });
+    assert_eq!(
+        CookieJar::load(&jar_path).unwrap().get("sess").as_deref(),
+        Some("refreshed-session==")
+    );
+    std::fs::remove_file(&jar_path).unwrap();
+}
+
+#[test]
+fn expired_session_is_reported_as_invalid_credentials() {
+    let jar_path = cookie_jar_path("expired");
+    block_on(async {
+        let err = OnlyFans::new(config("expired_session.json", &jar_path))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<GathererErrors>(),
+            Some(GathererErrors::InvalidCredentials { name, .. }) if name == "OnlyFans"
+        ));
+    });
+    std::fs::remove_file(&jar_path).unwrap();
+}
+
+#[test]
+fn failed_posts_keep_their_error_type() {
+    let jar_path = cookie_jar_path("forbidden");
+    block_on(async {
+        let onlyfans = OnlyFans::new(config("forbidden_posts.json", &jar_path))
+            .await
+            .unwrap();
+        let err = onlyfans.gather_posts(&subscription()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<GathererErrors>(),
+            Some(GathererErrors::InvalidCredentials { .. })
+        ));
+    });
+    std::fs::remove_file(&jar_path).unwrap();
+}
+
+#[test]
+fn refused_listings_stop_paging() {
+    let jar_path = cookie_jar_path("refused");
+    block_on(async {
+        let onlyfans = OnlyFans::new(config("refused_lists.json", &jar_path))
+            .await
+            .unwrap();
+        // Each page is recorded once, asking for it again would fail with a missing response instead
+        for err in [
+            onlyfans.gather_subscriptions().await.unwrap_err(),
+            onlyfans.gather_paid_content().await.unwrap_err(),
+        ] {
+            assert!(
+                matches!(
+                    err.downcast_ref::<GathererErrors>(),
+                    Some(GathererErrors::InvalidCredentials { .. })
+                ),
+                "{:?}",
+                err
+            );
+        }
+    });
+    std::fs::remove_file(&jar_path).unwrap();
+}